@@ -0,0 +1,31 @@
+use ftdi_embedded_hal as hal;
+use hal::x232h::FTx232H;
+
+fn main() {
+    let devices = FTx232H::list().unwrap();
+
+    if devices.is_empty() {
+        println!("No FTDI devices found");
+    }
+
+    for dev in devices {
+        println!(
+            "Bus {:03} Device {:03}: ID {:04x}:{:04x} {} ({} MPSSE)",
+            dev.bus_number,
+            dev.address,
+            dev.vendor_id,
+            dev.product_id,
+            dev.chip_type,
+            dev.mpsse_interfaces,
+        );
+        println!(
+            "  manufacturer: {}",
+            dev.manufacturer.as_deref().unwrap_or("-")
+        );
+        println!(
+            "  description: {}",
+            dev.description.as_deref().unwrap_or("-")
+        );
+        println!("  serial: {}", dev.serial_number.as_deref().unwrap_or("-"));
+    }
+}
@@ -16,3 +16,38 @@ fn main() {
         sleep(delay);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use ftdi_embedded_hal as hal;
+    use hal::emulator::i2c;
+    use hal::emulator::MpsseEmulator;
+    use hal::x232h::FTx232H;
+    use lm75::{Lm75, SlaveAddr};
+
+    #[test]
+    fn lm75_emulator_t1() {
+        let emu = MpsseEmulator::new();
+        emu.add_i2c_target(i2c::Lm75::with_default_address(24.5));
+
+        let dev = FTx232H::with_transport(emu).unwrap();
+        let i2c = dev.i2c(hal::i2c::I2cSpeed::CLK_400kHz).unwrap();
+        let mut sensor = Lm75::new(i2c, SlaveAddr::default());
+
+        let temperature = sensor.read_temperature().unwrap();
+        assert_eq!(temperature, 24.5);
+    }
+
+    #[test]
+    fn lm75_emulator_t2() {
+        let emu = MpsseEmulator::new();
+        emu.add_i2c_target(i2c::Lm75::with_default_address(-10.0));
+
+        let dev = FTx232H::with_transport(emu).unwrap();
+        let i2c = dev.i2c(hal::i2c::I2cSpeed::CLK_100kHz).unwrap();
+        let mut sensor = Lm75::new(i2c, SlaveAddr::default());
+
+        let temperature = sensor.read_temperature().unwrap();
+        assert_eq!(temperature, -10.0);
+    }
+}
@@ -22,3 +22,35 @@ fn main() {
         sleep(Duration::from_millis(1000));
     }
 }
+
+#[cfg(test)]
+mod test {
+    use adc_mcp3008::*;
+    use ftdi_embedded_hal as hal;
+    use hal::emulator::spi;
+    use hal::emulator::MpsseEmulator;
+    use hal::gpio::PinBank;
+    use hal::x232h::FTx232H;
+
+    #[test]
+    fn mcp3008_emulator_t1() {
+        let emu = MpsseEmulator::new();
+        let values = [0, 1, 2, 3, 0x100, 0x200, 0x3fe, 0x3ff];
+        emu.add_spi_target(PinBank::Low, 6, spi::Mcp3008::new(values));
+
+        let dev = FTx232H::with_transport(emu).unwrap();
+        let spi = dev.spi(hal::spi::SpiSpeed::CLK_1MHz).unwrap();
+        let ncs = dev.pl2().unwrap();
+
+        let mut adc = Mcp3008::new(spi, ncs).unwrap();
+
+        assert_eq!(adc.read_channel(Channels8::CH0).unwrap(), values[0]);
+        assert_eq!(adc.read_channel(Channels8::CH1).unwrap(), values[1]);
+        assert_eq!(adc.read_channel(Channels8::CH2).unwrap(), values[2]);
+        assert_eq!(adc.read_channel(Channels8::CH3).unwrap(), values[3]);
+        assert_eq!(adc.read_channel(Channels8::CH4).unwrap(), values[4]);
+        assert_eq!(adc.read_channel(Channels8::CH5).unwrap(), values[5]);
+        assert_eq!(adc.read_channel(Channels8::CH6).unwrap(), values[6]);
+        assert_eq!(adc.read_channel(Channels8::CH7).unwrap(), values[7]);
+    }
+}
@@ -43,3 +43,42 @@ fn main() {
         sleep(delay);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use embedded_nrf24l01::{Configuration, CrcMode, DataRate, NRF24L01};
+    use ftdi_embedded_hal as hal;
+    use hal::emulator::spi::Nrf24l01;
+    use hal::emulator::MpsseEmulator;
+    use hal::gpio::PinBank;
+    use hal::x232h::FTx232H;
+
+    #[test]
+    fn nrf24_emulator_t1() {
+        let emu = MpsseEmulator::new();
+        let radio = Nrf24l01::new();
+        emu.add_spi_target(PinBank::Low, 6, radio.clone());
+
+        let dev = FTx232H::with_transport(emu).unwrap();
+        let spidev = dev.spi(hal::spi::SpiSpeed::CLK_1MHz).unwrap();
+        let ce = dev.pl1().unwrap();
+        let cs = dev.pl2().unwrap();
+
+        let mut nrf = NRF24L01::new(ce, cs, spidev).unwrap();
+        nrf.set_frequency(120).unwrap();
+        nrf.set_rf(&DataRate::R250Kbps, 3).unwrap();
+        nrf.set_crc(CrcMode::OneByte).unwrap();
+
+        let addr: [u8; 5] = [0xe5, 0xe4, 0xe3, 0xe2, 0xe1];
+        nrf.set_tx_addr(&addr).unwrap();
+        nrf.set_pipes_rx_lengths(&[None; 6]).unwrap();
+        nrf.flush_tx().unwrap();
+
+        let mut tx = nrf.tx().unwrap();
+        tx.send(b"hello").unwrap();
+        tx.wait_empty().unwrap();
+
+        assert_eq!(radio.register(0x10), addr.to_vec());
+        assert_eq!(radio.sent(), vec![b"hello".to_vec()]);
+    }
+}
@@ -0,0 +1,1044 @@
+//! Async buses and pins implementing `embedded-hal-async` traits.
+//!
+//! USB requests of both backends are blocking, so the device is owned by a
+//! worker thread and async handles send jobs to it over a channel. The jobs
+//! run one by one in the order they were sent: a busy handle can not starve
+//! the others, each job waits only for the jobs queued before it.
+//!
+//! The device is opened on the worker thread and never leaves it, so e.g.
+//! libftdi devices, which are not `Send`, can be used as well. The handles
+//! are `Send` and can be moved to any executor task.
+
+use crate::error::{ErrorKind, Result, X232Error};
+use crate::gpio::{GpioPin, PinBank};
+use crate::i2c::{I2cBus, I2cSpeed};
+use crate::spi::{Mode, SpiBus, SpiSpeed, MODE_0, MODE_2};
+use crate::transport::{Interface, MpsseTransport};
+use crate::x232h::{Device, FTx232H};
+
+use embedded_hal::blocking::i2c::{Operation as I2cTransfer, Transactional};
+use embedded_hal::blocking::spi::{Transfer, Write as SpiWrite};
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use embedded_hal_1::i2c::{Operation as I2cOperation, SevenBitAddress};
+use embedded_hal_1::spi::Operation as SpiOperation;
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Pin levels are sampled with this interval while a `Wait` future is
+/// pending, so shorter pulses may be missed.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+type Job<T> = Box<dyn FnOnce(&mut FTx232H<T>) + Send>;
+
+enum Request<T: MpsseTransport> {
+    Run(Job<T>),
+    Watch(Watch),
+}
+
+struct Shared<R> {
+    result: Option<Result<R>>,
+    waker: Option<Waker>,
+}
+
+// Future resolved by the worker thread
+struct Reply<R> {
+    shared: Arc<Mutex<Shared<R>>>,
+}
+
+// Worker side of a reply: if it is dropped without a result, e.g. because
+// the worker thread is gone, the reply fails with `Disconnected` error
+struct Completer<R> {
+    shared: Option<Arc<Mutex<Shared<R>>>>,
+}
+
+fn reply<R>() -> (Reply<R>, Completer<R>) {
+    let shared = Arc::new(Mutex::new(Shared {
+        result: None,
+        waker: None,
+    }));
+
+    (
+        Reply {
+            shared: shared.clone(),
+        },
+        Completer {
+            shared: Some(shared),
+        },
+    )
+}
+
+impl<R> Future for Reply<R> {
+    type Output = Result<R>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<R>> {
+        let mut shared = self.shared.lock().unwrap();
+
+        match shared.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+fn finish<R>(shared: &Mutex<Shared<R>>, result: Result<R>) {
+    let mut shared = shared.lock().unwrap();
+
+    shared.result = Some(result);
+    if let Some(waker) = shared.waker.take() {
+        waker.wake();
+    }
+}
+
+impl<R> Completer<R> {
+    fn complete(mut self, result: Result<R>) {
+        if let Some(shared) = self.shared.take() {
+            finish(&shared, result);
+        }
+    }
+
+    // Nobody waits for the result anymore
+    fn is_cancelled(&self) -> bool {
+        match self.shared {
+            Some(ref shared) => Arc::strong_count(shared) == 1,
+            None => true,
+        }
+    }
+}
+
+impl<R> Drop for Completer<R> {
+    fn drop(&mut self) {
+        if let Some(shared) = self.shared.take() {
+            finish(&shared, Err(X232Error::HAL(ErrorKind::Disconnected)));
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Condition {
+    High,
+    Low,
+    RisingEdge,
+    FallingEdge,
+    AnyEdge,
+}
+
+// Pending `Wait` request, resolved by the worker thread polling the pin
+struct Watch {
+    bank: PinBank,
+    bit: u8,
+    condition: Condition,
+    last: Option<bool>,
+    done: Completer<()>,
+}
+
+impl Watch {
+    fn is_met(&self, level: bool) -> bool {
+        match (self.condition, self.last) {
+            (Condition::High, _) => level,
+            (Condition::Low, _) => !level,
+            (Condition::RisingEdge, Some(last)) => !last && level,
+            (Condition::FallingEdge, Some(last)) => last && !level,
+            (Condition::AnyEdge, Some(last)) => last != level,
+            (_, None) => false,
+        }
+    }
+}
+
+fn poll_watches<T: MpsseTransport>(dev: &FTx232H<T>, watches: &mut Vec<Watch>) {
+    let mut pending = Vec::with_capacity(watches.len());
+
+    for mut watch in watches.drain(..) {
+        if watch.done.is_cancelled() {
+            continue;
+        }
+
+        match GpioPin::new(dev.context(), watch.bit, watch.bank).is_high() {
+            Ok(level) if watch.is_met(level) => watch.done.complete(Ok(())),
+            Ok(level) => {
+                watch.last = Some(level);
+                pending.push(watch);
+            }
+            Err(e) => watch.done.complete(Err(e)),
+        }
+    }
+
+    *watches = pending;
+}
+
+fn worker<T: MpsseTransport>(mut dev: FTx232H<T>, rx: Receiver<Request<T>>) {
+    let mut watches: Vec<Watch> = Vec::new();
+    let mut polled = Instant::now();
+
+    loop {
+        let request = if watches.is_empty() {
+            match rx.recv() {
+                Ok(request) => Some(request),
+                Err(_) => break,
+            }
+        } else {
+            match rx.recv_timeout(POLL_INTERVAL.saturating_sub(polled.elapsed())) {
+                Ok(request) => Some(request),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        };
+
+        // new watches are checked right away, e.g. a pin may already be high
+        let mut poll = polled.elapsed() >= POLL_INTERVAL;
+
+        match request {
+            Some(Request::Run(job)) => job(&mut dev),
+            Some(Request::Watch(watch)) => {
+                watches.push(watch);
+                poll = true;
+            }
+            None => {}
+        }
+
+        if poll && !watches.is_empty() {
+            poll_watches(&dev, &mut watches);
+            polled = Instant::now();
+        }
+    }
+}
+
+// Sending side of the worker thread channel shared by all the handles
+struct Worker<T: MpsseTransport> {
+    tx: Sender<Request<T>>,
+}
+
+impl<T: MpsseTransport> Clone for Worker<T> {
+    fn clone(&self) -> Self {
+        Worker {
+            tx: self.tx.clone(),
+        }
+    }
+}
+
+impl<T: MpsseTransport> Worker<T> {
+    fn run<R, F>(&self, f: F) -> Reply<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut FTx232H<T>) -> Result<R> + Send + 'static,
+    {
+        let (reply, done) = reply();
+        let job: Job<T> = Box::new(move |dev| done.complete(f(dev)));
+
+        // if the worker is gone, the dropped job fails the reply
+        let _ = self.tx.send(Request::Run(job));
+
+        reply
+    }
+
+    fn watch(&self, bank: PinBank, bit: u8, condition: Condition) -> Reply<()> {
+        let (reply, done) = reply();
+        let watch = Watch {
+            bank,
+            bit,
+            condition,
+            last: None,
+            done,
+        };
+
+        let _ = self.tx.send(Request::Watch(watch));
+
+        reply
+    }
+}
+
+/// Async counterpart of [`FTx232H`]: the device is owned by a worker thread,
+/// buses and pins are async handles sending requests to it.
+pub struct AsyncFTx232H<T: MpsseTransport = Device> {
+    worker: Worker<T>,
+}
+
+impl AsyncFTx232H<Device> {
+    pub fn init(vendor: u16, product: u16) -> Result<AsyncFTx232H> {
+        AsyncFTx232H::init_chan(vendor, product, Interface::A)
+    }
+
+    pub fn init_chan(vendor: u16, product: u16, intf: Interface) -> Result<AsyncFTx232H> {
+        AsyncFTx232H::open(move || FTx232H::init_chan(vendor, product, intf))
+    }
+}
+
+macro_rules! declare_async_gpio_pin {
+    ($pin: ident) => {
+        pub async fn $pin(&self) -> Result<AsyncGpioPin<T>> {
+            let (bit, bank) = self
+                .worker
+                .run(|dev| dev.$pin().map(|pin| (pin.get_bit(), pin.get_bank())))
+                .await?;
+
+            Ok(AsyncGpioPin {
+                worker: self.worker.clone(),
+                bank,
+                bit,
+            })
+        }
+    };
+}
+
+impl<T: MpsseTransport + 'static> AsyncFTx232H<T> {
+    /// Start a worker thread and open the device on it with `open`, blocks
+    /// until the device is initialized.
+    pub fn open<F>(open: F) -> Result<AsyncFTx232H<T>>
+    where
+        F: FnOnce() -> Result<FTx232H<T>> + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+        let (opened_tx, opened_rx) = mpsc::channel();
+
+        thread::Builder::new()
+            .name("ftdi-mpsse".to_string())
+            .spawn(move || match open() {
+                Ok(dev) => {
+                    let _ = opened_tx.send(Ok(()));
+                    worker(dev, rx);
+                }
+                Err(e) => {
+                    let _ = opened_tx.send(Err(e));
+                }
+            })?;
+
+        opened_rx
+            .recv()
+            .map_err(|_| X232Error::HAL(ErrorKind::Disconnected))??;
+
+        Ok(AsyncFTx232H {
+            worker: Worker { tx },
+        })
+    }
+
+    /// Initialize MPSSE on an already opened transport.
+    pub fn with_transport(device: T) -> Result<AsyncFTx232H<T>>
+    where
+        T: Send,
+    {
+        AsyncFTx232H::open(move || FTx232H::with_transport(device))
+    }
+
+    pub async fn loopback(&self, lp: bool) -> Result<()> {
+        self.worker.run(move |dev| dev.loopback(lp)).await
+    }
+
+    pub async fn is_loopback(&self) -> Result<bool> {
+        self.worker.run(|dev| Ok(dev.is_loopback())).await
+    }
+
+    /// Run `f` with exclusive access to the blocking device on the worker
+    /// thread, e.g. to use an API not available on async handles.
+    pub async fn with_device<R, F>(&self, f: F) -> Result<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut FTx232H<T>) -> Result<R> + Send + 'static,
+    {
+        self.worker.run(f).await
+    }
+
+    // spi/i2c buses
+
+    pub async fn spi(&self, speed: SpiSpeed) -> Result<AsyncSpiBus<T>> {
+        self.worker
+            .run(move |dev| dev.spi(speed).map(|_| ()))
+            .await?;
+
+        Ok(AsyncSpiBus {
+            worker: self.worker.clone(),
+            mode: MODE_0,
+        })
+    }
+
+    pub async fn i2c(&self, speed: I2cSpeed) -> Result<AsyncI2cBus<T>> {
+        self.worker
+            .run(move |dev| dev.i2c(speed).map(|_| ()))
+            .await?;
+
+        Ok(AsyncI2cBus {
+            worker: self.worker.clone(),
+        })
+    }
+
+    // gpio pins: low bank
+    declare_async_gpio_pin!(pl0);
+    declare_async_gpio_pin!(pl1);
+    declare_async_gpio_pin!(pl2);
+    declare_async_gpio_pin!(pl3);
+
+    // gpio pins: high bank
+    declare_async_gpio_pin!(ph0);
+    declare_async_gpio_pin!(ph1);
+    declare_async_gpio_pin!(ph2);
+    declare_async_gpio_pin!(ph3);
+    declare_async_gpio_pin!(ph4);
+    declare_async_gpio_pin!(ph5);
+    declare_async_gpio_pin!(ph6);
+    declare_async_gpio_pin!(ph7);
+}
+
+/// Async SPI bus, see [`SpiBus`].
+pub struct AsyncSpiBus<T: MpsseTransport = Device> {
+    worker: Worker<T>,
+    mode: Mode,
+}
+
+impl<T: MpsseTransport + 'static> AsyncSpiBus<T> {
+    pub fn set_mode(&mut self, mode: Mode) -> Result<()> {
+        if mode != MODE_0 && mode != MODE_2 {
+            return Err(X232Error::HAL(ErrorKind::SpiModeNotSupported));
+        }
+
+        self.mode = mode;
+        Ok(())
+    }
+
+    pub fn get_mode(&self) -> Mode {
+        self.mode
+    }
+
+    async fn transfer_data(&mut self, mut data: Vec<u8>) -> Result<Vec<u8>> {
+        let mode = self.mode;
+
+        self.worker
+            .run(move |dev| {
+                let mut bus = SpiBus::new(dev.context());
+                bus.set_mode(mode)?;
+                bus.transfer(&mut data)?;
+                Ok(data)
+            })
+            .await
+    }
+}
+
+impl<T: MpsseTransport> embedded_hal_1::spi::ErrorType for AsyncSpiBus<T> {
+    type Error = X232Error;
+}
+
+impl<T: MpsseTransport + 'static> embedded_hal_async::spi::SpiBus<u8> for AsyncSpiBus<T> {
+    async fn read(&mut self, words: &mut [u8]) -> Result<()> {
+        let data = self.transfer_data(vec![0; words.len()]).await?;
+        words.copy_from_slice(&data);
+        Ok(())
+    }
+
+    async fn write(&mut self, words: &[u8]) -> Result<()> {
+        let mode = self.mode;
+        let data = words.to_vec();
+
+        self.worker
+            .run(move |dev| {
+                let mut bus = SpiBus::new(dev.context());
+                bus.set_mode(mode)?;
+                bus.write(&data)
+            })
+            .await
+    }
+
+    async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<()> {
+        // the shorter buffer is padded: zeros are sent, extra bytes dropped
+        let mut data = write.to_vec();
+        data.resize(read.len().max(write.len()), 0);
+
+        let data = self.transfer_data(data).await?;
+        read.copy_from_slice(&data[..read.len()]);
+        Ok(())
+    }
+
+    async fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<()> {
+        let data = self.transfer_data(words.to_vec()).await?;
+        words.copy_from_slice(&data);
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Owned copy of `embedded_hal_1::spi::Operation` for the worker thread
+enum SpiOp {
+    Read(usize),
+    Write(Vec<u8>),
+    Transfer(Vec<u8>, usize),
+    TransferInPlace(Vec<u8>),
+    DelayNs(u32),
+}
+
+fn run_spi_op<T: MpsseTransport>(bus: &mut SpiBus<T>, op: SpiOp) -> Result<Option<Vec<u8>>> {
+    match op {
+        SpiOp::Read(len) => {
+            let mut data = vec![0; len];
+            bus.transfer(&mut data)?;
+            Ok(Some(data))
+        }
+        SpiOp::Write(data) => {
+            bus.write(&data)?;
+            Ok(None)
+        }
+        SpiOp::Transfer(mut data, len) => {
+            bus.transfer(&mut data)?;
+            data.truncate(len);
+            Ok(Some(data))
+        }
+        SpiOp::TransferInPlace(mut data) => {
+            bus.transfer(&mut data)?;
+            Ok(Some(data))
+        }
+        SpiOp::DelayNs(ns) => {
+            thread::sleep(Duration::from_nanos(ns as u64));
+            Ok(None)
+        }
+    }
+}
+
+/// Async SPI device: a bus with a chip select pin.
+///
+/// A transaction is a single job for the worker thread, so the other handles
+/// of the same device are not served until it is complete, including its
+/// delays.
+pub struct AsyncSpiDevice<T: MpsseTransport = Device> {
+    bus: AsyncSpiBus<T>,
+    cs: AsyncGpioPin<T>,
+}
+
+impl<T: MpsseTransport + 'static> AsyncSpiDevice<T> {
+    pub fn new(bus: AsyncSpiBus<T>, cs: AsyncGpioPin<T>) -> AsyncSpiDevice<T> {
+        AsyncSpiDevice { bus, cs }
+    }
+
+    /// Bus and chip select pin.
+    pub fn release(self) -> (AsyncSpiBus<T>, AsyncGpioPin<T>) {
+        (self.bus, self.cs)
+    }
+}
+
+impl<T: MpsseTransport> embedded_hal_1::spi::ErrorType for AsyncSpiDevice<T> {
+    type Error = X232Error;
+}
+
+impl<T: MpsseTransport + 'static> embedded_hal_async::spi::SpiDevice<u8> for AsyncSpiDevice<T> {
+    async fn transaction(&mut self, operations: &mut [SpiOperation<'_, u8>]) -> Result<()> {
+        let ops: Vec<SpiOp> = operations
+            .iter()
+            .map(|op| match op {
+                SpiOperation::Read(words) => SpiOp::Read(words.len()),
+                SpiOperation::Write(words) => SpiOp::Write(words.to_vec()),
+                SpiOperation::Transfer(read, write) => {
+                    let mut data = write.to_vec();
+                    data.resize(read.len().max(write.len()), 0);
+                    SpiOp::Transfer(data, read.len())
+                }
+                SpiOperation::TransferInPlace(words) => SpiOp::TransferInPlace(words.to_vec()),
+                SpiOperation::DelayNs(ns) => SpiOp::DelayNs(*ns),
+            })
+            .collect();
+
+        let mode = self.bus.mode;
+        let (bit, bank) = (self.cs.bit, self.cs.bank);
+
+        let results = self
+            .bus
+            .worker
+            .run(move |dev| {
+                let mut bus = SpiBus::new(dev.context());
+                let mut cs = GpioPin::new(dev.context(), bit, bank);
+                bus.set_mode(mode)?;
+
+                cs.set_low()?;
+                let results: Result<Vec<_>> =
+                    ops.into_iter().map(|op| run_spi_op(&mut bus, op)).collect();
+                // release the device even if the transfer failed
+                let released = cs.set_high();
+
+                let results = results?;
+                released?;
+
+                Ok(results)
+            })
+            .await?;
+
+        for (op, result) in operations.iter_mut().zip(results) {
+            match (op, result) {
+                (SpiOperation::Read(words), Some(data))
+                | (SpiOperation::Transfer(words, _), Some(data))
+                | (SpiOperation::TransferInPlace(words), Some(data)) => {
+                    words.copy_from_slice(&data)
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Async I2C bus, see [`I2cBus`].
+pub struct AsyncI2cBus<T: MpsseTransport = Device> {
+    worker: Worker<T>,
+}
+
+impl<T: MpsseTransport> embedded_hal_1::i2c::ErrorType for AsyncI2cBus<T> {
+    type Error = X232Error;
+}
+
+impl<T: MpsseTransport + 'static> embedded_hal_async::i2c::I2c<SevenBitAddress> for AsyncI2cBus<T> {
+    /// The transaction is run as one transfer on the worker thread, see
+    /// [`Transactional`](embedded_hal::blocking::i2c::Transactional).
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [I2cOperation<'_>],
+    ) -> Result<()> {
+        // (is read, data to write or buffer to read into)
+        let ops: Vec<(bool, Vec<u8>)> = operations
+            .iter()
+            .map(|op| match op {
+                I2cOperation::Read(buf) => (true, vec![0; buf.len()]),
+                I2cOperation::Write(buf) => (false, buf.to_vec()),
+            })
+            .collect();
+
+        let data = self
+            .worker
+            .run(move |dev| {
+                let mut bus = I2cBus::new(dev.context());
+                let mut ops = ops;
+                let mut transfer: Vec<I2cTransfer> = ops
+                    .iter_mut()
+                    .map(|(read, data)| match read {
+                        true => I2cTransfer::Read(data),
+                        false => I2cTransfer::Write(data),
+                    })
+                    .collect();
+
+                bus.exec(address, &mut transfer)?;
+                drop(transfer);
+
+                Ok(ops)
+            })
+            .await?;
+
+        for (op, (_, data)) in operations.iter_mut().zip(data) {
+            if let I2cOperation::Read(buf) = op {
+                buf.copy_from_slice(&data);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Async GPIO pin, see [`GpioPin`].
+pub struct AsyncGpioPin<T: MpsseTransport = Device> {
+    worker: Worker<T>,
+    bank: PinBank,
+    bit: u8,
+}
+
+impl<T: MpsseTransport> fmt::Display for AsyncGpioPin<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.bank {
+            PinBank::Low => write!(f, "P{}{}", self.bank, self.bit - 4),
+            PinBank::High => write!(f, "P{}{}", self.bank, self.bit),
+        }
+    }
+}
+
+impl<T: MpsseTransport + 'static> AsyncGpioPin<T> {
+    pub fn get_bit(&self) -> u8 {
+        self.bit
+    }
+
+    pub fn get_bank(&self) -> PinBank {
+        self.bank
+    }
+
+    fn pin<R, F>(&self, f: F) -> Reply<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut GpioPin<T>) -> Result<R> + Send + 'static,
+    {
+        let (bit, bank) = (self.bit, self.bank);
+
+        self.worker
+            .run(move |dev| f(&mut GpioPin::new(dev.context(), bit, bank)))
+    }
+
+    pub async fn set_high(&mut self) -> Result<()> {
+        self.pin(|pin| pin.set_high()).await
+    }
+
+    pub async fn set_low(&mut self) -> Result<()> {
+        self.pin(|pin| pin.set_low()).await
+    }
+
+    /// Switch the pin to input mode, see [`GpioPin::set_input`].
+    pub async fn set_input(&mut self) -> Result<()> {
+        self.pin(|pin| pin.set_input()).await
+    }
+
+    pub async fn is_high(&self) -> Result<bool> {
+        self.pin(|pin| pin.is_high()).await
+    }
+
+    pub async fn is_low(&self) -> Result<bool> {
+        self.pin(|pin| pin.is_low()).await
+    }
+}
+
+impl<T: MpsseTransport> embedded_hal_1::digital::ErrorType for AsyncGpioPin<T> {
+    type Error = X232Error;
+}
+
+/// The pin level is polled by the worker thread every [`POLL_INTERVAL`],
+/// the other requests are served in between.
+impl<T: MpsseTransport + 'static> embedded_hal_async::digital::Wait for AsyncGpioPin<T> {
+    async fn wait_for_high(&mut self) -> Result<()> {
+        self.worker
+            .watch(self.bank, self.bit, Condition::High)
+            .await
+    }
+
+    async fn wait_for_low(&mut self) -> Result<()> {
+        self.worker.watch(self.bank, self.bit, Condition::Low).await
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<()> {
+        self.worker
+            .watch(self.bank, self.bit, Condition::RisingEdge)
+            .await
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<()> {
+        self.worker
+            .watch(self.bank, self.bit, Condition::FallingEdge)
+            .await
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<()> {
+        self.worker
+            .watch(self.bank, self.bit, Condition::AnyEdge)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::i2c::{Eeprom24c04, I2cTarget};
+    use crate::emulator::spi::Mcp3008;
+    use crate::emulator::MpsseEmulator;
+    use embedded_hal_1::i2c::{Error as _, ErrorKind as I2cErrorKind, NoAcknowledgeSource};
+    use embedded_hal_async::digital::Wait;
+    use embedded_hal_async::i2c::I2c;
+    use embedded_hal_async::spi::{SpiBus as _, SpiDevice};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::task::Wake;
+    use std::thread::Thread;
+
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut fut = Box::pin(fut);
+
+        loop {
+            if let Poll::Ready(v) = fut.as_mut().poll(&mut cx) {
+                return v;
+            }
+            thread::park();
+        }
+    }
+
+    fn device() -> (AsyncFTx232H<MpsseEmulator>, MpsseEmulator) {
+        let emu = MpsseEmulator::new();
+        let dev = AsyncFTx232H::with_transport(emu.clone()).unwrap();
+
+        (dev, emu)
+    }
+
+    fn assert_send<S: Send>(_: &S) {}
+
+    #[test]
+    fn test_spi_bus() {
+        let (dev, emu) = device();
+
+        block_on(async {
+            dev.loopback(true).await.unwrap();
+            assert!(emu.is_loopback());
+
+            let mut spi = dev.spi(SpiSpeed::CLK_5MHz).await.unwrap();
+            assert_send(&spi);
+            assert_eq!(emu.clock_frequency(), 5_000_000);
+
+            let mut buf = [1, 2, 3, 4];
+            spi.transfer_in_place(&mut buf).await.unwrap();
+            assert_eq!(buf, [1, 2, 3, 4]);
+
+            let mut rx = [0; 2];
+            spi.transfer(&mut rx, &[5, 6, 7]).await.unwrap();
+            assert_eq!(rx, [5, 6]);
+
+            let mut rx = [0xff; 3];
+            spi.transfer(&mut rx, &[8]).await.unwrap();
+            assert_eq!(rx, [8, 0, 0]);
+
+            spi.write(&[9, 10]).await.unwrap();
+            spi.read(&mut rx).await.unwrap();
+            assert_eq!(rx, [0, 0, 0]);
+
+            spi.set_mode(MODE_2).unwrap();
+            spi.transfer_in_place(&mut buf).await.unwrap();
+            assert_eq!(buf, [1, 2, 3, 4]);
+            assert!(spi.set_mode(crate::spi::MODE_1).is_err());
+
+            // the blocking rules apply: pins and buses are claimed once
+            assert!(matches!(
+                dev.i2c(I2cSpeed::CLK_AUTO).await,
+                Err(X232Error::HAL(ErrorKind::BusBusy))
+            ));
+
+            let _pl0 = dev.pl0().await.unwrap();
+            assert!(matches!(
+                dev.pl0().await,
+                Err(X232Error::HAL(ErrorKind::GpioPinBusy))
+            ));
+        });
+    }
+
+    #[test]
+    fn test_spi_device() {
+        let (dev, emu) = device();
+        let values = [0, 1, 0x155, 0x2aa, 0x3ff, 512, 100, 1000];
+        emu.add_spi_target(PinBank::Low, 6, Mcp3008::new(values));
+
+        block_on(async {
+            let spi = dev.spi(SpiSpeed::CLK_1MHz).await.unwrap();
+            let mut cs = dev.pl2().await.unwrap();
+            cs.set_high().await.unwrap();
+
+            let mut adc = AsyncSpiDevice::new(spi, cs);
+            assert_send(&adc);
+
+            for (ch, value) in values.iter().enumerate() {
+                let mut buf = [0x01, 0x80 | (ch as u8) << 4, 0x00];
+                adc.transfer_in_place(&mut buf).await.unwrap();
+                assert_eq!(((buf[1] as u16 & 0x3) << 8) | buf[2] as u16, *value);
+            }
+
+            // chip select is released after the transaction
+            assert_eq!(emu.gpio_lower() & 0b0100_0000, 0b0100_0000);
+
+            let mut rx = [0; 2];
+            adc.transaction(&mut [
+                SpiOperation::Write(&[0x01]),
+                SpiOperation::DelayNs(1000),
+                SpiOperation::Transfer(&mut rx, &[0x80 | 4 << 4, 0x00]),
+            ])
+            .await
+            .unwrap();
+            assert_eq!(((rx[0] as u16 & 0x3) << 8) | rx[1] as u16, 0x3ff);
+        });
+    }
+
+    #[test]
+    fn test_i2c() {
+        let (dev, emu) = device();
+        emu.add_i2c_target(Eeprom24c04::with_default_address());
+
+        block_on(async {
+            let mut i2c = dev.i2c(I2cSpeed::CLK_400kHz).await.unwrap();
+            assert_send(&i2c);
+
+            // adjacent writes go out without SR in between
+            i2c.transaction(
+                0x50,
+                &mut [
+                    I2cOperation::Write(&[0x10]),
+                    I2cOperation::Write(&[1, 2, 3]),
+                ],
+            )
+            .await
+            .unwrap();
+
+            let mut buf = [0; 3];
+            i2c.write_read(0x50, &[0x10], &mut buf).await.unwrap();
+            assert_eq!(buf, [1, 2, 3]);
+
+            // and so are adjacent reads
+            let (mut a, mut b) = ([0; 2], [0; 1]);
+            i2c.transaction(
+                0x50,
+                &mut [
+                    I2cOperation::Write(&[0x11]),
+                    I2cOperation::Read(&mut a),
+                    I2cOperation::Read(&mut b),
+                ],
+            )
+            .await
+            .unwrap();
+            assert_eq!((a, b), ([2, 3], [0xff]));
+
+            let err = i2c.write(0x20, &[0x0]).await.unwrap_err();
+            assert_eq!(
+                err.kind(),
+                I2cErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown)
+            );
+        });
+    }
+
+    // Target logging the bus conditions and bytes, reads return 0x5a
+    struct Trace(Arc<Mutex<Vec<String>>>);
+
+    impl I2cTarget for Trace {
+        fn matches(&self, address: u8) -> bool {
+            address == 0x30
+        }
+
+        fn start(&mut self, _address: u8, read: bool) {
+            let rw = if read { "R" } else { "W" };
+            self.0.lock().unwrap().push(format!("ST+{}", rw));
+        }
+
+        fn write(&mut self, byte: u8) -> bool {
+            self.0.lock().unwrap().push(format!("{:02x}", byte));
+            true
+        }
+
+        fn read(&mut self) -> u8 {
+            self.0.lock().unwrap().push("rd".into());
+            0x5a
+        }
+
+        fn stop(&mut self) {
+            self.0.lock().unwrap().push("SP".into());
+        }
+    }
+
+    #[test]
+    fn test_i2c_transaction() {
+        let (dev, emu) = device();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        emu.add_i2c_target(Trace(log.clone()));
+        let mut i2c = block_on(dev.i2c(I2cSpeed::CLK_400kHz)).unwrap();
+
+        let mut transaction = |operations: &mut [I2cOperation<'_>]| {
+            block_on(i2c.transaction(0x30, operations)).unwrap();
+            log.lock().unwrap().drain(..).collect::<Vec<_>>()
+        };
+
+        // no SR/SP between operations of the same kind
+        assert_eq!(
+            transaction(&mut [I2cOperation::Write(&[1]), I2cOperation::Write(&[2])]),
+            ["ST+W", "01", "02", "SP"]
+        );
+
+        // a read followed by a write
+        let mut buf = [0; 2];
+        assert_eq!(
+            transaction(&mut [I2cOperation::Read(&mut buf), I2cOperation::Write(&[3])]),
+            ["ST+R", "rd", "rd", "ST+W", "03", "SP"]
+        );
+        assert_eq!(buf, [0x5a, 0x5a]);
+
+        // SR between every change of kind, a single SP at the end
+        let mut buf = [0; 1];
+        assert_eq!(
+            transaction(&mut [
+                I2cOperation::Write(&[4]),
+                I2cOperation::Read(&mut buf),
+                I2cOperation::Write(&[5, 6]),
+            ]),
+            ["ST+W", "04", "ST+R", "rd", "ST+W", "05", "06", "SP"]
+        );
+        assert_eq!(buf, [0x5a]);
+    }
+
+    #[test]
+    fn test_wait() {
+        let (dev, emu) = device();
+
+        let mut ph3 = block_on(dev.ph3()).unwrap();
+        assert_send(&ph3);
+        assert_eq!(format!("{}", ph3), "PH3");
+
+        block_on(ph3.set_input()).unwrap();
+        assert!(block_on(ph3.is_low()).unwrap());
+
+        // already low: no need to wait
+        block_on(ph3.wait_for_low()).unwrap();
+
+        let input = emu.clone();
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            input.set_input_upper(0b0000_1000);
+            thread::sleep(Duration::from_millis(20));
+            input.set_input_upper(0b0000_0000);
+        });
+
+        block_on(ph3.wait_for_rising_edge()).unwrap();
+        assert!(block_on(ph3.is_high()).unwrap());
+        block_on(ph3.wait_for_falling_edge()).unwrap();
+        assert!(block_on(ph3.is_low()).unwrap());
+
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn test_fairness() {
+        let (dev, emu) = device();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let mut spi = block_on(dev.spi(SpiSpeed::CLK_AUTO)).unwrap();
+        let mut ph0 = block_on(dev.ph0()).unwrap();
+
+        // one handle keeps the device busy all the time
+        let busy = stop.clone();
+        let t = thread::spawn(move || {
+            let mut buf = [0x55; 256];
+
+            while !busy.load(Ordering::Relaxed) {
+                block_on(spi.transfer_in_place(&mut buf)).unwrap();
+            }
+        });
+
+        // while the other one is still served
+        for _ in 0..50 {
+            block_on(ph0.set_high()).unwrap();
+            assert_eq!(emu.gpio_upper() & 0x1, 0x1);
+            block_on(ph0.set_low()).unwrap();
+            assert_eq!(emu.gpio_upper() & 0x1, 0x0);
+        }
+
+        stop.store(true, Ordering::Relaxed);
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn test_open_error() {
+        let res =
+            AsyncFTx232H::<MpsseEmulator>::open(|| Err(X232Error::HAL(ErrorKind::DeviceNotFound)));
+
+        assert!(matches!(
+            res,
+            Err(X232Error::HAL(ErrorKind::DeviceNotFound))
+        ));
+    }
+}
@@ -0,0 +1,410 @@
+//! One-shot configuration of an adapter.
+//!
+//! [`FTx232HBuilder`] collects device selection, USB parameters, initial GPIO
+//! state, loopback and an optional bus, then [`open`] checks all of them and
+//! applies them in a single MPSSE init sequence. Pins not configured here are
+//! inputs, so nothing is driven until the pins are used:
+//!
+//! ```no_run
+//! use ftdi_embedded_hal::enumerate::DeviceSelector;
+//! use ftdi_embedded_hal::gpio::{PinBank, PinState};
+//! use ftdi_embedded_hal::spi::SpiSpeed;
+//! use ftdi_embedded_hal::x232h::{FTx232H, Interface};
+//! use std::time::Duration;
+//!
+//! let dev = FTx232H::builder()
+//!     .selector(DeviceSelector::Serial("FT0001".to_string()))
+//!     .interface(Interface::A)
+//!     .latency_timer(Duration::from_millis(2))
+//!     // active-low chip select on PL1 is kept inactive
+//!     .pin(PinBank::Low, 5, PinState::High)
+//!     .spi(SpiSpeed::CLK_3MHz)
+//!     .open()
+//!     .unwrap();
+//!
+//! let spi = dev.spi(SpiSpeed::CLK_AUTO).unwrap();
+//! ```
+//!
+//! [`open`]: FTx232HBuilder::open
+
+use crate::enumerate::DeviceSelector;
+use crate::error::{ErrorKind, Result, X232Error};
+use crate::ftdimpsse::MpsseSettings;
+use crate::gpio::{PinBank, PinState};
+use crate::i2c::I2cSpeed;
+use crate::spi::SpiSpeed;
+use crate::transport::{Interface, MpsseTransport};
+use crate::x232h::{Bus, Device, FTx232H, Init};
+
+use std::time::Duration;
+
+/// Builder of [`FTx232H`], see the [module documentation](self).
+///
+/// By default the first attached FTDI device is opened on interface A with
+/// [`MpsseSettings::default`], all pins as inputs, loopback disabled and no
+/// bus selected.
+#[derive(Clone, Debug)]
+pub struct FTx232HBuilder {
+    selector: DeviceSelector,
+    interface: Interface,
+    init: Init,
+    invalid_pin: bool,
+}
+
+impl Default for FTx232HBuilder {
+    fn default() -> Self {
+        FTx232HBuilder::new()
+    }
+}
+
+impl FTx232HBuilder {
+    pub fn new() -> FTx232HBuilder {
+        FTx232HBuilder {
+            selector: DeviceSelector::Index(0),
+            interface: Interface::A,
+            init: Init {
+                settings: MpsseSettings::default(),
+                usb: true,
+                ..Init::default()
+            },
+            invalid_pin: false,
+        }
+    }
+
+    /// Adapter to open, see [`FTx232H::init_device`].
+    pub fn selector(mut self, selector: DeviceSelector) -> Self {
+        self.selector = selector;
+        self
+    }
+
+    pub fn interface(mut self, interface: Interface) -> Self {
+        self.interface = interface;
+        self
+    }
+
+    /// All the settings at once, the other setters change single fields.
+    pub fn settings(mut self, settings: MpsseSettings) -> Self {
+        self.init.settings = settings;
+        self
+    }
+
+    /// Reset the chip before switching to MPSSE mode.
+    pub fn reset(mut self, reset: bool) -> Self {
+        self.init.settings.reset = reset;
+        self
+    }
+
+    /// Latency timer, 1..255 ms.
+    pub fn latency_timer(mut self, latency: Duration) -> Self {
+        self.init.settings.latency_timer = latency;
+        self
+    }
+
+    /// USB IN transfer size in bytes, a multiple of 64 up to 64k.
+    pub fn transfer_size(mut self, size: u32) -> Self {
+        self.init.settings.in_transfer_size = size;
+        self
+    }
+
+    /// USB read and write timeouts, both non-zero.
+    pub fn timeouts(mut self, read: Duration, write: Duration) -> Self {
+        self.init.settings.read_timeout = read;
+        self.init.settings.write_timeout = write;
+        self
+    }
+
+    pub fn loopback(mut self, lp: bool) -> Self {
+        self.init.loopback = lp;
+        self
+    }
+
+    /// Initial value and direction (1 - output) of the lower GPIO byte.
+    ///
+    /// With a bus selected its pins AD0..AD2 are configured by the bus.
+    /// AD3 is left as set here, e.g. `pin(PinBank::Low, 3, PinState::High)`
+    /// makes it an inactive SPI chip select.
+    pub fn gpio_lower(mut self, value: u8, direction: u8) -> Self {
+        self.init.lower = (value, direction);
+        self
+    }
+
+    /// Initial value and direction (1 - output) of the upper GPIO byte,
+    /// chips without it fail to open if this is set.
+    pub fn gpio_upper(mut self, value: u8, direction: u8) -> Self {
+        self.init.upper = (value, direction);
+        self
+    }
+
+    /// Initial state of a single pin, `bit` is numbered as in
+    /// [`GpioPin::get_bit`]: PL0..PL3 are bits 4..7 of the lower byte.
+    ///
+    /// [`GpioPin::get_bit`]: crate::gpio::GpioPin::get_bit
+    pub fn pin(mut self, bank: PinBank, bit: u8, state: PinState) -> Self {
+        if bit > 7 {
+            self.invalid_pin = true;
+            return self;
+        }
+
+        let (value, direction) = match bank {
+            PinBank::Low => &mut self.init.lower,
+            PinBank::High => &mut self.init.upper,
+        };
+
+        let mask = 1 << bit;
+
+        match state {
+            PinState::Input => (*value &= !mask, *direction &= !mask),
+            PinState::Low => (*value &= !mask, *direction |= mask),
+            PinState::High => (*value |= mask, *direction |= mask),
+        };
+
+        self
+    }
+
+    /// Value and direction of the lower GPIO byte to keep after closing the
+    /// device, by default all the pins are tri-stated, see [`FTx232H::close`].
+    pub fn safe_lower(mut self, value: u8, direction: u8) -> Self {
+        let upper = self.init.safe.map(|(_, upper)| upper).unwrap_or_default();
+        self.init.safe = Some(((value, direction), upper));
+        self
+    }
+
+    /// Value and direction of the upper GPIO byte to keep after closing the
+    /// device, see [`safe_lower`](Self::safe_lower).
+    pub fn safe_upper(mut self, value: u8, direction: u8) -> Self {
+        let lower = self.init.safe.map(|(lower, _)| lower).unwrap_or_default();
+        self.init.safe = Some((lower, (value, direction)));
+        self
+    }
+
+    /// Select SPI bus with the given clock, `FTx232H::spi` then returns the
+    /// bus for the same speed or `CLK_AUTO`.
+    pub fn spi(mut self, speed: SpiSpeed) -> Self {
+        self.init.bus = Some(Bus::Spi(speed));
+        self
+    }
+
+    /// Select I2C bus with the given clock, `FTx232H::i2c` then returns the
+    /// bus for the same speed or `CLK_AUTO`.
+    pub fn i2c(mut self, speed: I2cSpeed) -> Self {
+        self.init.bus = Some(Bus::I2c(speed));
+        self
+    }
+
+    /// Open the selected adapter with the backend chosen by the cargo
+    /// features, see [`Device`], and initialize it.
+    pub fn open(self) -> Result<FTx232H<Device>> {
+        if self.invalid_pin {
+            return Err(X232Error::HAL(ErrorKind::GpioInvalidPin));
+        }
+
+        let (device, chip) = FTx232H::open_selected(&self.selector, self.interface)?;
+        FTx232H::with_init(device, chip, self.init)
+    }
+
+    /// Initialize an already opened transport, device selection and
+    /// interface are ignored.
+    ///
+    /// Invalid parameters fail before anything is sent to the chip.
+    pub fn open_transport<T: MpsseTransport>(self, mut device: T) -> Result<FTx232H<T>> {
+        if self.invalid_pin {
+            return Err(X232Error::HAL(ErrorKind::GpioInvalidPin));
+        }
+
+        let chip = device.chip_type();
+        FTx232H::with_init(device, chip, self.init)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip::ChipType;
+    use crate::emulator::MpsseEmulator;
+    use crate::record::tests::Log;
+    use crate::record::Recorder;
+    use embedded_hal::blocking::spi::Transfer;
+    use embedded_hal::digital::v2::OutputPin;
+
+    #[test]
+    fn test_open() {
+        let emu = MpsseEmulator::new();
+        let log = Log::default();
+
+        let dev = FTx232H::builder()
+            .latency_timer(Duration::from_millis(2))
+            .transfer_size(512)
+            .timeouts(Duration::from_millis(100), Duration::from_millis(200))
+            .loopback(true)
+            .gpio_lower(0b0011_0000, 0b0111_0000)
+            .gpio_upper(0b1000_0001, 0b1100_0011)
+            .spi(SpiSpeed::CLK_3MHz)
+            .open_transport(Recorder::new(emu.clone(), log.clone()).unwrap())
+            .unwrap();
+
+        assert_eq!(emu.latency_timer(), 2);
+        assert_eq!(emu.transfer_size(), 512);
+        assert_eq!(
+            emu.timeouts(),
+            (Duration::from_millis(100), Duration::from_millis(200))
+        );
+        assert_eq!(emu.clock_frequency(), 3_000_000);
+        assert!(emu.is_loopback() && dev.is_loopback());
+
+        // bus pins are set up by SPI, the others as requested
+        assert_eq!(emu.gpio_lower(), 0b0011_0000);
+        assert_eq!(emu.gpio_lower_direction(), 0b0111_0011);
+        assert_eq!(emu.gpio_upper(), 0b1000_0001);
+        assert_eq!(emu.gpio_upper_direction(), 0b1100_0011);
+
+        // all the MPSSE commands are sent at once after the sync handshake
+        let writes: Vec<String> = log
+            .requests()
+            .into_iter()
+            .filter(|r| r.starts_with("write "))
+            .collect();
+        assert_eq!(writes.len(), 2);
+        assert_eq!(writes[0], "write aa 87");
+
+        // the bus is preselected
+        assert!(dev.spi(SpiSpeed::CLK_1MHz).is_err());
+        assert!(dev.i2c(I2cSpeed::CLK_AUTO).is_err());
+
+        let mut spi = dev.spi(SpiSpeed::CLK_AUTO).unwrap();
+        let mut data = [0x12, 0x34];
+        assert_eq!(spi.transfer(&mut data).unwrap(), [0x12, 0x34]);
+    }
+
+    #[test]
+    fn test_open_i2c() {
+        let emu = MpsseEmulator::with_chip(ChipType::FT2232C);
+        let dev = FTx232H::builder()
+            .i2c(I2cSpeed::CLK_400kHz)
+            .open_transport(emu.clone())
+            .unwrap();
+
+        assert_eq!(emu.clock_frequency(), 400_000);
+        assert!(dev.i2c(I2cSpeed::CLK_400kHz).is_ok());
+        assert!(dev.spi(SpiSpeed::CLK_AUTO).is_err());
+    }
+
+    #[test]
+    fn test_pins() {
+        // nothing is driven by default
+        let emu = MpsseEmulator::new();
+        let _dev = FTx232H::with_transport(emu.clone()).unwrap();
+        assert_eq!(emu.gpio_lower_direction(), 0x0);
+        assert_eq!(emu.gpio_upper_direction(), 0x0);
+
+        let emu = MpsseEmulator::new();
+        let log = Log::default();
+        let dev = FTx232H::builder()
+            .gpio_upper(0b0000_0011, 0b0000_0011)
+            .pin(PinBank::Low, 5, PinState::High)
+            .pin(PinBank::Low, 6, PinState::Low)
+            .pin(PinBank::High, 0, PinState::Input)
+            .pin(PinBank::High, 7, PinState::High)
+            .open_transport(Recorder::new(emu.clone(), log.clone()).unwrap())
+            .unwrap();
+
+        assert_eq!(emu.gpio_lower(), 0b0010_0000);
+        assert_eq!(emu.gpio_lower_direction(), 0b0110_0000);
+        assert_eq!(emu.gpio_upper(), 0b1000_0010);
+        assert_eq!(emu.gpio_upper_direction(), 0b1000_0010);
+
+        // the pins are set once, by the first MPSSE command
+        let requests = log.requests();
+        let write = requests
+            .iter()
+            .filter(|r| r.starts_with("write "))
+            .nth(1)
+            .unwrap();
+        assert_eq!(write.matches(" 80 ").count(), 1);
+        assert!(write.contains(" 80 20 60 82 82 82"));
+
+        // the pins keep their state when used
+        let mut pl2 = dev.pl2().unwrap();
+        pl2.set_high().unwrap();
+        assert_eq!(emu.gpio_lower(), 0b0110_0000);
+        assert_eq!(emu.gpio_upper_direction(), 0b1000_0010);
+    }
+
+    #[test]
+    fn test_safe_pins() {
+        let emu = MpsseEmulator::new();
+        let dev = FTx232H::builder()
+            .safe_lower(0b0010_0000, 0b0010_0000)
+            .safe_upper(0b0000_0001, 0b0000_0011)
+            .open_transport(emu.clone())
+            .unwrap();
+
+        dev.pl2().unwrap().set_high().unwrap();
+        dev.close().unwrap();
+
+        // the chip stays in MPSSE mode to keep the pattern
+        assert!(emu.is_mpsse());
+        assert!(!emu.is_claimed());
+        assert_eq!(emu.gpio_lower(), 0b0010_0000);
+        assert_eq!(emu.gpio_lower_direction(), 0b0010_0000);
+        assert_eq!(emu.gpio_upper(), 0b0000_0001);
+        assert_eq!(emu.gpio_upper_direction(), 0b0000_0011);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let builders = [
+            (
+                ChipType::FT232H,
+                FTx232H::builder().latency_timer(Duration::ZERO),
+                ErrorKind::InvalidParams,
+            ),
+            (
+                ChipType::FT232H,
+                FTx232H::builder().transfer_size(100),
+                ErrorKind::InvalidParams,
+            ),
+            (
+                ChipType::FT232H,
+                FTx232H::builder().timeouts(Duration::ZERO, Duration::from_secs(1)),
+                ErrorKind::InvalidParams,
+            ),
+            (
+                ChipType::FT2232C,
+                FTx232H::builder().spi(SpiSpeed::CLK_20MHz),
+                ErrorKind::InvalidClock,
+            ),
+            (
+                ChipType::FT4232H,
+                FTx232H::builder().gpio_upper(0x0, 0x1),
+                ErrorKind::GpioNoUpperByte,
+            ),
+            (
+                ChipType::FT4232H,
+                FTx232H::builder().pin(PinBank::High, 3, PinState::Low),
+                ErrorKind::GpioNoUpperByte,
+            ),
+            (
+                ChipType::FT4232H,
+                FTx232H::builder().safe_upper(0x0, 0x1),
+                ErrorKind::GpioNoUpperByte,
+            ),
+            (
+                ChipType::FT232H,
+                FTx232H::builder().pin(PinBank::Low, 8, PinState::Low),
+                ErrorKind::GpioInvalidPin,
+            ),
+        ];
+
+        for (chip, builder, kind) in builders {
+            let emu = MpsseEmulator::with_chip(chip);
+
+            assert!(
+                matches!(builder.open_transport(emu.clone()), Err(X232Error::HAL(k)) if k == kind)
+            );
+
+            // nothing is applied
+            assert!(!emu.is_mpsse());
+            assert_eq!(emu.transfer_size(), 4096);
+        }
+    }
+}
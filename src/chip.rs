@@ -0,0 +1,284 @@
+use crate::transport::Interface;
+
+use std::fmt;
+
+/// FTDI chip families, as reported by the USB device descriptor.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChipType {
+    FT232AM,
+    FT232BM,
+    FT2232C,
+    FT232R,
+    FT2232H,
+    FT4232H,
+    FT232H,
+    FT230X,
+    Unknown,
+}
+
+/// MPSSE features of a chip.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Capabilities {
+    /// MPSSE base clock in Hz, data is clocked at half of it at most.
+    pub base_clock: u32,
+    /// 3-phase data clocking, needed e.g. for I2C.
+    pub three_phase_clocking: bool,
+    /// Adaptive clocking with RTCK on GPIOL3.
+    pub adaptive_clocking: bool,
+    /// Open-drain outputs with `EnableDriveOnlyZero` command.
+    pub drive_only_zero: bool,
+    /// Number of GPIO bytes of an MPSSE interface: lower only or lower and upper.
+    pub gpio_banks: u8,
+    /// Number of interfaces with MPSSE, they always come first: A or A and B.
+    pub mpsse_interfaces: u8,
+}
+
+/// MPSSE clock setup selected by [`Capabilities::clock`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Clock {
+    /// Divide-by-5 prescaler state, `None` for chips without it.
+    pub divide_by_5: Option<bool>,
+    /// Argument of the `SetClockFrequency` command.
+    pub divisor: u16,
+    /// Resulting frequency in Hz, rounded down.
+    pub frequency: u32,
+}
+
+/// Hi-speed chips run MPSSE from 60 MHz, FT2232C/D from 12 MHz.
+const CLOCK_60MHZ: u32 = 60_000_000;
+const CLOCK_12MHZ: u32 = 12_000_000;
+
+impl Capabilities {
+    /// Check if the 60 MHz base clock can be divided by 5 to get 12 MHz with
+    /// `EnableClockDivide`/`DisableClockDivide` commands.
+    pub fn has_clock_divide(&self) -> bool {
+        self.base_clock == CLOCK_60MHZ
+    }
+
+    /// Clock divisor for the highest frequency not above `hz`:
+    /// `freq = base_clock / ((1 + divisor) * 2)`.
+    ///
+    /// Returns `None` if the frequency can not be reached by this chip.
+    pub fn clock_divisor(&self, hz: u32) -> Option<u16> {
+        if hz == 0 || hz > self.base_clock / 2 {
+            return None;
+        }
+
+        // ceil(base_clock / (2 * hz)) - 1
+        let divisor = (self.base_clock - 1) / (2 * hz);
+
+        if divisor > u16::MAX as u32 {
+            return None;
+        }
+
+        Some(divisor as u16)
+    }
+
+    /// Clock setup for the highest frequency not above `hz`. The 60 MHz base
+    /// clock is divided by 5 only for frequencies below 458 Hz, which can
+    /// not be reached by the divisor alone.
+    ///
+    /// Returns `None` if the frequency can not be reached by this chip.
+    pub fn clock(&self, hz: u32) -> Option<Clock> {
+        let slow = Capabilities {
+            base_clock: CLOCK_12MHZ,
+            ..*self
+        };
+
+        let (base_clock, divide_by_5, divisor) = match self.clock_divisor(hz) {
+            Some(divisor) => (self.base_clock, false, divisor),
+            None if self.has_clock_divide() => (CLOCK_12MHZ, true, slow.clock_divisor(hz)?),
+            None => return None,
+        };
+
+        Some(Clock {
+            divide_by_5: self.has_clock_divide().then_some(divide_by_5),
+            divisor,
+            frequency: base_clock / ((1 + divisor as u32) * 2),
+        })
+    }
+}
+
+impl ChipType {
+    /// Detect chip type by the `bcdDevice` field of the USB device descriptor.
+    pub fn from_bcd_device(bcd: u16) -> ChipType {
+        match bcd >> 8 {
+            0x02 => ChipType::FT232AM,
+            0x04 => ChipType::FT232BM,
+            0x05 => ChipType::FT2232C,
+            0x06 => ChipType::FT232R,
+            0x07 => ChipType::FT2232H,
+            0x08 => ChipType::FT4232H,
+            0x09 => ChipType::FT232H,
+            0x10 => ChipType::FT230X,
+            _ => ChipType::Unknown,
+        }
+    }
+
+    /// MPSSE features of the chip, an unknown chip is assumed to be FT232H
+    /// compatible.
+    pub fn capabilities(self) -> Capabilities {
+        let (base_clock, three_phase, adaptive, drive_only_zero, gpio_banks, mpsse) = match self {
+            ChipType::FT232H | ChipType::Unknown => (CLOCK_60MHZ, true, true, true, 2, 1),
+            ChipType::FT2232H => (CLOCK_60MHZ, true, true, false, 2, 2),
+            ChipType::FT4232H => (CLOCK_60MHZ, true, false, false, 1, 2),
+            ChipType::FT2232C => (CLOCK_12MHZ, false, false, false, 2, 1),
+            _ => (CLOCK_12MHZ, false, false, false, 0, 0),
+        };
+
+        Capabilities {
+            base_clock,
+            three_phase_clocking: three_phase,
+            adaptive_clocking: adaptive,
+            drive_only_zero,
+            gpio_banks,
+            mpsse_interfaces: mpsse,
+        }
+    }
+
+    /// Number of interfaces with MPSSE, they always come first: A or A and B.
+    /// An unknown chip is assumed to have one, like FT232H.
+    pub fn mpsse_interfaces(self) -> u8 {
+        self.capabilities().mpsse_interfaces
+    }
+
+    /// Check if MPSSE is available on the interface.
+    pub fn has_mpsse(self, intf: Interface) -> bool {
+        intf.number() < self.mpsse_interfaces()
+    }
+
+    /// Check if MPSSE interfaces have the upper GPIO byte (ACBUS/BCBUS).
+    pub fn has_gpio_upper(self) -> bool {
+        self.capabilities().gpio_banks > 1
+    }
+}
+
+impl fmt::Display for ChipType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChipType::Unknown => write!(f, "unknown"),
+            chip => write!(f, "{:?}", chip),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_bcd_device() {
+        assert_eq!(ChipType::from_bcd_device(0x0900), ChipType::FT232H);
+        assert_eq!(ChipType::from_bcd_device(0x0700), ChipType::FT2232H);
+        assert_eq!(ChipType::from_bcd_device(0x0800), ChipType::FT4232H);
+        assert_eq!(ChipType::from_bcd_device(0x0600), ChipType::FT232R);
+        assert_eq!(ChipType::from_bcd_device(0x1000), ChipType::FT230X);
+        assert_eq!(ChipType::from_bcd_device(0x0100), ChipType::Unknown);
+    }
+
+    #[test]
+    fn test_mpsse_interfaces() {
+        assert_eq!(ChipType::FT232H.mpsse_interfaces(), 1);
+        assert_eq!(ChipType::FT2232H.mpsse_interfaces(), 2);
+        assert_eq!(ChipType::FT4232H.mpsse_interfaces(), 2);
+        assert_eq!(ChipType::FT232R.mpsse_interfaces(), 0);
+        assert_eq!(format!("{}", ChipType::FT2232H), "FT2232H");
+    }
+
+    #[test]
+    fn test_ft4232h() {
+        let chip = ChipType::FT4232H;
+
+        assert!(chip.has_mpsse(Interface::A));
+        assert!(chip.has_mpsse(Interface::B));
+        assert!(!chip.has_mpsse(Interface::C));
+        assert!(!chip.has_mpsse(Interface::D));
+        assert!(!chip.has_gpio_upper());
+
+        assert!(ChipType::FT232H.has_gpio_upper());
+        assert!(!ChipType::FT232H.has_mpsse(Interface::B));
+    }
+
+    #[test]
+    fn test_unknown() {
+        let chip = ChipType::Unknown;
+
+        assert_eq!(chip.capabilities(), ChipType::FT232H.capabilities());
+        assert_eq!(chip.mpsse_interfaces(), 1);
+        assert!(chip.has_mpsse(Interface::A));
+        assert!(!chip.has_mpsse(Interface::B));
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let caps = ChipType::FT232H.capabilities();
+        assert_eq!(caps.base_clock, 60_000_000);
+        assert!(caps.three_phase_clocking && caps.adaptive_clocking && caps.drive_only_zero);
+        assert_eq!(caps.gpio_banks, 2);
+
+        let caps = ChipType::FT2232H.capabilities();
+        assert!(caps.adaptive_clocking && !caps.drive_only_zero);
+
+        let caps = ChipType::FT4232H.capabilities();
+        assert!(caps.three_phase_clocking && !caps.adaptive_clocking);
+        assert_eq!(caps.gpio_banks, 1);
+
+        let caps = ChipType::FT2232C.capabilities();
+        assert_eq!(caps.base_clock, 12_000_000);
+        assert!(!caps.has_clock_divide());
+        assert!(!caps.three_phase_clocking && !caps.adaptive_clocking);
+    }
+
+    #[test]
+    fn test_clock_divisor() {
+        let h = ChipType::FT232H.capabilities();
+
+        assert_eq!(h.clock_divisor(1_000_000), Some(0x1d));
+        assert_eq!(h.clock_divisor(500_000), Some(0x3b));
+        assert_eq!(h.clock_divisor(100_000), Some(0x12b));
+        assert_eq!(h.clock_divisor(400_000), Some(0x4a));
+        assert_eq!(h.clock_divisor(30_000_000), Some(0));
+        // not reachable exactly: the next lower frequency (15MHz)
+        assert_eq!(h.clock_divisor(20_000_000), Some(1));
+        assert_eq!(h.clock_divisor(40_000_000), None);
+        assert_eq!(h.clock_divisor(0), None);
+        assert_eq!(h.clock_divisor(100), None);
+
+        let c = ChipType::FT2232C.capabilities();
+        assert_eq!(c.clock_divisor(1_000_000), Some(5));
+        assert_eq!(c.clock_divisor(6_000_000), Some(0));
+        assert_eq!(c.clock_divisor(10_000_000), None);
+    }
+
+    #[test]
+    fn test_clock() {
+        let h = ChipType::FT232H.capabilities();
+
+        let clock = h.clock(400_000).unwrap();
+        assert_eq!(clock.divide_by_5, Some(false));
+        assert_eq!(clock.divisor, 0x4a);
+        assert_eq!(clock.frequency, 400_000);
+
+        // 60MHz / 14: the next lower frequency
+        let clock = h.clock(4_500_000).unwrap();
+        assert_eq!((clock.divisor, clock.frequency), (6, 4_285_714));
+
+        // the prescaler is needed for the slowest clocks only
+        assert_eq!(h.clock(458).unwrap().divide_by_5, Some(false));
+        let clock = h.clock(100).unwrap();
+        assert_eq!(clock.divide_by_5, Some(true));
+        assert_eq!((clock.divisor, clock.frequency), (59_999, 100));
+        assert_eq!(h.clock(91).map(|c| c.frequency), None);
+        assert_eq!(h.clock(92).map(|c| c.frequency), Some(91));
+        assert_eq!(h.clock(30_000_000).map(|c| c.frequency), Some(30_000_000));
+        assert_eq!(h.clock(30_000_001), None);
+        assert_eq!(h.clock(0), None);
+
+        let c = ChipType::FT2232C.capabilities();
+        let clock = c.clock(1_000_000).unwrap();
+        assert_eq!(clock.divide_by_5, None);
+        assert_eq!((clock.divisor, clock.frequency), (5, 1_000_000));
+        assert_eq!(c.clock(100).map(|c| c.frequency), Some(100));
+        assert_eq!(c.clock(91), None);
+    }
+}
@@ -0,0 +1,831 @@
+//! Decoding of MPSSE command buffers back into operations.
+//!
+//! Useful to see what a [`MpsseCmdBuilder`] or a captured write actually asks
+//! the chip to do:
+//!
+//! ```
+//! use ftdi_embedded_hal::ftdimpsse::{ClockDataOut, MpsseCmdBuilder};
+//!
+//! let cmd = MpsseCmdBuilder::new()
+//!     .set_gpio_lower(0x0, 0xfb)
+//!     .clock_data_out(ClockDataOut::MsbNeg, &[0x12, 0x34])
+//!     .gpio_lower()
+//!     .send_immediate();
+//!
+//! let dis = cmd.disassemble();
+//! assert_eq!(dis.response_len, 1);
+//! assert_eq!(
+//!     dis.to_string(),
+//!     "set_gpio_lower(0x00, 0xfb); clock_data_out(MsbNeg, [12 34]); gpio_lower(); send_immediate()"
+//! );
+//! ```
+
+use crate::ftdimpsse::{
+    ClockBits, ClockBitsIn, ClockBitsOut, ClockData, ClockDataIn, ClockDataOut, ClockTMS,
+    ClockTMSOut, MpsseCmd, MpsseCmdBuilder,
+};
+
+use std::fmt;
+
+/// One MPSSE command, named after the [`MpsseCmdBuilder`] method making it.
+///
+/// Lengths are the numbers of bytes or bits, not the `length - 1` values
+/// sent to the chip.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MpsseOp {
+    SetGpioLower {
+        value: u8,
+        dir: u8,
+    },
+    SetGpioUpper {
+        value: u8,
+        dir: u8,
+    },
+    GetGpioLower,
+    GetGpioUpper,
+    EnableLoopback,
+    DisableLoopback,
+    SetClockFrequency {
+        divisor: u16,
+    },
+    SendImmediate,
+    WaitOnIOHigh,
+    WaitOnIOLow,
+    DisableClockDivide,
+    EnableClockDivide,
+    Enable3PhaseClocking,
+    Disable3PhaseClocking,
+    ClockBitsNoData {
+        len: u8,
+    },
+    ClockBytesNoData {
+        len: usize,
+    },
+    CpuReadShort {
+        addr: u8,
+    },
+    CpuReadExtended {
+        addr: u16,
+    },
+    CpuWriteShort {
+        addr: u8,
+        data: u8,
+    },
+    CpuWriteExtended {
+        addr: u16,
+        data: u8,
+    },
+    ClockUntilIOHigh,
+    ClockUntilIOLow,
+    EnableAdaptiveClocking,
+    DisableAdaptiveClocking,
+    ClockBytesOrIOHigh {
+        len: usize,
+    },
+    ClockBytesOrIOLow {
+        len: usize,
+    },
+    EnableDriveOnlyZero {
+        lower: u8,
+        upper: u8,
+    },
+    ClockDataOut {
+        mode: ClockDataOut,
+        len: usize,
+        data: Vec<u8>,
+    },
+    ClockDataIn {
+        mode: ClockDataIn,
+        len: usize,
+    },
+    ClockData {
+        mode: ClockData,
+        len: usize,
+        data: Vec<u8>,
+    },
+    ClockBitsOut {
+        mode: ClockBitsOut,
+        len: u8,
+        data: u8,
+    },
+    ClockBitsIn {
+        mode: ClockBitsIn,
+        len: u8,
+    },
+    ClockBits {
+        mode: ClockBits,
+        len: u8,
+        data: u8,
+    },
+    ClockTMSOut {
+        mode: ClockTMSOut,
+        len: u8,
+        data: u8,
+        tdi: bool,
+    },
+    ClockTMS {
+        mode: ClockTMS,
+        len: u8,
+        data: u8,
+        tdi: bool,
+    },
+    /// Opcode the chip does not know, it answers with `0xFA` and the opcode.
+    BadOpcode(u8),
+    /// Command cut short at the end of the buffer: the opcode and the bytes
+    /// present after it.
+    Truncated(u8, Vec<u8>),
+}
+
+impl MpsseOp {
+    /// Number of bytes the chip sends back for this command.
+    pub fn response_len(&self) -> usize {
+        match self {
+            MpsseOp::GetGpioLower
+            | MpsseOp::GetGpioUpper
+            | MpsseOp::CpuReadShort { .. }
+            | MpsseOp::CpuReadExtended { .. }
+            | MpsseOp::ClockBitsIn { .. }
+            | MpsseOp::ClockBits { .. }
+            | MpsseOp::ClockTMS { .. } => 1,
+            MpsseOp::ClockDataIn { len, .. } | MpsseOp::ClockData { len, .. } => *len,
+            MpsseOp::BadOpcode(_) => 2,
+            _ => 0,
+        }
+    }
+}
+
+struct Hex<'a>(&'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[")?;
+
+        for (i, b) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:02x}", b)?;
+        }
+
+        write!(f, "]")
+    }
+}
+
+impl fmt::Display for MpsseOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MpsseOp::SetGpioLower { value, dir } => {
+                write!(f, "set_gpio_lower({:#04x}, {:#04x})", value, dir)
+            }
+            MpsseOp::SetGpioUpper { value, dir } => {
+                write!(f, "set_gpio_upper({:#04x}, {:#04x})", value, dir)
+            }
+            MpsseOp::GetGpioLower => write!(f, "gpio_lower()"),
+            MpsseOp::GetGpioUpper => write!(f, "gpio_upper()"),
+            MpsseOp::EnableLoopback => write!(f, "enable_loopback()"),
+            MpsseOp::DisableLoopback => write!(f, "disable_loopback()"),
+            MpsseOp::SetClockFrequency { divisor } => {
+                write!(f, "set_clock_frequency({:#06x})", divisor)
+            }
+            MpsseOp::SendImmediate => write!(f, "send_immediate()"),
+            MpsseOp::WaitOnIOHigh => write!(f, "wait_on_io_high()"),
+            MpsseOp::WaitOnIOLow => write!(f, "wait_on_io_low()"),
+            MpsseOp::DisableClockDivide => write!(f, "disable_clock_divide()"),
+            MpsseOp::EnableClockDivide => write!(f, "enable_clock_divide()"),
+            MpsseOp::Enable3PhaseClocking => write!(f, "enable_3phase_data_clocking()"),
+            MpsseOp::Disable3PhaseClocking => write!(f, "disable_3phase_data_clocking()"),
+            MpsseOp::ClockBitsNoData { len } => write!(f, "clock_bits_no_data({})", len),
+            MpsseOp::ClockBytesNoData { len } => write!(f, "clock_bytes_no_data({})", len),
+            MpsseOp::CpuReadShort { addr } => write!(f, "cpu_read_short({:#04x})", addr),
+            MpsseOp::CpuReadExtended { addr } => write!(f, "cpu_read_extended({:#06x})", addr),
+            MpsseOp::CpuWriteShort { addr, data } => {
+                write!(f, "cpu_write_short({:#04x}, {:#04x})", addr, data)
+            }
+            MpsseOp::CpuWriteExtended { addr, data } => {
+                write!(f, "cpu_write_extended({:#06x}, {:#04x})", addr, data)
+            }
+            MpsseOp::ClockUntilIOHigh => write!(f, "clock_until_io_high()"),
+            MpsseOp::ClockUntilIOLow => write!(f, "clock_until_io_low()"),
+            MpsseOp::EnableAdaptiveClocking => write!(f, "enable_adaptive_data_clocking()"),
+            MpsseOp::DisableAdaptiveClocking => write!(f, "disable_adaptive_data_clocking()"),
+            MpsseOp::ClockBytesOrIOHigh { len } => write!(f, "clock_bytes_or_io_high({})", len),
+            MpsseOp::ClockBytesOrIOLow { len } => write!(f, "clock_bytes_or_io_low({})", len),
+            MpsseOp::EnableDriveOnlyZero { lower, upper } => {
+                write!(f, "enable_drive_only_zero({:#04x}, {:#04x})", lower, upper)
+            }
+            MpsseOp::ClockDataOut { mode, data, .. } => {
+                write!(f, "clock_data_out({:?}, {})", mode, Hex(data))
+            }
+            MpsseOp::ClockDataIn { mode, len } => write!(f, "clock_data_in({:?}, {})", mode, len),
+            MpsseOp::ClockData { mode, data, .. } => {
+                write!(f, "clock_data({:?}, {})", mode, Hex(data))
+            }
+            MpsseOp::ClockBitsOut { mode, len, data } => {
+                write!(f, "clock_bits_out({:?}, {:#04x}, {})", mode, data, len)
+            }
+            MpsseOp::ClockBitsIn { mode, len } => write!(f, "clock_bits_in({:?}, {})", mode, len),
+            MpsseOp::ClockBits { mode, len, data } => {
+                write!(f, "clock_bits({:?}, {:#04x}, {})", mode, data, len)
+            }
+            MpsseOp::ClockTMSOut {
+                mode,
+                len,
+                data,
+                tdi,
+            } => write!(
+                f,
+                "clock_tms_out({:?}, {:#04x}, {}, {})",
+                mode, data, tdi, len
+            ),
+            MpsseOp::ClockTMS {
+                mode,
+                len,
+                data,
+                tdi,
+            } => write!(f, "clock_tms({:?}, {:#04x}, {}, {})", mode, data, tdi, len),
+            MpsseOp::BadOpcode(op) => write!(f, "bad opcode {:#04x}", op),
+            MpsseOp::Truncated(op, rest) => {
+                write!(f, "truncated opcode {:#04x} {}", op, Hex(rest))
+            }
+        }
+    }
+}
+
+/// Operations of a command buffer, see [`disassemble`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Disassembly {
+    pub ops: Vec<MpsseOp>,
+    /// Number of bytes the chip sends back for the whole buffer.
+    pub response_len: usize,
+}
+
+impl Disassembly {
+    /// Check that the buffer has neither unknown opcodes nor a truncated
+    /// command at the end.
+    pub fn is_valid(&self) -> bool {
+        !self
+            .ops
+            .iter()
+            .any(|op| matches!(op, MpsseOp::BadOpcode(_) | MpsseOp::Truncated(_, _)))
+    }
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, op) in self.ops.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", op)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl MpsseCmdBuilder {
+    /// Decode the commands built so far, see [`disassemble`].
+    pub fn disassemble(&self) -> Disassembly {
+        disassemble(self.as_slice())
+    }
+}
+
+// Mode of a data clocking opcode, `None` if the opcode is not one of `modes`
+fn mode<M: Copy + Into<u8>>(op: u8, modes: &[M]) -> Option<M> {
+    modes.iter().copied().find(|&m| m.into() == op)
+}
+
+const DATA_OUT: [ClockDataOut; 4] = [
+    ClockDataOut::MsbPos,
+    ClockDataOut::MsbNeg,
+    ClockDataOut::LsbPos,
+    ClockDataOut::LsbNeg,
+];
+const BITS_OUT: [ClockBitsOut; 4] = [
+    ClockBitsOut::MsbPos,
+    ClockBitsOut::MsbNeg,
+    ClockBitsOut::LsbPos,
+    ClockBitsOut::LsbNeg,
+];
+const DATA_IN: [ClockDataIn; 4] = [
+    ClockDataIn::MsbPos,
+    ClockDataIn::MsbNeg,
+    ClockDataIn::LsbPos,
+    ClockDataIn::LsbNeg,
+];
+const BITS_IN: [ClockBitsIn; 4] = [
+    ClockBitsIn::MsbPos,
+    ClockBitsIn::MsbNeg,
+    ClockBitsIn::LsbPos,
+    ClockBitsIn::LsbNeg,
+];
+const DATA: [ClockData; 4] = [
+    ClockData::MsbPosIn,
+    ClockData::MsbNegIn,
+    ClockData::LsbPosIn,
+    ClockData::LsbNegIn,
+];
+const BITS: [ClockBits; 4] = [
+    ClockBits::MsbPosIn,
+    ClockBits::MsbNegIn,
+    ClockBits::LsbPosIn,
+    ClockBits::LsbNegIn,
+];
+const TMS_OUT: [ClockTMSOut; 2] = [ClockTMSOut::PosEdge, ClockTMSOut::NegEdge];
+const TMS: [ClockTMS; 4] = [
+    ClockTMS::PosTMSPosTDO,
+    ClockTMS::NegTMSPosTDO,
+    ClockTMS::PosTMSNegTDO,
+    ClockTMS::NegTMSNegTDO,
+];
+
+// Number of argument bytes following the opcode, data bytes of byte
+// clocking commands are counted separately
+pub(crate) fn args_len(op: u8) -> Option<usize> {
+    const NO_ARGS: [MpsseCmd; 14] = [
+        MpsseCmd::GetDataBitsLowbyte,
+        MpsseCmd::GetDataBitsHighbyte,
+        MpsseCmd::EnableLoopback,
+        MpsseCmd::DisableLoopback,
+        MpsseCmd::SendImmediate,
+        MpsseCmd::WaitOnIOHigh,
+        MpsseCmd::WaitOnIOLow,
+        MpsseCmd::DisableClockDivide,
+        MpsseCmd::EnableClockDivide,
+        MpsseCmd::Enable3PhaseClocking,
+        MpsseCmd::Disable3PhaseClocking,
+        MpsseCmd::ClockUntilIOHigh,
+        MpsseCmd::ClockUntilIOLow,
+        MpsseCmd::EnableAdaptiveClocking,
+    ];
+
+    let len = if mode(op, &NO_ARGS).is_some() || op == MpsseCmd::DisableAdaptiveClocking.into() {
+        0
+    } else if op == MpsseCmd::ClockBitsNoData.into()
+        || op == MpsseCmd::CpuReadShort.into()
+        || mode(op, &BITS_IN).is_some()
+    {
+        1
+    } else if op == MpsseCmd::SetDataBitsLowbyte.into()
+        || op == MpsseCmd::SetDataBitsHighbyte.into()
+        || op == MpsseCmd::SetClockFrequency.into()
+        || op == MpsseCmd::ClockBytesNoData.into()
+        || op == MpsseCmd::CpuReadExtended.into()
+        || op == MpsseCmd::CpuWriteShort.into()
+        || op == MpsseCmd::ClockBytesOrIOHigh.into()
+        || op == MpsseCmd::ClockBytesOrIOLow.into()
+        || op == MpsseCmd::EnableDriveOnlyZero.into()
+        || mode(op, &DATA_OUT).is_some()
+        || mode(op, &DATA_IN).is_some()
+        || mode(op, &DATA).is_some()
+        || mode(op, &BITS_OUT).is_some()
+        || mode(op, &BITS).is_some()
+        || mode(op, &TMS_OUT).is_some()
+        || mode(op, &TMS).is_some()
+    {
+        2
+    } else if op == MpsseCmd::CpuWriteExtended.into() {
+        3
+    } else {
+        return None;
+    };
+
+    Some(len)
+}
+
+// Decode a complete command: `args` has exactly `args_len(op)` bytes and
+// `data` the bytes clocked out by byte clocking commands
+fn decode(op: u8, args: &[u8], data: &[u8]) -> MpsseOp {
+    let byte_len = |args: &[u8]| (args[0] as usize | (args[1] as usize) << 8) + 1;
+    let tms = |args: &[u8]| (args[0] + 1, args[1] & 0x7f, args[1] & 0x80 != 0);
+
+    if let Some(mode) = mode(op, &DATA_OUT) {
+        return MpsseOp::ClockDataOut {
+            mode,
+            len: data.len(),
+            data: data.to_vec(),
+        };
+    }
+    if let Some(mode) = mode(op, &DATA) {
+        return MpsseOp::ClockData {
+            mode,
+            len: data.len(),
+            data: data.to_vec(),
+        };
+    }
+    if let Some(mode) = mode(op, &DATA_IN) {
+        return MpsseOp::ClockDataIn {
+            mode,
+            len: byte_len(args),
+        };
+    }
+    if let Some(mode) = mode(op, &BITS_OUT) {
+        return MpsseOp::ClockBitsOut {
+            mode,
+            len: args[0] + 1,
+            data: args[1],
+        };
+    }
+    if let Some(mode) = mode(op, &BITS_IN) {
+        return MpsseOp::ClockBitsIn {
+            mode,
+            len: args[0] + 1,
+        };
+    }
+    if let Some(mode) = mode(op, &BITS) {
+        return MpsseOp::ClockBits {
+            mode,
+            len: args[0] + 1,
+            data: args[1],
+        };
+    }
+    if let Some(mode) = mode(op, &TMS_OUT) {
+        let (len, data, tdi) = tms(args);
+        return MpsseOp::ClockTMSOut {
+            mode,
+            len,
+            data,
+            tdi,
+        };
+    }
+    if let Some(mode) = mode(op, &TMS) {
+        let (len, data, tdi) = tms(args);
+        return MpsseOp::ClockTMS {
+            mode,
+            len,
+            data,
+            tdi,
+        };
+    }
+
+    let is = |cmd: MpsseCmd| op == cmd.into();
+
+    if is(MpsseCmd::SetDataBitsLowbyte) {
+        MpsseOp::SetGpioLower {
+            value: args[0],
+            dir: args[1],
+        }
+    } else if is(MpsseCmd::SetDataBitsHighbyte) {
+        MpsseOp::SetGpioUpper {
+            value: args[0],
+            dir: args[1],
+        }
+    } else if is(MpsseCmd::GetDataBitsLowbyte) {
+        MpsseOp::GetGpioLower
+    } else if is(MpsseCmd::GetDataBitsHighbyte) {
+        MpsseOp::GetGpioUpper
+    } else if is(MpsseCmd::EnableLoopback) {
+        MpsseOp::EnableLoopback
+    } else if is(MpsseCmd::DisableLoopback) {
+        MpsseOp::DisableLoopback
+    } else if is(MpsseCmd::SetClockFrequency) {
+        MpsseOp::SetClockFrequency {
+            divisor: args[0] as u16 | (args[1] as u16) << 8,
+        }
+    } else if is(MpsseCmd::SendImmediate) {
+        MpsseOp::SendImmediate
+    } else if is(MpsseCmd::WaitOnIOHigh) {
+        MpsseOp::WaitOnIOHigh
+    } else if is(MpsseCmd::WaitOnIOLow) {
+        MpsseOp::WaitOnIOLow
+    } else if is(MpsseCmd::DisableClockDivide) {
+        MpsseOp::DisableClockDivide
+    } else if is(MpsseCmd::EnableClockDivide) {
+        MpsseOp::EnableClockDivide
+    } else if is(MpsseCmd::Enable3PhaseClocking) {
+        MpsseOp::Enable3PhaseClocking
+    } else if is(MpsseCmd::Disable3PhaseClocking) {
+        MpsseOp::Disable3PhaseClocking
+    } else if is(MpsseCmd::ClockBitsNoData) {
+        MpsseOp::ClockBitsNoData { len: args[0] + 1 }
+    } else if is(MpsseCmd::ClockBytesNoData) {
+        MpsseOp::ClockBytesNoData {
+            len: byte_len(args),
+        }
+    } else if is(MpsseCmd::CpuReadShort) {
+        MpsseOp::CpuReadShort { addr: args[0] }
+    } else if is(MpsseCmd::CpuReadExtended) {
+        MpsseOp::CpuReadExtended {
+            addr: (args[0] as u16) << 8 | args[1] as u16,
+        }
+    } else if is(MpsseCmd::CpuWriteShort) {
+        MpsseOp::CpuWriteShort {
+            addr: args[0],
+            data: args[1],
+        }
+    } else if is(MpsseCmd::CpuWriteExtended) {
+        MpsseOp::CpuWriteExtended {
+            addr: (args[0] as u16) << 8 | args[1] as u16,
+            data: args[2],
+        }
+    } else if is(MpsseCmd::ClockUntilIOHigh) {
+        MpsseOp::ClockUntilIOHigh
+    } else if is(MpsseCmd::ClockUntilIOLow) {
+        MpsseOp::ClockUntilIOLow
+    } else if is(MpsseCmd::EnableAdaptiveClocking) {
+        MpsseOp::EnableAdaptiveClocking
+    } else if is(MpsseCmd::DisableAdaptiveClocking) {
+        MpsseOp::DisableAdaptiveClocking
+    } else if is(MpsseCmd::ClockBytesOrIOHigh) {
+        MpsseOp::ClockBytesOrIOHigh {
+            len: byte_len(args),
+        }
+    } else if is(MpsseCmd::ClockBytesOrIOLow) {
+        MpsseOp::ClockBytesOrIOLow {
+            len: byte_len(args),
+        }
+    } else if is(MpsseCmd::EnableDriveOnlyZero) {
+        MpsseOp::EnableDriveOnlyZero {
+            lower: args[0],
+            upper: args[1],
+        }
+    } else {
+        MpsseOp::BadOpcode(op)
+    }
+}
+
+// Command split off a buffer: opcode, arguments and data bytes
+enum Command<'a> {
+    Op(u8, &'a [u8], &'a [u8]),
+    Bad(u8),
+    Truncated(u8, &'a [u8]),
+}
+
+// Commands of a buffer, a truncated command is the last one
+struct Commands<'a>(&'a [u8]);
+
+impl<'a> Iterator for Commands<'a> {
+    type Item = Command<'a>;
+
+    fn next(&mut self) -> Option<Command<'a>> {
+        let (&op, rest) = self.0.split_first()?;
+
+        let args_len = match args_len(op) {
+            Some(len) => len,
+            None => {
+                self.0 = rest;
+                return Some(Command::Bad(op));
+            }
+        };
+
+        // data bytes follow the length of byte clocking commands
+        let data_len = if rest.len() >= args_len
+            && (mode(op, &DATA_OUT).is_some() || mode(op, &DATA).is_some())
+        {
+            (rest[0] as usize | (rest[1] as usize) << 8) + 1
+        } else {
+            0
+        };
+
+        if rest.len() < args_len + data_len {
+            self.0 = &[];
+            return Some(Command::Truncated(op, rest));
+        }
+
+        let (args, rest) = rest.split_at(args_len);
+        let (payload, rest) = rest.split_at(data_len);
+        self.0 = rest;
+
+        Some(Command::Op(op, args, payload))
+    }
+}
+
+/// Decode a buffer of MPSSE commands.
+///
+/// The chip skips unknown opcodes after reporting them, so decoding goes on
+/// after a [`MpsseOp::BadOpcode`]. A command cut short at the end of the
+/// buffer is returned as [`MpsseOp::Truncated`].
+pub fn disassemble(data: &[u8]) -> Disassembly {
+    let mut dis = Disassembly::default();
+
+    for cmd in Commands(data) {
+        let op = match cmd {
+            Command::Op(op, args, payload) => decode(op, args, payload),
+            Command::Bad(op) => MpsseOp::BadOpcode(op),
+            Command::Truncated(op, rest) => MpsseOp::Truncated(op, rest.to_vec()),
+        };
+
+        dis.response_len += op.response_len();
+        dis.ops.push(op);
+    }
+
+    dis
+}
+
+// Opcode and response length of the complete commands of a buffer, data
+// bytes are not copied unlike `disassemble`
+pub(crate) fn responses(data: &[u8]) -> impl Iterator<Item = (u8, usize)> + '_ {
+    Commands(data).filter_map(|cmd| match cmd {
+        Command::Op(op, _, payload) if mode(op, &DATA).is_some() => Some((op, payload.len())),
+        Command::Op(op, args, _) => Some((op, decode(op, args, &[]).response_len())),
+        Command::Bad(op) => Some((op, 2)),
+        Command::Truncated(_, _) => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let cmd = MpsseCmdBuilder::new()
+            .set_gpio_lower(0x12, 0xfb)
+            .set_gpio_upper(0x34, 0x0f)
+            .gpio_lower()
+            .gpio_upper()
+            .enable_loopback()
+            .disable_loopback()
+            .disable_3phase_data_clocking()
+            .enable_3phase_data_clocking()
+            .enable_adaptive_data_clocking()
+            .disable_adaptive_data_clocking()
+            .enable_drive_only_zero(0x03, 0x00)
+            .wait_on_io_high()
+            .wait_on_io_low()
+            .clock_data_out(ClockDataOut::MsbNeg, &[0xaa, 0x55])
+            .clock_data_in(ClockDataIn::MsbPos, 300)
+            .clock_data(ClockData::LsbNegIn, &[0x01, 0x02, 0x03])
+            .clock_bits_out(ClockBitsOut::LsbPos, 0x5, 3)
+            .clock_bits_in(ClockBitsIn::MsbNeg, 8)
+            .clock_bits(ClockBits::MsbPosIn, 0x80, 1)
+            .clock_tms_out(ClockTMSOut::NegEdge, 0x1f, true, 5)
+            .clock_tms(ClockTMS::PosTMSNegTDO, 0x03, false, 2)
+            .clock_bits_no_data(4)
+            .clock_bytes_no_data(1024)
+            .clock_until_io_high()
+            .clock_until_io_low()
+            .clock_bytes_or_io_high(2)
+            .clock_bytes_or_io_low(65536)
+            .cpu_read_short(0x10)
+            .cpu_read_extended(0x1234)
+            .cpu_write_short(0x20, 0xee)
+            .cpu_write_extended(0x5678, 0xdd)
+            .send_immediate();
+
+        let (clock, _) = MpsseCmdBuilder::new()
+            .set_clock(100, crate::chip::ChipType::FT232H)
+            .unwrap();
+
+        let dis = cmd.disassemble();
+        assert!(dis.is_valid());
+        assert_eq!(
+            dis.ops,
+            [
+                MpsseOp::SetGpioLower {
+                    value: 0x12,
+                    dir: 0xfb
+                },
+                MpsseOp::SetGpioUpper {
+                    value: 0x34,
+                    dir: 0x0f
+                },
+                MpsseOp::GetGpioLower,
+                MpsseOp::GetGpioUpper,
+                MpsseOp::EnableLoopback,
+                MpsseOp::DisableLoopback,
+                MpsseOp::Disable3PhaseClocking,
+                MpsseOp::Enable3PhaseClocking,
+                MpsseOp::EnableAdaptiveClocking,
+                MpsseOp::DisableAdaptiveClocking,
+                MpsseOp::EnableDriveOnlyZero {
+                    lower: 0x03,
+                    upper: 0x00
+                },
+                MpsseOp::WaitOnIOHigh,
+                MpsseOp::WaitOnIOLow,
+                MpsseOp::ClockDataOut {
+                    mode: ClockDataOut::MsbNeg,
+                    len: 2,
+                    data: vec![0xaa, 0x55]
+                },
+                MpsseOp::ClockDataIn {
+                    mode: ClockDataIn::MsbPos,
+                    len: 300
+                },
+                MpsseOp::ClockData {
+                    mode: ClockData::LsbNegIn,
+                    len: 3,
+                    data: vec![0x01, 0x02, 0x03]
+                },
+                MpsseOp::ClockBitsOut {
+                    mode: ClockBitsOut::LsbPos,
+                    len: 3,
+                    data: 0x5
+                },
+                MpsseOp::ClockBitsIn {
+                    mode: ClockBitsIn::MsbNeg,
+                    len: 8
+                },
+                MpsseOp::ClockBits {
+                    mode: ClockBits::MsbPosIn,
+                    len: 1,
+                    data: 0x80
+                },
+                MpsseOp::ClockTMSOut {
+                    mode: ClockTMSOut::NegEdge,
+                    len: 5,
+                    data: 0x1f,
+                    tdi: true
+                },
+                MpsseOp::ClockTMS {
+                    mode: ClockTMS::PosTMSNegTDO,
+                    len: 2,
+                    data: 0x03,
+                    tdi: false
+                },
+                MpsseOp::ClockBitsNoData { len: 4 },
+                MpsseOp::ClockBytesNoData { len: 1024 },
+                MpsseOp::ClockUntilIOHigh,
+                MpsseOp::ClockUntilIOLow,
+                MpsseOp::ClockBytesOrIOHigh { len: 2 },
+                MpsseOp::ClockBytesOrIOLow { len: 65536 },
+                MpsseOp::CpuReadShort { addr: 0x10 },
+                MpsseOp::CpuReadExtended { addr: 0x1234 },
+                MpsseOp::CpuWriteShort {
+                    addr: 0x20,
+                    data: 0xee
+                },
+                MpsseOp::CpuWriteExtended {
+                    addr: 0x5678,
+                    data: 0xdd
+                },
+                MpsseOp::SendImmediate,
+            ]
+        );
+
+        // gpio x2, data in + data, bits in + bits, TMS, CPU reads x2
+        assert_eq!(dis.response_len, 2 + 300 + 3 + 2 + 1 + 2);
+
+        assert_eq!(
+            clock.disassemble().ops,
+            [
+                MpsseOp::EnableClockDivide,
+                MpsseOp::SetClockFrequency { divisor: 59_999 }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bad_opcode() {
+        // the chip skips an unknown opcode and goes on
+        let dis = disassemble(&[0xaa, 0x81, 0x30, 0x87]);
+
+        assert!(!dis.is_valid());
+        assert_eq!(
+            dis.ops,
+            [
+                MpsseOp::BadOpcode(0xaa),
+                MpsseOp::GetGpioLower,
+                MpsseOp::BadOpcode(0x30),
+                MpsseOp::SendImmediate,
+            ]
+        );
+        assert_eq!(dis.response_len, 5);
+    }
+
+    #[test]
+    fn test_truncated() {
+        for (data, op) in [
+            (&[0x80, 0x12][..], MpsseOp::Truncated(0x80, vec![0x12])),
+            (
+                &[0x11, 0x02, 0x00, 0xaa],
+                MpsseOp::Truncated(0x11, vec![0x02, 0x00, 0xaa]),
+            ),
+            (&[0x11, 0x02], MpsseOp::Truncated(0x11, vec![0x02])),
+            (
+                &[0x93, 0x12, 0x34],
+                MpsseOp::Truncated(0x93, vec![0x12, 0x34]),
+            ),
+        ] {
+            let mut buf = vec![0x87];
+            buf.extend_from_slice(data);
+            let dis = disassemble(&buf);
+
+            assert!(!dis.is_valid());
+            assert_eq!(dis.ops, [MpsseOp::SendImmediate, op]);
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let cmd = MpsseCmdBuilder::new()
+            .set_gpio_upper(0x1, 0xff)
+            .clock_data(ClockData::MsbPosIn, &[0xde, 0xad])
+            .clock_data_in(ClockDataIn::LsbNeg, 16)
+            .clock_bits_out(ClockBitsOut::MsbNeg, 0x80, 1)
+            .clock_tms_out(ClockTMSOut::PosEdge, 0x3, false, 2)
+            .cpu_write_extended(0x100, 0x2);
+
+        let mut data = cmd.as_slice().to_vec();
+        data.extend_from_slice(&[0xab, 0x86, 0x01]);
+
+        assert_eq!(
+            disassemble(&data).to_string(),
+            "set_gpio_upper(0x01, 0xff); clock_data(MsbPosIn, [de ad]); \
+             clock_data_in(LsbNeg, 16); clock_bits_out(MsbNeg, 0x80, 1); \
+             clock_tms_out(PosEdge, 0x03, false, 2); cpu_write_extended(0x0100, 0x02); \
+             bad opcode 0xab; truncated opcode 0x86 [01]"
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! Both MPSSE channels of a dual-channel chip (FT2232H, FT4232H) in one handle.
+//!
+//! Each channel is a separate USB interface with its own [`FTx232H`] state:
+//! buses, pins and lock. So e.g. SPI on channel A and I2C on channel B can be
+//! used at the same time, and work on one channel never waits for the other.
+
+use crate::chip::ChipType;
+use crate::enumerate::DeviceSelector;
+use crate::error::Result;
+use crate::transport::{Interface, MpsseTransport};
+use crate::x232h::{Device, FTx232H};
+
+pub struct FTx232HDual<T: MpsseTransport = Device> {
+    a: FTx232H<T>,
+    b: FTx232H<T>,
+}
+
+impl FTx232HDual<Device> {
+    /// Open channels A and B of the first device with the given VID/PID.
+    ///
+    /// Channel B is opened on the same chip as channel A. With libftdi it
+    /// is found by the serial number, so this fails with `DeviceAmbiguous`
+    /// if several adapters with the VID/PID can not be told apart.
+    pub fn init(vendor: u16, product: u16) -> Result<FTx232HDual> {
+        let (a, chip) = FTx232H::open_device(vendor, product, Interface::A)?;
+        FTx232HDual::init_channels(a, chip)
+    }
+
+    /// Open channels A and B of the adapter matching `selector`.
+    pub fn init_device(selector: &DeviceSelector) -> Result<FTx232HDual> {
+        let (a, chip) = FTx232H::open_selected(selector, Interface::A)?;
+        FTx232HDual::init_channels(a, chip)
+    }
+
+    fn init_channels(mut a: Device, chip: Option<ChipType>) -> Result<FTx232HDual> {
+        let (b, chip_b) = FTx232H::open_sibling(&mut a, Interface::B)?;
+
+        Ok(FTx232HDual {
+            a: FTx232H::with_transport_chip(a, chip)?,
+            b: FTx232H::with_transport_chip(b, chip_b)?,
+        })
+    }
+}
+
+impl<T: MpsseTransport> FTx232HDual<T> {
+    /// Initialize MPSSE on already opened transports of channels A and B.
+    pub fn with_transports(a: T, b: T) -> Result<FTx232HDual<T>> {
+        Ok(FTx232HDual {
+            a: FTx232H::with_transport(a)?,
+            b: FTx232H::with_transport(b)?,
+        })
+    }
+
+    /// Channel A.
+    pub fn a(&self) -> &FTx232H<T> {
+        &self.a
+    }
+
+    /// Channel B.
+    pub fn b(&self) -> &FTx232H<T> {
+        &self.b
+    }
+
+    /// Channel by interface, `None` for interfaces C and D.
+    pub fn channel(&self, intf: Interface) -> Option<&FTx232H<T>> {
+        match intf {
+            Interface::A => Some(&self.a),
+            Interface::B => Some(&self.b),
+            _ => None,
+        }
+    }
+
+    /// Mutable access to both channels at once, e.g. to switch loopback.
+    pub fn channels_mut(&mut self) -> (&mut FTx232H<T>, &mut FTx232H<T>) {
+        (&mut self.a, &mut self.b)
+    }
+
+    /// Split into independent channel A and B handles.
+    pub fn split(self) -> (FTx232H<T>, FTx232H<T>) {
+        (self.a, self.b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip::ChipType;
+    use crate::emulator::i2c::Lm75;
+    use crate::emulator::MpsseEmulator;
+    use crate::i2c::I2cSpeed;
+    use crate::spi::SpiSpeed;
+    use embedded_hal::blocking::i2c::WriteRead;
+    use embedded_hal::blocking::spi::Transfer;
+
+    fn dual() -> (FTx232HDual<MpsseEmulator>, MpsseEmulator, MpsseEmulator) {
+        let a = MpsseEmulator::with_chip(ChipType::FT2232H);
+        let b = MpsseEmulator::with_chip(ChipType::FT2232H);
+        let dev = FTx232HDual::with_transports(a.clone(), b.clone()).unwrap();
+
+        (dev, a, b)
+    }
+
+    #[test]
+    fn test_spi_and_i2c() {
+        let (mut dev, _, b) = dual();
+        b.add_i2c_target(Lm75::with_default_address(21.0));
+
+        dev.channels_mut().0.loopback(true).unwrap();
+        assert!(!dev.b().is_loopback());
+
+        // both buses are alive at the same time
+        let mut spi = dev.a().spi(SpiSpeed::CLK_1MHz).unwrap();
+        let mut i2c = dev.b().i2c(I2cSpeed::CLK_400kHz).unwrap();
+
+        for v in 0..8 {
+            let mut tx = [v, v + 1];
+            assert_eq!(spi.transfer(&mut tx).unwrap(), [v, v + 1]);
+
+            let mut rx = [0; 2];
+            i2c.write_read(0x48, &[0x0], &mut rx).unwrap();
+            assert_eq!(rx, [21, 0]);
+        }
+    }
+
+    #[test]
+    fn test_independent_pins() {
+        let (dev, a, b) = dual();
+
+        let _pa = dev.a().pl0().unwrap();
+        let _pb = dev.b().pl0().unwrap();
+
+        // pins are tracked per channel
+        assert!(dev.a().pl0().is_err());
+        assert!(dev.b().pl0().is_err());
+
+        // I2C and SPI exclude each other only within a channel
+        let _spi = dev.a().spi(SpiSpeed::CLK_AUTO).unwrap();
+        assert!(dev.b().i2c(I2cSpeed::CLK_AUTO).is_ok());
+        assert!(dev.a().i2c(I2cSpeed::CLK_AUTO).is_err());
+
+        assert_eq!(a.clock_frequency(), 1_000_000);
+        assert_eq!(b.clock_frequency(), 100_000);
+    }
+
+    #[test]
+    fn test_channel() {
+        let (dev, _, _) = dual();
+
+        assert!(dev.channel(Interface::A).is_some());
+        assert!(dev.channel(Interface::B).is_some());
+        assert!(dev.channel(Interface::C).is_none());
+
+        let (a, b) = dev.split();
+        assert_eq!(a.chip_type(), Some(ChipType::FT2232H));
+        assert_eq!(b.chip_type(), Some(ChipType::FT2232H));
+    }
+}
@@ -0,0 +1,1361 @@
+//! Software model of the FTDI MPSSE command processor.
+//!
+//! [`MpsseEmulator`] implements [`MpsseTransport`], so it can be passed to
+//! [`FTx232H::with_transport`] in place of a real FT232H/FT2232H. It consumes
+//! the command streams produced by [`MpsseCmdBuilder`], keeps track of pin
+//! levels and directions, clock settings and loopback state, and queues the
+//! bytes that a real chip would return.
+//!
+//! The emulator is a cheap handle: clones share the same simulated chip, so a
+//! test can keep one clone to inspect or drive the pins while the other one is
+//! owned by [`FTx232H`].
+//!
+//! Virtual I2C and SPI devices can be attached to the emulated buses, see the
+//! [`i2c`] and [`spi`] modules.
+//!
+//! [`FTx232H`]: crate::x232h::FTx232H
+//! [`FTx232H::with_transport`]: crate::x232h::FTx232H::with_transport
+//! [`MpsseCmdBuilder`]: crate::ftdimpsse::MpsseCmdBuilder
+
+pub mod i2c;
+pub mod spi;
+
+use crate::chip::ChipType;
+use crate::disasm;
+use crate::error::{ErrorKind, Result, X232Error};
+use crate::ftdimpsse::MpsseCmd;
+use crate::gpio::PinBank;
+use crate::transport::{BitMode, MpsseTransport};
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use self::i2c::{I2cTarget, I2cWire};
+use self::spi::{SpiTarget, SpiWire};
+
+// Bits of the data shifting opcodes, see AN 108 section 3.2
+const CMD_BIT_MODE: u8 = 0x02;
+const CMD_LSB_FIRST: u8 = 0x08;
+const CMD_WRITE_TDI: u8 = 0x10;
+const CMD_READ_TDO: u8 = 0x20;
+
+// Low byte pins used by the data shifting commands
+const PIN_SK: u8 = 0b0000_0001;
+const PIN_DO: u8 = 0b0000_0010;
+const PIN_DI: u8 = 0b0000_0100;
+const PIN_TMS: u8 = 0b0000_1000;
+
+// Response to an opcode that MPSSE does not recognize
+const BAD_COMMAND: u8 = 0xFA;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+struct Port {
+    value: u8,
+    direction: u8,
+    input: u8,
+    drive_zero: u8,
+}
+
+impl Port {
+    fn level(&self) -> u8 {
+        // drive-only-zero outputs are tri-stated while high
+        let driven = self.direction & !(self.drive_zero & self.value);
+        (self.value & driven) | (self.input & !driven)
+    }
+}
+
+struct Chip {
+    chip_type: ChipType,
+    mode: BitMode,
+    lower: Port,
+    upper: Port,
+    loopback: bool,
+    divide_by_5: bool,
+    divisor: u16,
+    three_phase: bool,
+    adaptive: bool,
+    pending: Vec<u8>,
+    response: VecDeque<u8>,
+    i2c: I2cWire,
+    spi: SpiWire,
+    connected: bool,
+    generation: u32,
+    // interface not released by `close`
+    claimed: bool,
+    latency: u8,
+    // host side USB settings
+    transfer_size: u32,
+    timeouts: (Duration, Duration),
+}
+
+impl Default for Chip {
+    fn default() -> Self {
+        Chip {
+            chip_type: ChipType::FT232H,
+            mode: BitMode::Reset,
+            lower: Port::default(),
+            upper: Port::default(),
+            loopback: false,
+            divide_by_5: true,
+            divisor: 0,
+            three_phase: false,
+            adaptive: false,
+            pending: Vec::new(),
+            response: VecDeque::new(),
+            i2c: I2cWire::new(),
+            spi: SpiWire::new(),
+            connected: true,
+            generation: 0,
+            claimed: true,
+            latency: 16,
+            transfer_size: 4096,
+            timeouts: (Duration::from_secs(1), Duration::from_secs(1)),
+        }
+    }
+}
+
+impl Chip {
+    // Back to the power-on state, external inputs, attached targets and
+    // USB connection state are kept
+    fn power_on(&mut self) {
+        let old = std::mem::take(self);
+
+        self.chip_type = old.chip_type;
+        self.lower.input = old.lower.input;
+        self.upper.input = old.upper.input;
+        self.i2c = old.i2c;
+        self.spi = old.spi;
+        self.connected = old.connected;
+        self.generation = old.generation;
+        self.transfer_size = old.transfer_size;
+        self.timeouts = old.timeouts;
+    }
+
+    // Pin levels of the lower byte, SK/DO/DI inputs read I2C lines if the bus
+    // has targets attached: pull-ups keep released lines high.
+    fn lower_level(&self) -> u8 {
+        let mut level = self.lower.level();
+
+        if self.i2c.is_active() {
+            let released = !self.lower.direction & (PIN_SK | PIN_DO | PIN_DI);
+            level |= released;
+
+            if self.lower.direction & PIN_DI == 0 && level & PIN_DO == 0 {
+                level &= !PIN_DI;
+            }
+        }
+
+        level
+    }
+
+    fn update_lines(&mut self) {
+        let level = self.lower_level();
+
+        if self.i2c.is_active() {
+            self.i2c.lines(level & PIN_SK != 0, level & PIN_DO != 0);
+        }
+
+        self.spi.update(level, self.upper.level());
+    }
+
+    // Length of the command at the head of the pending buffer or None if
+    // more bytes are needed to tell.
+    fn command_len(&self) -> Option<usize> {
+        let cmd = &self.pending;
+        let op = *cmd.first()?;
+
+        if is_data_cmd(op) {
+            let write = op & CMD_WRITE_TDI != 0;
+
+            if op & CMD_BIT_MODE != 0 {
+                return Some(if write { 3 } else { 2 });
+            }
+
+            if !write {
+                return Some(3);
+            }
+
+            if cmd.len() < 3 {
+                return None;
+            }
+
+            let len = (cmd[1] as usize | (cmd[2] as usize) << 8) + 1;
+            return Some(3 + len);
+        }
+
+        // the chip takes an unknown opcode for a 1-byte command
+        Some(1 + disasm::args_len(op).unwrap_or(0))
+    }
+
+    fn process(&mut self) {
+        while let Some(len) = self.command_len() {
+            if self.pending.len() < len {
+                break;
+            }
+
+            let cmd: Vec<u8> = self.pending.drain(..len).collect();
+            self.execute(&cmd);
+        }
+    }
+
+    fn execute(&mut self, cmd: &[u8]) {
+        let op = cmd[0];
+        let caps = self.chip_type.capabilities();
+
+        if is_data_cmd(op) {
+            self.clock_data(cmd);
+            return;
+        }
+
+        match op {
+            op if op == MpsseCmd::SetDataBitsLowbyte as u8 => {
+                self.lower.value = cmd[1];
+                self.lower.direction = cmd[2];
+                self.update_lines();
+            }
+            op if op == MpsseCmd::GetDataBitsLowbyte as u8 => {
+                let level = self.lower_level();
+                self.response.push_back(level);
+            }
+            op if op == MpsseCmd::SetDataBitsHighbyte as u8 && self.chip_type.has_gpio_upper() => {
+                self.upper.value = cmd[1];
+                self.upper.direction = cmd[2];
+                self.update_lines();
+            }
+            op if op == MpsseCmd::GetDataBitsHighbyte as u8 && self.chip_type.has_gpio_upper() => {
+                let level = self.upper.level();
+                self.response.push_back(level);
+            }
+            op if op == MpsseCmd::EnableLoopback as u8 => self.loopback = true,
+            op if op == MpsseCmd::DisableLoopback as u8 => self.loopback = false,
+            op if op == MpsseCmd::SetClockFrequency as u8 => {
+                self.divisor = cmd[1] as u16 | (cmd[2] as u16) << 8;
+            }
+            op if op == MpsseCmd::DisableClockDivide as u8 && caps.has_clock_divide() => {
+                self.divide_by_5 = false
+            }
+            op if op == MpsseCmd::EnableClockDivide as u8 && caps.has_clock_divide() => {
+                self.divide_by_5 = true
+            }
+            op if op == MpsseCmd::Enable3PhaseClocking as u8 && caps.three_phase_clocking => {
+                self.three_phase = true
+            }
+            op if op == MpsseCmd::Disable3PhaseClocking as u8 && caps.three_phase_clocking => {
+                self.three_phase = false
+            }
+            op if op == MpsseCmd::EnableAdaptiveClocking as u8 && caps.adaptive_clocking => {
+                self.adaptive = true
+            }
+            op if op == MpsseCmd::DisableAdaptiveClocking as u8 && caps.adaptive_clocking => {
+                self.adaptive = false
+            }
+            op if op == MpsseCmd::EnableDriveOnlyZero as u8 && caps.drive_only_zero => {
+                self.lower.drive_zero = cmd[1];
+                self.upper.drive_zero = cmd[2];
+                self.update_lines();
+            }
+            op if is_tms_cmd(op) => self.clock_tms(cmd),
+            op if op == MpsseCmd::ClockBitsNoData as u8 && caps.has_clock_divide() => {
+                self.clock_no_data(cmd[1] as usize + 1);
+            }
+            // GPIOL1 is never waited for, all the bytes are clocked
+            op if (op == MpsseCmd::ClockBytesNoData as u8
+                || op == MpsseCmd::ClockBytesOrIOHigh as u8
+                || op == MpsseCmd::ClockBytesOrIOLow as u8)
+                && caps.has_clock_divide() =>
+            {
+                let len = (cmd[1] as usize | (cmd[2] as usize) << 8) + 1;
+                self.clock_no_data(len * 8);
+            }
+            op if (op == MpsseCmd::ClockUntilIOHigh as u8
+                || op == MpsseCmd::ClockUntilIOLow as u8)
+                && caps.has_clock_divide() => {}
+            // there is nothing on the CPU bus: reads float high, writes are lost
+            op if op == MpsseCmd::CpuReadShort as u8 || op == MpsseCmd::CpuReadExtended as u8 => {
+                self.response.push_back(0xff);
+            }
+            op if op == MpsseCmd::CpuWriteShort as u8 || op == MpsseCmd::CpuWriteExtended as u8 => {
+            }
+            // responses are readable right away, waits are never blocking
+            op if op == MpsseCmd::SendImmediate as u8
+                || op == MpsseCmd::WaitOnIOHigh as u8
+                || op == MpsseCmd::WaitOnIOLow as u8 => {}
+            _ => {
+                self.response.push_back(BAD_COMMAND);
+                self.response.push_back(op);
+            }
+        }
+    }
+
+    fn clock_data(&mut self, cmd: &[u8]) {
+        let op = cmd[0];
+        let lsb = op & CMD_LSB_FIRST != 0;
+        let write = op & CMD_WRITE_TDI != 0;
+        let read = op & CMD_READ_TDO != 0;
+
+        if op & CMD_BIT_MODE != 0 {
+            let bits = cmd[1] + 1;
+            let data = if write { Some(cmd[2]) } else { None };
+            let mut byte: u8 = 0;
+
+            for n in 0..bits {
+                let pos = if lsb { n } else { 7 - n };
+                let bit = self.clock_bit(data.map(|d| d & (1 << pos) != 0));
+
+                byte = if lsb {
+                    (byte >> 1) | ((bit as u8) << 7)
+                } else {
+                    (byte << 1) | bit as u8
+                };
+            }
+
+            if read {
+                self.response.push_back(byte);
+            }
+
+            return;
+        }
+
+        let len = (cmd[1] as usize | (cmd[2] as usize) << 8) + 1;
+
+        for i in 0..len {
+            let data = if write { Some(cmd[3 + i]) } else { None };
+            let mut byte: u8 = 0;
+
+            // DO keeps its level when nothing is clocked out
+            let idle = if self.lower.value & PIN_DO != 0 {
+                0xff
+            } else {
+                0x0
+            };
+            let miso = if self.loopback {
+                None
+            } else {
+                self.spi.transfer(data.unwrap_or(idle))
+            };
+
+            for n in 0..8 {
+                let pos = if lsb { n } else { 7 - n };
+
+                if self.clock_bit(data.map(|d| d & (1 << pos) != 0)) {
+                    byte |= 1 << pos;
+                }
+            }
+
+            if let Some(miso) = miso {
+                byte = miso;
+            }
+
+            if read {
+                self.response.push_back(byte);
+            }
+        }
+    }
+
+    // TMS bits on AD3, LSB first, with DO held at the TDI level and DI
+    // sampled like by the bit mode commands
+    fn clock_tms(&mut self, cmd: &[u8]) {
+        let bits = cmd[1] + 1;
+        let tdi = cmd[2] & 0x80 != 0;
+        let mut byte: u8 = 0;
+
+        for n in 0..bits {
+            if cmd[2] & (1 << n) != 0 {
+                self.lower.value |= PIN_TMS;
+            } else {
+                self.lower.value &= !PIN_TMS;
+            }
+
+            let bit = self.clock_bit(Some(tdi));
+            byte = (byte >> 1) | ((bit as u8) << 7);
+        }
+
+        if cmd[0] & CMD_READ_TDO != 0 {
+            self.response.push_back(byte);
+        }
+    }
+
+    // SK pulses with DO left as it is and DI ignored
+    fn clock_no_data(&mut self, bits: usize) {
+        for _ in 0..bits {
+            self.clock_bit(None);
+        }
+    }
+
+    // One SK pulse: drive DO if requested and sample DI
+    fn clock_bit(&mut self, out: Option<bool>) -> bool {
+        if let Some(bit) = out {
+            if bit {
+                self.lower.value |= PIN_DO;
+            } else {
+                self.lower.value &= !PIN_DO;
+            }
+        }
+
+        if self.loopback {
+            return self.lower.level() & PIN_DO != 0;
+        }
+
+        if self.i2c.is_active() {
+            let sda = self.lower_level() & PIN_DO != 0;
+            return self.i2c.clock(sda);
+        }
+
+        self.lower.level() & PIN_DI != 0
+    }
+}
+
+fn is_data_cmd(op: u8) -> bool {
+    op & 0xC0 == 0 && op & (CMD_WRITE_TDI | CMD_READ_TDO) != 0
+}
+
+// TMS clocking opcodes: 0x4A/0x4B out, 0x6A/0x6B/0x6E/0x6F with TDO read
+fn is_tms_cmd(op: u8) -> bool {
+    matches!(op, 0x4A | 0x4B | 0x6A | 0x6B | 0x6E | 0x6F)
+}
+
+/// Simulated FTDI chip operated in MPSSE mode.
+///
+/// Unknown opcodes are answered with `0xFA` followed by the opcode, the same
+/// way the real MPSSE reports bad commands. Reading more bytes than the
+/// processed commands produced fails with a timeout error instead of
+/// blocking.
+#[derive(Clone, Default)]
+pub struct MpsseEmulator {
+    chip: Arc<Mutex<Chip>>,
+    // connection the handle was opened on, see `plug`
+    generation: u32,
+}
+
+impl MpsseEmulator {
+    /// Create an emulated FT232H.
+    pub fn new() -> MpsseEmulator {
+        MpsseEmulator::default()
+    }
+
+    /// Create an emulated chip of the given type: commands for missing
+    /// features, e.g. the upper GPIO byte of FT4232H, are rejected as bad
+    /// commands.
+    pub fn with_chip(chip_type: ChipType) -> MpsseEmulator {
+        let emu = MpsseEmulator::default();
+        emu.chip().chip_type = chip_type;
+        emu
+    }
+
+    fn chip(&self) -> MutexGuard<'_, Chip> {
+        match self.chip.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    // Chip state for transport requests, which fail if the chip has been
+    // unplugged since the handle was opened
+    fn device(&self) -> Result<MutexGuard<'_, Chip>> {
+        let chip = self.chip();
+
+        if !chip.connected || chip.generation != self.generation {
+            return Err(X232Error::HAL(ErrorKind::Disconnected));
+        }
+
+        Ok(chip)
+    }
+
+    /// Simulate USB cable removal: all the transport requests fail with
+    /// `Disconnected` error.
+    pub fn unplug(&self) {
+        self.chip().connected = false;
+    }
+
+    /// Simulate USB cable insertion: the chip is back in the power-on state
+    /// and has to be reopened with [`MpsseTransport::reopen`].
+    pub fn plug(&self) {
+        let mut chip = self.chip();
+
+        chip.power_on();
+        chip.connected = true;
+        chip.generation += 1;
+    }
+
+    /// Whether the chip has been switched to MPSSE mode.
+    pub fn is_mpsse(&self) -> bool {
+        self.chip().mode == BitMode::Mpsse
+    }
+
+    /// Whether the interface is still held, i.e. not released by
+    /// [`MpsseTransport::close`].
+    pub fn is_claimed(&self) -> bool {
+        self.chip().claimed
+    }
+
+    /// Attach a virtual device to the emulated I2C bus.
+    pub fn add_i2c_target<D: I2cTarget + 'static>(&self, target: D) {
+        self.chip().i2c.add_target(Box::new(target));
+    }
+
+    /// Attach a virtual device to the emulated SPI bus, selected by driving
+    /// pin `bit` of `bank` low.
+    pub fn add_spi_target<D: SpiTarget + 'static>(&self, bank: PinBank, bit: u8, target: D) {
+        self.chip().spi.add_target(bank, bit, Box::new(target));
+    }
+
+    /// Current levels of the lower byte pins.
+    pub fn gpio_lower(&self) -> u8 {
+        self.chip().lower_level()
+    }
+
+    /// Direction of the lower byte pins, `1` is output.
+    pub fn gpio_lower_direction(&self) -> u8 {
+        self.chip().lower.direction
+    }
+
+    /// Current levels of the upper byte pins.
+    pub fn gpio_upper(&self) -> u8 {
+        self.chip().upper.level()
+    }
+
+    /// Direction of the upper byte pins, `1` is output.
+    pub fn gpio_upper_direction(&self) -> u8 {
+        self.chip().upper.direction
+    }
+
+    /// Set levels applied from outside to the lower byte input pins.
+    pub fn set_input_lower(&self, levels: u8) {
+        self.chip().lower.input = levels;
+    }
+
+    /// Set levels applied from outside to the upper byte input pins.
+    pub fn set_input_upper(&self, levels: u8) {
+        self.chip().upper.input = levels;
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        self.chip().loopback
+    }
+
+    pub fn is_3phase_clocking(&self) -> bool {
+        self.chip().three_phase
+    }
+
+    pub fn is_adaptive_clocking(&self) -> bool {
+        self.chip().adaptive
+    }
+
+    /// Drive-only-zero masks of the lower and upper byte pins.
+    pub fn drive_only_zero(&self) -> (u8, u8) {
+        let chip = self.chip();
+        (chip.lower.drive_zero, chip.upper.drive_zero)
+    }
+
+    /// Latency timer in milliseconds.
+    pub fn latency_timer(&self) -> u8 {
+        self.chip().latency
+    }
+
+    /// USB IN transfer size set by the host.
+    pub fn transfer_size(&self) -> u32 {
+        self.chip().transfer_size
+    }
+
+    /// Read and write timeouts set by the host.
+    pub fn timeouts(&self) -> (Duration, Duration) {
+        self.chip().timeouts
+    }
+
+    /// SK frequency in Hz resulting from the clock divisor settings.
+    pub fn clock_frequency(&self) -> u32 {
+        let chip = self.chip();
+        let base: u32 = if chip.divide_by_5 {
+            12_000_000
+        } else {
+            chip.chip_type.capabilities().base_clock
+        };
+
+        base / ((1 + chip.divisor as u32) * 2)
+    }
+}
+
+impl MpsseTransport for MpsseEmulator {
+    fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        let mut chip = self.device()?;
+
+        // outside of MPSSE mode the data would go to UART/bit-bang logic
+        if chip.mode != BitMode::Mpsse {
+            return Ok(());
+        }
+
+        chip.pending.extend_from_slice(data);
+        chip.process();
+
+        Ok(())
+    }
+
+    fn read_exact(&mut self, data: &mut [u8]) -> Result<()> {
+        let mut chip = self.device()?;
+
+        if chip.response.len() < data.len() {
+            return Err(X232Error::Io(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "not enough MPSSE response data",
+            )));
+        }
+
+        for b in data.iter_mut() {
+            *b = chip.response.pop_front().unwrap();
+        }
+
+        Ok(())
+    }
+
+    fn purge(&mut self) -> Result<()> {
+        let mut chip = self.device()?;
+
+        chip.pending.clear();
+        chip.response.clear();
+
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.device()?.power_on();
+
+        Ok(())
+    }
+
+    fn set_latency_timer(&mut self, value: u8) -> Result<()> {
+        self.device()?.latency = value;
+
+        Ok(())
+    }
+
+    fn set_bitmode(&mut self, mask: u8, mode: BitMode) -> Result<()> {
+        let mut chip = self.device()?;
+
+        chip.mode = mode;
+        chip.lower.direction = mask;
+        chip.upper.direction = 0;
+        chip.pending.clear();
+
+        Ok(())
+    }
+
+    fn set_transfer_size(&mut self, in_size: u32) -> Result<()> {
+        self.device()?.transfer_size = in_size;
+
+        Ok(())
+    }
+
+    fn set_timeouts(&mut self, read: Duration, write: Duration) -> Result<()> {
+        self.device()?.timeouts = (read, write);
+
+        Ok(())
+    }
+
+    fn chip_type(&mut self) -> Option<ChipType> {
+        Some(self.chip().chip_type)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.device()?.claimed = false;
+
+        Ok(())
+    }
+
+    fn reopen(&mut self) -> Result<()> {
+        let (connected, generation) = {
+            let chip = self.chip();
+            (chip.connected, chip.generation)
+        };
+
+        if !connected {
+            return Err(X232Error::HAL(ErrorKind::Disconnected));
+        }
+
+        self.generation = generation;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::ftdimpsse::{
+        ClockBits, ClockBitsIn, ClockBitsOut, ClockData, ClockDataOut, ClockTMS, ClockTMSOut,
+        MpsseCmdBuilder, MpsseSettings,
+    };
+    use crate::gpio::{GpioPin, PinBank, PinState};
+    use crate::i2c::{I2cBus, I2cSpeed};
+    use crate::spi::{SpiBus, SpiSpeed};
+    use crate::x232h::{Device, FTx232H};
+    use embedded_hal::blocking::i2c::WriteRead;
+    use embedded_hal::blocking::spi::Transfer;
+    use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+    fn emulator() -> MpsseEmulator {
+        let mut emu = MpsseEmulator::new();
+        emu.set_bitmode(0, BitMode::Mpsse).unwrap();
+        emu
+    }
+
+    #[test]
+    fn test_gpio() {
+        let mut emu = emulator();
+        let mut buf = [0; 2];
+
+        emu.set_input_lower(0b0000_0100);
+        emu.set_input_upper(0b1010_0000);
+
+        let cmd = MpsseCmdBuilder::new()
+            .set_gpio_lower(0b1111_0001, 0b0000_1011)
+            .set_gpio_upper(0b0000_0101, 0b0000_1111)
+            .gpio_lower()
+            .gpio_upper()
+            .send_immediate();
+
+        emu.write_all(cmd.as_slice()).unwrap();
+        emu.read_exact(&mut buf).unwrap();
+
+        assert_eq!(buf, [0b0000_0101, 0b1010_0101]);
+        assert_eq!(emu.gpio_lower_direction(), 0b0000_1011);
+        assert_eq!(emu.gpio_upper_direction(), 0b0000_1111);
+        assert!(emu.read_exact(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_gpio_input() {
+        let emu = emulator();
+        let dev = FTx232H::with_transport(emu.clone()).unwrap();
+
+        let mut pl2 = dev.pl2().unwrap();
+        let mut ph5 = dev.ph5().unwrap();
+        let mut ph6 = dev.ph6().unwrap();
+
+        pl2.set_input().unwrap();
+        ph5.set_input().unwrap();
+        ph6.set_high().unwrap();
+        assert_eq!(emu.gpio_lower_direction(), 0x0);
+        assert_eq!(emu.gpio_upper_direction(), 0b0100_0000);
+
+        emu.set_input_lower(0b0100_0000);
+        assert!(pl2.is_high().unwrap());
+        assert!(ph5.is_low().unwrap());
+
+        emu.set_input_upper(0b0010_0000);
+        assert!(ph5.is_high().unwrap());
+
+        // writing to other pins keeps inputs
+        ph6.set_low().unwrap();
+        assert_eq!(emu.gpio_upper_direction(), 0b0100_0000);
+        assert!(ph5.is_high().unwrap());
+
+        // and output mode is back on write
+        ph5.set_low().unwrap();
+        assert_eq!(emu.gpio_upper_direction(), 0b0110_0000);
+        assert!(ph5.is_low().unwrap());
+    }
+
+    #[test]
+    fn test_loopback() {
+        let mut emu = emulator();
+        let mut buf = [0; 5];
+
+        let cmd = MpsseCmdBuilder::new()
+            .set_gpio_lower(0x0, 0b0000_1011)
+            .enable_loopback()
+            .clock_data(ClockData::MsbPosIn, &[0x12, 0x34, 0x56])
+            .clock_bits(ClockBits::MsbPosIn, 0b1010_0000, 3)
+            .clock_bits(ClockBits::LsbPosIn, 0b0000_0101, 3)
+            .send_immediate();
+
+        emu.write_all(cmd.as_slice()).unwrap();
+        emu.read_exact(&mut buf).unwrap();
+
+        assert_eq!(buf, [0x12, 0x34, 0x56, 0b0000_0101, 0b1010_0000]);
+        assert!(emu.is_loopback());
+    }
+
+    #[test]
+    fn test_bits_in() {
+        let mut emu = emulator();
+        let mut buf = [0; 2];
+
+        emu.set_input_lower(PIN_DI);
+
+        let cmd = MpsseCmdBuilder::new()
+            .clock_bits_out(ClockBitsOut::MsbNeg, 0xff, 8)
+            .clock_bits_in(ClockBitsIn::MsbPos, 1)
+            .clock_bits_in(ClockBitsIn::LsbPos, 1);
+
+        emu.write_all(cmd.as_slice()).unwrap();
+        emu.read_exact(&mut buf).unwrap();
+
+        assert_eq!(buf, [0b0000_0001, 0b1000_0000]);
+    }
+
+    #[test]
+    fn test_an108_commands() {
+        let emu = emulator();
+        let dev = FTx232H::with_transport(emu.clone()).unwrap();
+        let lower = (emu.gpio_lower(), emu.gpio_lower_direction());
+        let clock = emu.clock_frequency();
+
+        // arguments look like GPIO, clock and drive-only-zero opcodes
+        let cmds = [
+            MpsseCmdBuilder::new().clock_tms_out(ClockTMSOut::NegEdge, 0x1f, true, 5),
+            MpsseCmdBuilder::new().clock_tms_out(ClockTMSOut::PosEdge, 0x06, false, 7),
+            MpsseCmdBuilder::new().clock_tms(ClockTMS::PosTMSPosTDO, 0x06, true, 7),
+            MpsseCmdBuilder::new().clock_tms(ClockTMS::NegTMSNegTDO, 0x1e, false, 3),
+            MpsseCmdBuilder::new().clock_bits_no_data(8),
+            MpsseCmdBuilder::new().clock_bytes_no_data(4),
+            MpsseCmdBuilder::new().clock_bytes_no_data(0x9e82 + 1),
+            MpsseCmdBuilder::new().clock_bytes_or_io_high(0x86 + 1),
+            MpsseCmdBuilder::new().clock_bytes_or_io_low(2),
+            MpsseCmdBuilder::new().clock_until_io_high(),
+            MpsseCmdBuilder::new().clock_until_io_low(),
+            MpsseCmdBuilder::new().cpu_read_short(0x80),
+            MpsseCmdBuilder::new().cpu_read_extended(0x8286),
+            MpsseCmdBuilder::new().cpu_write_short(0x82, 0x86),
+            MpsseCmdBuilder::new().cpu_write_extended(0x5678, 0xdd),
+            MpsseCmdBuilder::new().cpu_write_extended(0x9e80, 0x82),
+        ];
+
+        for cmd in cmds {
+            let cmd = cmd.send_immediate();
+            let mut data = vec![0; cmd.disassemble().response_len];
+
+            let lock = dev.context().lock().unwrap();
+            let mut ctx = lock.borrow_mut();
+            ctx.write_all(cmd.as_slice()).unwrap();
+            ctx.read_exact(&mut data).unwrap();
+
+            // nothing is left over and no argument ran as a command
+            assert!(
+                ctx.read_exact(&mut [0; 1]).is_err(),
+                "{}",
+                cmd.disassemble()
+            );
+            assert_eq!((emu.gpio_lower(), emu.gpio_lower_direction()), lower);
+            assert_eq!(emu.clock_frequency(), clock);
+            assert_eq!(emu.drive_only_zero(), (0x0, 0x0));
+        }
+    }
+
+    #[test]
+    fn test_tms_read() {
+        let mut emu = emulator();
+        let mut buf = [0; 2];
+
+        emu.set_input_lower(PIN_DI);
+
+        let cmd = MpsseCmdBuilder::new()
+            .clock_tms(ClockTMS::PosTMSPosTDO, 0x1, true, 3)
+            .cpu_read_short(0x10);
+
+        emu.write_all(cmd.as_slice()).unwrap();
+        emu.read_exact(&mut buf).unwrap();
+
+        // TDO bits come in LSB first from the top, the CPU bus floats
+        assert_eq!(buf, [0b1110_0000, 0xff]);
+        assert!(emu.read_exact(&mut [0; 1]).is_err());
+    }
+
+    #[test]
+    fn test_clock() {
+        let mut emu = emulator();
+
+        let cmd = MpsseCmdBuilder::with_vec(vec![
+            MpsseCmd::DisableClockDivide.into(),
+            MpsseCmd::SetClockFrequency.into(),
+            0x1d,
+            0x0,
+        ]);
+
+        emu.write_all(cmd.as_slice()).unwrap();
+
+        assert_eq!(emu.clock_frequency(), 1_000_000);
+    }
+
+    #[test]
+    fn test_bad_command() {
+        let mut emu = emulator();
+        let mut buf = [0; 2];
+
+        // commands may be split across several writes
+        emu.write_all(&[MpsseCmd::SetDataBitsLowbyte.into(), 0xff])
+            .unwrap();
+        emu.write_all(&[0xff, 0xaa]).unwrap();
+        emu.read_exact(&mut buf).unwrap();
+
+        assert_eq!(buf, [0xfa, 0xaa]);
+        assert_eq!(emu.gpio_lower(), 0xff);
+    }
+
+    #[test]
+    fn test_ft4232h() {
+        let mut emu = MpsseEmulator::with_chip(ChipType::FT4232H);
+        let mut buf = [0; 2];
+
+        // chip type survives reset
+        emu.reset().unwrap();
+        emu.set_bitmode(0, BitMode::Mpsse).unwrap();
+        assert_eq!(emu.chip_type(), Some(ChipType::FT4232H));
+
+        // no upper byte on FT4232H
+        let cmd = MpsseCmdBuilder::new().set_gpio_upper(0xff, 0xff);
+        emu.write_all(cmd.as_slice()).unwrap();
+        emu.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0xfa, 0x82]);
+        assert_eq!(emu.gpio_upper(), 0x00);
+    }
+
+    #[test]
+    fn test_ft2232c() {
+        let mut emu = MpsseEmulator::with_chip(ChipType::FT2232C);
+        let dev = FTx232H::with_transport(emu.clone()).unwrap();
+
+        // hi-speed only commands are not sent on init: no bad command responses
+        assert!(emu.read_exact(&mut [0; 1]).is_err());
+
+        let _spi = dev.spi(SpiSpeed::CLK_1MHz).unwrap();
+        assert_eq!(emu.clock_frequency(), 1_000_000);
+
+        assert!(matches!(
+            dev.set_3phase_clocking(true),
+            Err(X232Error::HAL(ErrorKind::FeatureNotSupported))
+        ));
+        assert!(!emu.is_3phase_clocking());
+    }
+
+    // Chip that does not report its type and may have been left in the
+    // middle of a data command by a previous user
+    struct Unknown {
+        emu: MpsseEmulator,
+        stuck: bool,
+    }
+
+    impl MpsseTransport for Unknown {
+        fn write_all(&mut self, data: &[u8]) -> Result<()> {
+            self.emu.write_all(data)
+        }
+
+        fn read_exact(&mut self, data: &mut [u8]) -> Result<()> {
+            self.emu.read_exact(data)
+        }
+
+        // USB buffers are purged, the engine still waits for 16 data bytes
+        fn purge(&mut self) -> Result<()> {
+            self.emu.purge()?;
+
+            if self.stuck {
+                self.emu
+                    .write_all(&[ClockDataOut::MsbNeg.into(), 0x0f, 0x00])?;
+            }
+
+            Ok(())
+        }
+
+        fn reset(&mut self) -> Result<()> {
+            self.emu.reset()
+        }
+
+        fn set_latency_timer(&mut self, value: u8) -> Result<()> {
+            self.emu.set_latency_timer(value)
+        }
+
+        fn set_bitmode(&mut self, mask: u8, mode: BitMode) -> Result<()> {
+            self.emu.set_bitmode(mask, mode)
+        }
+    }
+
+    #[test]
+    fn test_sync() {
+        let emu = emulator();
+        let dev = FTx232H::with_transport(Unknown {
+            emu: emu.clone(),
+            stuck: true,
+        });
+
+        assert!(matches!(dev, Err(X232Error::HAL(ErrorKind::SyncFailed))));
+        assert_eq!(emu.gpio_lower_direction(), 0x0);
+
+        // the handshake answer is consumed by init
+        let mut emu = emulator();
+        let _dev = FTx232H::with_transport(emu.clone()).unwrap();
+        assert!(emu.read_exact(&mut [0; 1]).is_err());
+    }
+
+    #[test]
+    fn test_bad_command_echo() {
+        // taken for FT232H, the upper byte commands are rejected
+        let emu = MpsseEmulator::with_chip(ChipType::FT4232H);
+        let mut dev = FTx232H::with_transport(Unknown {
+            emu: emu.clone(),
+            stuck: false,
+        })
+        .unwrap();
+
+        let ph0 = dev.ph0().unwrap();
+        assert!(matches!(
+            ph0.is_high(),
+            Err(X232Error::HAL(ErrorKind::BadCommand(0x83)))
+        ));
+        drop(ph0);
+
+        // the responses are in sync again
+        let pl2 = dev.pl2().unwrap();
+        emu.set_input_lower(0b0100_0000);
+        assert!(pl2.is_high().unwrap());
+        drop(pl2);
+
+        // data looking like an echo is not taken for one
+        dev.loopback(true).unwrap();
+        let mut spi = dev.spi(SpiSpeed::CLK_1MHz).unwrap();
+        let mut data = [0xfa, ClockData::MsbNegIn.into(), 0xfa, 0xaa];
+        assert_eq!(
+            spi.transfer(&mut data).unwrap(),
+            [0xfa, ClockData::MsbNegIn.into(), 0xfa, 0xaa]
+        );
+    }
+
+    #[test]
+    fn test_drive_only_zero() {
+        let mut emu = emulator();
+        let dev = FTx232H::with_transport(emu.clone()).unwrap();
+
+        dev.set_drive_only_zero(0b0000_0011, 0x0).unwrap();
+        assert_eq!(emu.drive_only_zero(), (0b0000_0011, 0x0));
+
+        // outputs set high are released and read external levels
+        let cmd = MpsseCmdBuilder::new().set_gpio_lower(0b0000_0111, 0xff);
+        emu.write_all(cmd.as_slice()).unwrap();
+        emu.set_input_lower(0b0000_0001);
+        assert_eq!(emu.gpio_lower() & 0b0000_0111, 0b0000_0101);
+
+        // FT2232H has no drive-only-zero
+        let emu = MpsseEmulator::with_chip(ChipType::FT2232H);
+        let dev = FTx232H::with_transport(emu.clone()).unwrap();
+
+        assert!(matches!(
+            dev.set_drive_only_zero(0xff, 0xff),
+            Err(X232Error::HAL(ErrorKind::FeatureNotSupported))
+        ));
+        assert_eq!(emu.drive_only_zero(), (0x0, 0x0));
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let emu = emulator();
+        let mut dev = FTx232H::with_transport(emu.clone()).unwrap();
+
+        dev.loopback(true).unwrap();
+
+        let mut spi = dev.spi(SpiSpeed::CLK_5MHz).unwrap();
+        let mut pl1 = dev.pl1().unwrap();
+        let mut ph2 = dev.ph2().unwrap();
+        pl1.set_high().unwrap();
+        ph2.set_high().unwrap();
+
+        emu.unplug();
+
+        assert!(matches!(
+            spi.transfer(&mut [0x55]),
+            Err(X232Error::HAL(ErrorKind::Disconnected))
+        ));
+
+        assert!(matches!(
+            dev.reconnect(),
+            Err(X232Error::HAL(ErrorKind::Disconnected))
+        ));
+
+        // the chip is back in the power-on state, the old handle is stale
+        emu.plug();
+        assert!(!emu.is_mpsse());
+        assert!(spi.transfer(&mut [0x55]).is_err());
+
+        dev.reconnect().unwrap();
+
+        assert!(emu.is_mpsse());
+        assert!(emu.is_loopback());
+        assert_eq!(emu.clock_frequency(), 5_000_000);
+        assert_eq!(emu.gpio_lower_direction(), 0b0010_0011);
+        assert_eq!(emu.gpio_lower() & 0b1111_0000, 0b0010_0000);
+        assert_eq!(emu.gpio_upper(), 0b0000_0100);
+
+        // existing handles keep working
+        assert_eq!(spi.transfer(&mut [0x55, 0xaa]).unwrap(), [0x55, 0xaa]);
+        pl1.set_low().unwrap();
+        assert_eq!(emu.gpio_lower() & 0b1111_0000, 0x0);
+    }
+
+    #[test]
+    fn test_reconnect_i2c() {
+        let emu = emulator();
+        emu.add_i2c_target(i2c::Lm75::with_default_address(21.0));
+        let dev = FTx232H::with_transport(emu.clone()).unwrap();
+        let mut i2c = dev.i2c(I2cSpeed::CLK_400kHz).unwrap();
+        let mut buf = [0; 2];
+
+        i2c.write_read(0x48, &[0x0], &mut buf).unwrap();
+        let lower = (emu.gpio_lower_direction(), emu.gpio_lower());
+
+        emu.unplug();
+        emu.plug();
+        dev.reconnect().unwrap();
+
+        // the lower byte is restored as the last I2C transfer left it
+        assert_eq!((emu.gpio_lower_direction(), emu.gpio_lower()), lower);
+
+        i2c.write_read(0x48, &[0x0], &mut buf).unwrap();
+        assert_eq!(buf, [21, 0]);
+    }
+
+    #[test]
+    fn test_bus_pins() {
+        // I2C leaves AD3 and the GPIO inputs alone
+        let emu = emulator();
+        emu.add_i2c_target(i2c::Lm75::with_default_address(21.0));
+        let dev = FTx232H::with_transport(emu.clone()).unwrap();
+        let mut pl0 = dev.pl0().unwrap();
+        pl0.set_high().unwrap();
+        pl0.set_input().unwrap();
+        let mut i2c = dev.i2c(I2cSpeed::CLK_400kHz).unwrap();
+        let mut buf = [0; 2];
+
+        i2c.write_read(0x48, &[0x0], &mut buf).unwrap();
+        assert_eq!(emu.gpio_lower_direction() & 0b1111_1000, 0x0);
+
+        // SPI drives AD3 only if it is set up as chip select
+        let emu = emulator();
+        let dev = FTx232H::builder()
+            .pin(PinBank::Low, 3, PinState::High)
+            .open_transport(emu.clone())
+            .unwrap();
+        let mut spi = dev.spi(SpiSpeed::CLK_1MHz).unwrap();
+
+        spi.transfer(&mut [0x55]).unwrap();
+        assert_eq!(emu.gpio_lower_direction(), 0b0000_1011);
+        assert_eq!(emu.gpio_lower() & 0b1000, 0b1000);
+    }
+
+    #[test]
+    fn test_bus_clock() {
+        let emu = emulator();
+        let dev = FTx232H::with_transport(emu.clone()).unwrap();
+        assert_eq!(dev.clock_frequency(), None);
+
+        // the closest lower frequency: 60MHz / 14
+        dev.spi(SpiSpeed::Hz(4_500_000)).unwrap();
+        assert_eq!(emu.clock_frequency(), 4_285_714);
+        assert_eq!(dev.clock_frequency(), Some(4_285_714));
+
+        assert!(dev.spi(SpiSpeed::Hz(4_500_000)).is_ok());
+        assert!(dev.spi(SpiSpeed::CLK_AUTO).is_ok());
+        assert!(dev.spi(SpiSpeed::CLK_5MHz).is_err());
+
+        // fixed speeds are the same as their frequencies
+        let dev = FTx232H::with_transport(emulator()).unwrap();
+        dev.i2c(I2cSpeed::CLK_400kHz).unwrap();
+        assert!(dev.i2c(I2cSpeed::Hz(400_000)).is_ok());
+
+        // slow clocks need the divide-by-5 prescaler
+        let emu = emulator();
+        let dev = FTx232H::with_transport(emu.clone()).unwrap();
+        dev.i2c(I2cSpeed::Hz(200)).unwrap();
+        assert_eq!(emu.clock_frequency(), 200);
+
+        let dev = FTx232H::with_transport(emulator()).unwrap();
+        for hz in [0, 50, 30_000_001] {
+            assert!(matches!(
+                dev.spi(SpiSpeed::Hz(hz)),
+                Err(X232Error::HAL(ErrorKind::InvalidClock))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_close() {
+        let emu = emulator();
+        let dev = FTx232H::with_transport(emu.clone()).unwrap();
+
+        dev.pl1().unwrap().set_high().unwrap();
+        dev.ph3().unwrap().set_low().unwrap();
+        dev.spi(SpiSpeed::CLK_1MHz).unwrap();
+        assert_eq!(emu.gpio_lower_direction(), 0b0010_0011);
+
+        // all the pins are released along with the chip
+        dev.close().unwrap();
+        assert!(!emu.is_mpsse());
+        assert!(!emu.is_claimed());
+        assert_eq!(emu.gpio_lower_direction(), 0x0);
+        assert_eq!(emu.gpio_upper_direction(), 0x0);
+    }
+
+    #[test]
+    fn test_drop() {
+        let emu = emulator();
+        let dev = FTx232H::with_transport(emu.clone()).unwrap();
+        dev.ph0().unwrap().set_high().unwrap();
+        drop(dev);
+
+        assert!(!emu.is_mpsse());
+        assert!(!emu.is_claimed());
+        assert_eq!(emu.gpio_upper_direction(), 0x0);
+
+        // errors are ignored on drop and reported by close
+        let emu = emulator();
+        let dev = FTx232H::with_transport(emu.clone()).unwrap();
+        emu.unplug();
+        drop(dev);
+
+        let emu = emulator();
+        let dev = FTx232H::with_transport(emu.clone()).unwrap();
+        emu.unplug();
+
+        assert!(matches!(
+            dev.close(),
+            Err(X232Error::HAL(ErrorKind::Disconnected))
+        ));
+    }
+
+    #[test]
+    fn test_threads() {
+        fn assert_send<S: Send>() {}
+        fn assert_sync<S: Sync>() {}
+
+        assert_sync::<FTx232H<Device>>();
+        assert_send::<SpiBus<Device>>();
+        assert_send::<I2cBus<Device>>();
+        assert_send::<GpioPin<Device>>();
+
+        let emu = emulator();
+        emu.add_i2c_target(i2c::Lm75::with_default_address(21.0));
+        let dev = FTx232H::with_transport(emu.clone()).unwrap();
+
+        // sensor polling and GPIO on the same adapter at the same time
+        std::thread::scope(|s| {
+            let mut i2c = dev.i2c(I2cSpeed::CLK_400kHz).unwrap();
+            let mut ph0 = dev.ph0().unwrap();
+
+            s.spawn(move || {
+                for _ in 0..20 {
+                    let mut buf = [0; 2];
+                    i2c.write_read(0x48, &[0x0], &mut buf).unwrap();
+                    assert_eq!(buf, [21, 0]);
+                }
+            });
+
+            s.spawn(move || {
+                for _ in 0..20 {
+                    ph0.set_high().unwrap();
+                    ph0.set_low().unwrap();
+                }
+            });
+
+            // pins are claimed once whatever thread asks
+            let claimed: Vec<_> = (0..4)
+                .map(|_| s.spawn(|| dev.ph1().is_ok()))
+                .map(|t| t.join().unwrap())
+                .collect();
+            assert_eq!(claimed.iter().filter(|ok| **ok).count(), 1);
+        });
+
+        assert_eq!(emu.gpio_upper() & 0x1, 0x0);
+    }
+
+    #[test]
+    fn test_settings() {
+        let mut emu = emulator();
+        let settings = MpsseSettings {
+            reset: false,
+            in_transfer_size: 8192,
+            read_timeout: Duration::from_millis(200),
+            write_timeout: Duration::from_millis(300),
+            latency_timer: Duration::from_millis(2),
+            mask: 0b0000_1011,
+            clock_frequency: Some(3_000_000),
+        };
+
+        // survives init only without reset
+        let cmd = MpsseCmdBuilder::new().enable_drive_only_zero(0x3, 0x0);
+        emu.write_all(cmd.as_slice()).unwrap();
+
+        let dev = FTx232H::with_transport_settings(emu.clone(), settings).unwrap();
+
+        assert!(emu.is_mpsse());
+        assert_eq!(emu.drive_only_zero(), (0x3, 0x0));
+        assert_eq!(emu.transfer_size(), 8192);
+        assert_eq!(
+            emu.timeouts(),
+            (Duration::from_millis(200), Duration::from_millis(300))
+        );
+        assert_eq!(emu.latency_timer(), 2);
+        assert_eq!(emu.clock_frequency(), 3_000_000);
+
+        // the same settings are applied on reconnect
+        emu.unplug();
+        emu.plug();
+        dev.reconnect().unwrap();
+        assert_eq!(emu.latency_timer(), 2);
+        assert_eq!(emu.clock_frequency(), 3_000_000);
+    }
+
+    #[test]
+    fn test_invalid_settings() {
+        let valid = MpsseSettings::default();
+
+        for (settings, kind) in [
+            (
+                MpsseSettings {
+                    latency_timer: Duration::from_millis(0),
+                    ..valid
+                },
+                ErrorKind::InvalidParams,
+            ),
+            (
+                MpsseSettings {
+                    latency_timer: Duration::from_millis(256),
+                    ..valid
+                },
+                ErrorKind::InvalidParams,
+            ),
+            (
+                MpsseSettings {
+                    in_transfer_size: 100,
+                    ..valid
+                },
+                ErrorKind::InvalidParams,
+            ),
+            (
+                MpsseSettings {
+                    read_timeout: Duration::from_secs(0),
+                    ..valid
+                },
+                ErrorKind::InvalidParams,
+            ),
+            (
+                MpsseSettings {
+                    clock_frequency: Some(40_000_000),
+                    ..valid
+                },
+                ErrorKind::InvalidClock,
+            ),
+        ] {
+            // nothing is sent to the chip
+            let emu = MpsseEmulator::new();
+
+            assert!(
+                matches!(FTx232H::with_transport_settings(emu.clone(), settings), Err(X232Error::HAL(k)) if k == kind)
+            );
+            assert!(!emu.is_mpsse());
+            assert_eq!(emu.transfer_size(), 4096);
+        }
+
+        let emu = MpsseEmulator::new();
+        FTx232H::with_transport_settings(emu.clone(), valid).unwrap();
+        assert_eq!(emu.latency_timer(), 16);
+    }
+}
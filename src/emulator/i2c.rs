@@ -0,0 +1,422 @@
+//! Virtual I2C targets for [`MpsseEmulator`].
+//!
+//! The emulator models the usual FTDI I2C wiring: SCL on AD0 and SDA on AD1
+//! (DO) tied to AD2 (DI), both lines pulled up. The waveform produced by
+//! [`I2cBus`] is decoded bit by bit: START/STOP conditions are detected from
+//! SDA edges while SCL is high, every clocked bit is one SCL pulse. Address
+//! and data bytes are routed to the matching [`I2cTarget`], which provides
+//! ACK/NACK and read data that the emulator drives back on SDA.
+//!
+//! [`MpsseEmulator`]: super::MpsseEmulator
+//! [`I2cBus`]: crate::i2c::I2cBus
+
+/// Byte-level model of an I2C device attached to the emulated bus.
+pub trait I2cTarget: Send {
+    /// Whether the target responds to the 7-bit `address`.
+    fn matches(&self, address: u8) -> bool;
+
+    /// START or repeated START addressed to the target.
+    fn start(&mut self, _address: u8, _read: bool) {}
+
+    /// Byte written by the controller, return `true` to ACK it.
+    fn write(&mut self, byte: u8) -> bool;
+
+    /// Next byte to be read by the controller.
+    fn read(&mut self) -> u8;
+
+    /// STOP condition ending a transfer addressed to the target.
+    fn stop(&mut self) {}
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Phase {
+    Idle,
+    Address,
+    TargetAck(bool),
+    Write,
+    Read(u8),
+    ControllerAck,
+}
+
+pub(super) struct I2cWire {
+    targets: Vec<Box<dyn I2cTarget>>,
+    active: Option<usize>,
+    read: bool,
+    phase: Phase,
+    shift: u8,
+    bits: u8,
+    scl: bool,
+    sda: bool,
+}
+
+impl I2cWire {
+    pub(super) fn new() -> I2cWire {
+        I2cWire {
+            targets: Vec::new(),
+            active: None,
+            read: false,
+            phase: Phase::Idle,
+            shift: 0,
+            bits: 0,
+            scl: true,
+            sda: true,
+        }
+    }
+
+    pub(super) fn add_target(&mut self, target: Box<dyn I2cTarget>) {
+        self.targets.push(target);
+    }
+
+    pub(super) fn is_active(&self) -> bool {
+        !self.targets.is_empty()
+    }
+
+    /// Line levels set by the controller outside of clocking commands.
+    pub(super) fn lines(&mut self, scl: bool, sda: bool) {
+        if self.scl && scl && self.sda && !sda {
+            self.start_condition();
+        }
+
+        if self.scl && scl && !self.sda && sda {
+            self.stop_condition();
+        }
+
+        self.scl = scl;
+        self.sda = sda;
+    }
+
+    /// One SCL pulse, `sda` is the level released or driven by the
+    /// controller. Returns the SDA level sampled by the controller.
+    pub(super) fn clock(&mut self, sda: bool) -> bool {
+        let line = match self.phase {
+            Phase::Idle | Phase::Address | Phase::Write | Phase::ControllerAck => sda,
+            Phase::TargetAck(ack) => sda && !ack,
+            Phase::Read(byte) => sda && (byte & (0x80 >> self.bits) != 0),
+        };
+
+        match self.phase {
+            Phase::Idle => {}
+            Phase::Address => {
+                if self.shift_in(line) {
+                    let address = self.shift >> 1;
+
+                    self.read = self.shift & 0x1 == 0x1;
+                    self.active = self.targets.iter().position(|t| t.matches(address));
+
+                    if let Some(i) = self.active {
+                        self.targets[i].start(address, self.read);
+                    }
+
+                    self.phase = Phase::TargetAck(self.active.is_some());
+                }
+            }
+            Phase::TargetAck(ack) => {
+                self.phase = if !ack {
+                    Phase::Idle
+                } else if self.read {
+                    self.next_read()
+                } else {
+                    Phase::Write
+                };
+            }
+            Phase::Write => {
+                if self.shift_in(line) {
+                    let ack = match self.active {
+                        Some(i) => self.targets[i].write(self.shift),
+                        None => false,
+                    };
+
+                    self.phase = Phase::TargetAck(ack);
+                }
+            }
+            Phase::Read(_) => {
+                self.bits += 1;
+
+                if self.bits == 8 {
+                    self.bits = 0;
+                    self.phase = Phase::ControllerAck;
+                }
+            }
+            Phase::ControllerAck => {
+                // NACK from controller: target releases SDA until STOP
+                self.phase = if line { Phase::Idle } else { self.next_read() };
+            }
+        }
+
+        self.scl = false;
+        self.sda = sda;
+
+        line
+    }
+
+    fn shift_in(&mut self, bit: bool) -> bool {
+        self.shift = (self.shift << 1) | bit as u8;
+        self.bits += 1;
+
+        if self.bits == 8 {
+            self.bits = 0;
+            return true;
+        }
+
+        false
+    }
+
+    fn next_read(&mut self) -> Phase {
+        match self.active {
+            Some(i) => Phase::Read(self.targets[i].read()),
+            None => Phase::Idle,
+        }
+    }
+
+    fn start_condition(&mut self) {
+        self.phase = Phase::Address;
+        self.shift = 0;
+        self.bits = 0;
+    }
+
+    fn stop_condition(&mut self) {
+        if let Some(i) = self.active.take() {
+            self.targets[i].stop();
+        }
+
+        self.phase = Phase::Idle;
+        self.bits = 0;
+    }
+}
+
+/// Model of a 24C04 serial EEPROM: 512 bytes in two 256-byte blocks selected
+/// by the LSB of the I2C address, 16-byte write pages.
+pub struct Eeprom24c04 {
+    address: u8,
+    memory: [u8; 512],
+    pointer: usize,
+    word_address: bool,
+}
+
+impl Eeprom24c04 {
+    const PAGE_SIZE: usize = 16;
+
+    /// Create an erased EEPROM responding to `address` and `address | 1`.
+    pub fn new(address: u8) -> Eeprom24c04 {
+        Eeprom24c04 {
+            address: address & 0x7e,
+            memory: [0xff; 512],
+            pointer: 0,
+            word_address: false,
+        }
+    }
+
+    /// Create an EEPROM with the default 0x50 address.
+    pub fn with_default_address() -> Eeprom24c04 {
+        Eeprom24c04::new(0x50)
+    }
+}
+
+impl I2cTarget for Eeprom24c04 {
+    fn matches(&self, address: u8) -> bool {
+        address & 0x7e == self.address
+    }
+
+    fn start(&mut self, address: u8, read: bool) {
+        let block = ((address & 0x1) as usize) << 8;
+
+        self.pointer = block | (self.pointer & 0xff);
+        self.word_address = !read;
+    }
+
+    fn write(&mut self, byte: u8) -> bool {
+        if self.word_address {
+            self.pointer = (self.pointer & 0x100) | byte as usize;
+            self.word_address = false;
+            return true;
+        }
+
+        self.memory[self.pointer] = byte;
+
+        // address rolls over within the current page
+        let page = self.pointer & !(Self::PAGE_SIZE - 1);
+        self.pointer = page | ((self.pointer + 1) & (Self::PAGE_SIZE - 1));
+
+        true
+    }
+
+    fn read(&mut self) -> u8 {
+        let byte = self.memory[self.pointer];
+        self.pointer = (self.pointer + 1) % self.memory.len();
+        byte
+    }
+}
+
+/// Model of an LM75 temperature sensor.
+///
+/// Register pointer 0 is the temperature, 1 the configuration, 2 the
+/// hysteresis and 3 the overtemperature shutdown threshold.
+pub struct Lm75 {
+    address: u8,
+    pointer: u8,
+    registers: [u16; 4],
+    index: usize,
+    pointer_write: bool,
+}
+
+impl Lm75 {
+    /// Create a sensor responding to `address` reporting `temperature` in °C.
+    pub fn new(address: u8, temperature: f32) -> Lm75 {
+        let mut lm75 = Lm75 {
+            address,
+            pointer: 0,
+            // power-up defaults: Thyst 75°C, Tos 80°C
+            registers: [0, 0, 0x4b00, 0x5000],
+            index: 0,
+            pointer_write: false,
+        };
+
+        lm75.set_temperature(temperature);
+        lm75
+    }
+
+    /// Create a sensor with the default 0x48 address.
+    pub fn with_default_address(temperature: f32) -> Lm75 {
+        Lm75::new(0x48, temperature)
+    }
+
+    /// Set the temperature, rounded to the 0.5°C sensor resolution.
+    pub fn set_temperature(&mut self, temperature: f32) {
+        let halves = (temperature * 2.0).round() as i16;
+        self.registers[0] = (halves << 7) as u16;
+    }
+
+    fn register_len(&self) -> usize {
+        if self.pointer == 1 {
+            1
+        } else {
+            2
+        }
+    }
+}
+
+impl I2cTarget for Lm75 {
+    fn matches(&self, address: u8) -> bool {
+        address == self.address
+    }
+
+    fn start(&mut self, _address: u8, read: bool) {
+        self.pointer_write = !read;
+        self.index = 0;
+    }
+
+    fn write(&mut self, byte: u8) -> bool {
+        if self.pointer_write {
+            self.pointer = byte & 0x3;
+            self.pointer_write = false;
+            return true;
+        }
+
+        // temperature register is read-only
+        if self.pointer != 0 && self.index < self.register_len() {
+            let shift = 8 * (1 - self.index);
+            let reg = &mut self.registers[self.pointer as usize];
+            *reg = (*reg & !(0xff << shift)) | ((byte as u16) << shift);
+            self.index += 1;
+        }
+
+        true
+    }
+
+    fn read(&mut self) -> u8 {
+        let reg = self.registers[self.pointer as usize];
+        let byte = if self.register_len() == 1 || self.index & 0x1 == 0 {
+            (reg >> 8) as u8
+        } else {
+            reg as u8
+        };
+
+        self.index += 1;
+        byte
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::MpsseEmulator;
+    use crate::error::{ErrorKind, X232Error};
+    use crate::i2c::I2cSpeed;
+    use crate::x232h::FTx232H;
+    use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+
+    #[test]
+    fn test_eeprom_t1() {
+        let emu = MpsseEmulator::new();
+        emu.add_i2c_target(Eeprom24c04::with_default_address());
+
+        let dev = FTx232H::with_transport(emu).unwrap();
+        let mut i2c = dev.i2c(I2cSpeed::CLK_400kHz).unwrap();
+        let mut data = [0; 4];
+
+        i2c.write(0x50, &[0x10, 0xaa, 0xbb, 0xcc, 0xdd]).unwrap();
+        i2c.write(0x51, &[0x10, 0x11, 0x22, 0x33, 0x44]).unwrap();
+
+        i2c.write_read(0x50, &[0x10], &mut data).unwrap();
+        assert_eq!(data, [0xaa, 0xbb, 0xcc, 0xdd]);
+
+        i2c.write_read(0x51, &[0x10], &mut data).unwrap();
+        assert_eq!(data, [0x11, 0x22, 0x33, 0x44]);
+
+        // current address read continues after the last byte
+        i2c.read(0x51, &mut data[..1]).unwrap();
+        assert_eq!(data[0], 0xff);
+    }
+
+    #[test]
+    fn test_eeprom_t2() {
+        let emu = MpsseEmulator::new();
+        emu.add_i2c_target(Eeprom24c04::with_default_address());
+
+        let dev = FTx232H::with_transport(emu).unwrap();
+        let mut i2c = dev.i2c(I2cSpeed::CLK_400kHz).unwrap();
+        let mut data = [0; 16];
+
+        // page write rolls over to the beginning of the page
+        let page: Vec<u8> = (0..18).collect();
+        i2c.write(0x50, &[&[0x20][..], &page].concat()).unwrap();
+        i2c.write_read(0x50, &[0x20], &mut data).unwrap();
+
+        assert_eq!(data[..2], [16, 17]);
+        assert_eq!(data[2..], page[2..16]);
+    }
+
+    #[test]
+    fn test_nack() {
+        let emu = MpsseEmulator::new();
+        emu.add_i2c_target(Eeprom24c04::with_default_address());
+
+        let dev = FTx232H::with_transport(emu).unwrap();
+        let mut i2c = dev.i2c(I2cSpeed::CLK_400kHz).unwrap();
+
+        match i2c.write(0x42, &[0x0]) {
+            Err(X232Error::HAL(ErrorKind::I2cNoAck)) => {}
+            _ => panic!("write to missing target should not be acked"),
+        }
+
+        // bus is usable after NACK
+        i2c.write(0x50, &[0x0, 0x5a]).unwrap();
+    }
+
+    #[test]
+    fn test_lm75() {
+        let emu = MpsseEmulator::new();
+        emu.add_i2c_target(Lm75::with_default_address(-25.5));
+
+        let dev = FTx232H::with_transport(emu).unwrap();
+        let mut i2c = dev.i2c(I2cSpeed::CLK_100kHz).unwrap();
+        let mut data = [0; 2];
+
+        i2c.write_read(0x48, &[0x0], &mut data).unwrap();
+        assert_eq!(data, [0xe6, 0x80]);
+
+        i2c.write(0x48, &[0x3, 0x3c, 0x00]).unwrap();
+        i2c.write_read(0x48, &[0x3], &mut data).unwrap();
+        assert_eq!(data, [0x3c, 0x00]);
+    }
+}
@@ -0,0 +1,592 @@
+//! Virtual SPI targets for [`MpsseEmulator`].
+//!
+//! Each target is attached to an active-low chip-select GPIO pin. The
+//! emulator watches every GPIO update for CS transitions and passes the
+//! bytes clocked by [`SpiBus`] to the selected target, which returns the
+//! byte shifted back on MISO. Bytes are handed over in the order of the
+//! clocking command, so the same model works for MSB and LSB first modes.
+//! Bit-mode clocking commands are not routed to SPI targets.
+//!
+//! [`MpsseEmulator`]: super::MpsseEmulator
+//! [`SpiBus`]: crate::spi::SpiBus
+
+use crate::gpio::PinBank;
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Byte-level model of an SPI device attached to the emulated bus.
+pub trait SpiTarget: Send {
+    /// Chip-select has been asserted.
+    fn select(&mut self) {}
+
+    /// Chip-select has been released.
+    fn deselect(&mut self) {}
+
+    /// Exchange one byte: `mosi` is clocked in, the result is clocked out.
+    fn transfer(&mut self, mosi: u8) -> u8;
+}
+
+struct Slot {
+    bank: PinBank,
+    bit: u8,
+    selected: bool,
+    target: Box<dyn SpiTarget>,
+}
+
+pub(super) struct SpiWire {
+    slots: Vec<Slot>,
+}
+
+impl SpiWire {
+    pub(super) fn new() -> SpiWire {
+        SpiWire { slots: Vec::new() }
+    }
+
+    pub(super) fn add_target(&mut self, bank: PinBank, bit: u8, target: Box<dyn SpiTarget>) {
+        self.slots.push(Slot {
+            bank,
+            bit,
+            selected: false,
+            target,
+        });
+    }
+
+    /// Check chip-select pins against the current GPIO levels.
+    pub(super) fn update(&mut self, lower: u8, upper: u8) {
+        for slot in self.slots.iter_mut() {
+            let level = match slot.bank {
+                PinBank::Low => lower,
+                PinBank::High => upper,
+            };
+            let selected = level & (1 << slot.bit) == 0;
+
+            if selected && !slot.selected {
+                slot.target.select();
+            }
+
+            if !selected && slot.selected {
+                slot.target.deselect();
+            }
+
+            slot.selected = selected;
+        }
+    }
+
+    /// Exchange a byte with the selected target, if any.
+    pub(super) fn transfer(&mut self, mosi: u8) -> Option<u8> {
+        self.slots
+            .iter_mut()
+            .find(|slot| slot.selected)
+            .map(|slot| slot.target.transfer(mosi))
+    }
+}
+
+// nRF24L01+ commands
+const R_REGISTER: u8 = 0x00;
+const W_REGISTER: u8 = 0x20;
+const R_RX_PL_WID: u8 = 0x60;
+const R_RX_PAYLOAD: u8 = 0x61;
+const W_TX_PAYLOAD: u8 = 0xA0;
+const W_TX_PAYLOAD_NOACK: u8 = 0xB0;
+const FLUSH_TX: u8 = 0xE1;
+const FLUSH_RX: u8 = 0xE2;
+
+// nRF24L01+ registers
+const CONFIG: usize = 0x00;
+const SETUP_AW: usize = 0x03;
+const STATUS: usize = 0x07;
+const RX_ADDR_P0: usize = 0x0A;
+const RX_ADDR_P1: usize = 0x0B;
+const TX_ADDR: usize = 0x10;
+const RX_PW_P0: usize = 0x11;
+const FIFO_STATUS: usize = 0x17;
+
+const FIFO_DEPTH: usize = 3;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Command {
+    ReadRegister(usize),
+    WriteRegister(usize),
+    ReadPayloadWidth,
+    ReadPayload,
+    WritePayload,
+    Other,
+}
+
+struct Radio {
+    registers: [[u8; 5]; 0x1E],
+    rx: VecDeque<(u8, Vec<u8>)>,
+    tx: VecDeque<Vec<u8>>,
+    sent: Vec<Vec<u8>>,
+    command: Option<Command>,
+    index: usize,
+    payload: Vec<u8>,
+    rx_payload: Option<Vec<u8>>,
+}
+
+impl Radio {
+    fn new() -> Radio {
+        let mut registers = [[0; 5]; 0x1E];
+
+        // power-on reset values
+        registers[CONFIG][0] = 0x08;
+        registers[0x01][0] = 0x3F;
+        registers[0x02][0] = 0x03;
+        registers[SETUP_AW][0] = 0x03;
+        registers[0x04][0] = 0x03;
+        registers[0x05][0] = 0x02;
+        registers[0x06][0] = 0x0E;
+        registers[STATUS][0] = 0x0E;
+        registers[RX_ADDR_P0] = [0xE7; 5];
+        registers[RX_ADDR_P1] = [0xC2; 5];
+        registers[0x0C][0] = 0xC3;
+        registers[0x0D][0] = 0xC4;
+        registers[0x0E][0] = 0xC5;
+        registers[0x0F][0] = 0xC6;
+        registers[TX_ADDR] = [0xE7; 5];
+
+        let mut radio = Radio {
+            registers,
+            rx: VecDeque::new(),
+            tx: VecDeque::new(),
+            sent: Vec::new(),
+            command: None,
+            index: 0,
+            payload: Vec::new(),
+            rx_payload: None,
+        };
+
+        radio.update_status();
+        radio
+    }
+
+    fn register_len(&self, reg: usize) -> usize {
+        match reg {
+            RX_ADDR_P0 | RX_ADDR_P1 | TX_ADDR => self.registers[SETUP_AW][0] as usize + 2,
+            _ => 1,
+        }
+    }
+
+    fn update_status(&mut self) {
+        let rx_pipe = match self.rx.front() {
+            Some((pipe, _)) => *pipe,
+            None => 0b111,
+        };
+        let tx_full = self.tx.len() >= FIFO_DEPTH;
+
+        let status = &mut self.registers[STATUS][0];
+        *status = (*status & 0x70) | (rx_pipe << 1) | tx_full as u8;
+
+        self.registers[FIFO_STATUS][0] = ((tx_full as u8) << 5)
+            | ((self.tx.is_empty() as u8) << 4)
+            | (((self.rx.len() >= FIFO_DEPTH) as u8) << 1)
+            | self.rx.is_empty() as u8;
+    }
+
+    fn start(&mut self, cmd: u8) {
+        let command = match cmd {
+            0x00..=0x1F => Command::ReadRegister((cmd - R_REGISTER) as usize),
+            0x20..=0x3F => Command::WriteRegister((cmd - W_REGISTER) as usize),
+            R_RX_PL_WID => Command::ReadPayloadWidth,
+            R_RX_PAYLOAD => {
+                self.rx_payload = self.rx.pop_front().map(|(_, payload)| payload);
+                Command::ReadPayload
+            }
+            W_TX_PAYLOAD | W_TX_PAYLOAD_NOACK => Command::WritePayload,
+            FLUSH_TX => {
+                self.tx.clear();
+                Command::Other
+            }
+            FLUSH_RX => {
+                self.rx.clear();
+                Command::Other
+            }
+            // ACK payloads, REUSE_TX_PL and NOP do not affect the model
+            _ => Command::Other,
+        };
+
+        self.command = Some(command);
+        self.index = 0;
+        self.payload.clear();
+    }
+
+    fn data(&mut self, command: Command, mosi: u8) -> u8 {
+        let index = self.index;
+        self.index += 1;
+
+        match command {
+            Command::ReadRegister(reg)
+                if reg < self.registers.len() && index < self.register_len(reg) =>
+            {
+                self.registers[reg][index]
+            }
+            Command::WriteRegister(reg) if reg < self.registers.len() => {
+                if index < self.register_len(reg) {
+                    if reg == STATUS {
+                        // interrupt flags are cleared by writing 1
+                        self.registers[STATUS][0] &= !(mosi & 0x70);
+                    } else if reg != FIFO_STATUS {
+                        self.registers[reg][index] = mosi;
+                    }
+                }
+                0
+            }
+            Command::ReadPayloadWidth => self.rx.front().map_or(0, |(_, p)| p.len() as u8),
+            Command::ReadPayload => self
+                .rx_payload
+                .as_ref()
+                .and_then(|p| p.get(index).copied())
+                .unwrap_or(0),
+            Command::WritePayload => {
+                self.payload.push(mosi);
+                0
+            }
+            _ => 0,
+        }
+    }
+
+    fn finish(&mut self) {
+        if self.command == Some(Command::WritePayload)
+            && !self.payload.is_empty()
+            && self.tx.len() < FIFO_DEPTH
+        {
+            self.tx.push_back(self.payload.clone());
+        }
+
+        self.rx_payload = None;
+
+        // PWR_UP and PRIM_TX: payloads are sent right away, always acked
+        let config = self.registers[CONFIG][0];
+        if config & 0x02 != 0 && config & 0x01 == 0 && !self.tx.is_empty() {
+            self.sent.extend(self.tx.drain(..));
+            self.registers[STATUS][0] |= 0x20;
+        }
+
+        self.command = None;
+        self.update_status();
+    }
+}
+
+/// Register-level model of an nRF24L01+ transceiver.
+///
+/// There is no radio: payloads written in TX mode are moved to a list of
+/// sent payloads as soon as CSN is released and reported as acknowledged.
+/// Received payloads are injected by [`receive`]. Clones share the same
+/// device, so a test can keep one to inspect it.
+///
+/// [`receive`]: Nrf24l01::receive
+#[derive(Clone)]
+pub struct Nrf24l01 {
+    radio: Arc<Mutex<Radio>>,
+}
+
+impl Default for Nrf24l01 {
+    fn default() -> Self {
+        Nrf24l01 {
+            radio: Arc::new(Mutex::new(Radio::new())),
+        }
+    }
+}
+
+impl Nrf24l01 {
+    /// Create a transceiver in its power-on reset state.
+    pub fn new() -> Nrf24l01 {
+        Nrf24l01::default()
+    }
+
+    fn radio(&self) -> MutexGuard<'_, Radio> {
+        match self.radio.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Current value of a register, multi-byte registers are LSB first.
+    pub fn register(&self, reg: u8) -> Vec<u8> {
+        let radio = self.radio();
+        let reg = reg as usize;
+        radio.registers[reg][..radio.register_len(reg)].to_vec()
+    }
+
+    /// Put a payload received on `pipe` into the RX FIFO.
+    pub fn receive(&self, pipe: u8, payload: &[u8]) {
+        let mut radio = self.radio();
+
+        if radio.rx.len() < FIFO_DEPTH {
+            radio.rx.push_back((pipe, payload.to_vec()));
+            radio.registers[RX_PW_P0 + pipe as usize][0] = payload.len() as u8;
+            radio.registers[STATUS][0] |= 0x40;
+            radio.update_status();
+        }
+    }
+
+    /// Payloads transmitted so far.
+    pub fn sent(&self) -> Vec<Vec<u8>> {
+        self.radio().sent.clone()
+    }
+}
+
+impl SpiTarget for Nrf24l01 {
+    fn select(&mut self) {
+        self.radio().command = None;
+    }
+
+    fn deselect(&mut self) {
+        self.radio().finish();
+    }
+
+    fn transfer(&mut self, mosi: u8) -> u8 {
+        let mut radio = self.radio();
+
+        match radio.command {
+            None => {
+                let status = radio.registers[STATUS][0];
+                radio.start(mosi);
+                status
+            }
+            Some(command) => radio.data(command, mosi),
+        }
+    }
+}
+
+/// Model of an MCP3008 8-channel 10-bit ADC.
+///
+/// The conversion request is decoded bit by bit, so the model works with
+/// any byte alignment of the start bit.
+pub struct Mcp3008 {
+    channels: [u16; 8],
+    clocks: usize,
+    request: u8,
+    result: u16,
+}
+
+impl Mcp3008 {
+    /// Create an ADC with fixed 10-bit readings for channels CH0..CH7.
+    pub fn new(channels: [u16; 8]) -> Mcp3008 {
+        Mcp3008 {
+            channels,
+            clocks: 0,
+            request: 0,
+            result: 0,
+        }
+    }
+
+    fn convert(&self) -> u16 {
+        let ch = (self.request & 0x7) as usize;
+
+        if self.request & 0x8 != 0 {
+            // single-ended
+            return self.channels[ch] & 0x3ff;
+        }
+
+        // pseudo-differential pairs: IN+ is the even channel unless D0 is set
+        let (pos, neg) = if ch & 0x1 == 0 {
+            (ch, ch + 1)
+        } else {
+            (ch, ch - 1)
+        };
+
+        self.channels[pos].saturating_sub(self.channels[neg]) & 0x3ff
+    }
+
+    // One clock after CS: clock 0 is the start bit, clocks 1..=4 are
+    // SGL/DIFF and D2..D0, then sample, null bit, B9..B0 and B1..B9 again
+    fn clock(&mut self, din: bool) -> bool {
+        if self.clocks == 0 && !din {
+            return false;
+        }
+
+        let clock = self.clocks;
+        self.clocks += 1;
+
+        match clock {
+            1..=4 => {
+                self.request = (self.request << 1) | din as u8;
+
+                if clock == 4 {
+                    self.result = self.convert();
+                }
+
+                false
+            }
+            7..=16 => self.result & (1 << (16 - clock)) != 0,
+            17..=25 => self.result & (1 << (clock - 16)) != 0,
+            _ => false,
+        }
+    }
+}
+
+impl SpiTarget for Mcp3008 {
+    fn select(&mut self) {
+        self.clocks = 0;
+        self.request = 0;
+    }
+
+    fn transfer(&mut self, mosi: u8) -> u8 {
+        let mut miso: u8 = 0;
+
+        for n in (0..8).rev() {
+            if self.clock(mosi & (1 << n) != 0) {
+                miso |= 1 << n;
+            }
+        }
+
+        miso
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::MpsseEmulator;
+    use crate::spi::SpiSpeed;
+    use crate::x232h::FTx232H;
+    use embedded_hal::blocking::spi::{Transfer, Write, WriteIter};
+    use embedded_hal::digital::v2::OutputPin;
+
+    // Records MOSI and answers with its complement
+    struct Recorder(Arc<Mutex<Vec<u8>>>);
+
+    impl SpiTarget for Recorder {
+        fn transfer(&mut self, mosi: u8) -> u8 {
+            self.0.lock().unwrap().push(mosi);
+            !mosi
+        }
+    }
+
+    #[test]
+    fn test_nrf24_registers() {
+        let emu = MpsseEmulator::new();
+        let nrf = Nrf24l01::new();
+        emu.add_spi_target(PinBank::Low, 4, nrf.clone());
+
+        let dev = FTx232H::with_transport(emu).unwrap();
+        let mut spi = dev.spi(SpiSpeed::CLK_1MHz).unwrap();
+        let mut cs = dev.pl0().unwrap();
+        cs.set_high().unwrap();
+
+        // read CONFIG: status byte comes first
+        let mut buf = [R_REGISTER | CONFIG as u8, 0xff];
+        cs.set_low().unwrap();
+        spi.transfer(&mut buf).unwrap();
+        cs.set_high().unwrap();
+        assert_eq!(buf, [0x0e, 0x08]);
+
+        // write and read back TX_ADDR
+        cs.set_low().unwrap();
+        spi.write(&[W_REGISTER | TX_ADDR as u8, 1, 2, 3, 4, 5])
+            .unwrap();
+        cs.set_high().unwrap();
+        assert_eq!(nrf.register(TX_ADDR as u8), vec![1, 2, 3, 4, 5]);
+
+        let mut buf = [R_REGISTER | TX_ADDR as u8, 0, 0, 0, 0, 0];
+        cs.set_low().unwrap();
+        spi.transfer(&mut buf).unwrap();
+        cs.set_high().unwrap();
+        assert_eq!(buf, [0x0e, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_nrf24_payloads() {
+        let emu = MpsseEmulator::new();
+        let nrf = Nrf24l01::new();
+        emu.add_spi_target(PinBank::Low, 4, nrf.clone());
+
+        let dev = FTx232H::with_transport(emu).unwrap();
+        let mut spi = dev.spi(SpiSpeed::CLK_1MHz).unwrap();
+        let mut cs = dev.pl0().unwrap();
+        cs.set_high().unwrap();
+
+        // power up in TX mode and send a payload
+        cs.set_low().unwrap();
+        spi.write(&[W_REGISTER | CONFIG as u8, 0x0a]).unwrap();
+        cs.set_high().unwrap();
+
+        cs.set_low().unwrap();
+        spi.write(&[W_TX_PAYLOAD, b'h', b'i']).unwrap();
+        cs.set_high().unwrap();
+        assert_eq!(nrf.sent(), vec![b"hi".to_vec()]);
+        assert_eq!(nrf.register(STATUS as u8), vec![0x2e]);
+
+        // receive a payload on pipe 1
+        nrf.receive(1, &[0xde, 0xad]);
+        assert_eq!(nrf.register(STATUS as u8), vec![0x62]);
+
+        let mut buf = [R_RX_PL_WID, 0];
+        cs.set_low().unwrap();
+        spi.transfer(&mut buf).unwrap();
+        cs.set_high().unwrap();
+        assert_eq!(buf, [0x62, 2]);
+
+        let mut buf = [R_RX_PAYLOAD, 0, 0];
+        cs.set_low().unwrap();
+        spi.transfer(&mut buf).unwrap();
+        cs.set_high().unwrap();
+        assert_eq!(buf, [0x62, 0xde, 0xad]);
+
+        // clear interrupt flags
+        cs.set_low().unwrap();
+        spi.write(&[W_REGISTER | STATUS as u8, 0x70]).unwrap();
+        cs.set_high().unwrap();
+        assert_eq!(nrf.register(STATUS as u8), vec![0x0e]);
+    }
+
+    #[test]
+    fn test_mcp3008() {
+        let emu = MpsseEmulator::new();
+        let values = [0, 1, 0x155, 0x2aa, 0x3ff, 512, 100, 1000];
+        emu.add_spi_target(PinBank::Low, 4, Mcp3008::new(values));
+
+        let dev = FTx232H::with_transport(emu).unwrap();
+        let mut spi = dev.spi(SpiSpeed::CLK_1MHz).unwrap();
+        let mut cs = dev.pl0().unwrap();
+        cs.set_high().unwrap();
+
+        for (ch, value) in values.iter().enumerate() {
+            let mut buf = [0x01, 0x80 | (ch as u8) << 4, 0x00];
+            cs.set_low().unwrap();
+            spi.transfer(&mut buf).unwrap();
+            cs.set_high().unwrap();
+            assert_eq!(((buf[1] as u16 & 0x3) << 8) | buf[2] as u16, *value);
+        }
+
+        // pseudo-differential: CH6 - CH7 saturates at zero, CH7 - CH6 does not
+        for (ch, value) in [(6, 0), (7, 900)] {
+            let mut buf = [0x01, (ch as u8) << 4, 0x00];
+            cs.set_low().unwrap();
+            spi.transfer(&mut buf).unwrap();
+            cs.set_high().unwrap();
+            assert_eq!(((buf[1] as u16 & 0x3) << 8) | buf[2] as u16, value);
+        }
+    }
+
+    #[test]
+    fn test_large_payloads() {
+        let emu = MpsseEmulator::new();
+        let mosi = Arc::new(Mutex::new(Vec::new()));
+        emu.add_spi_target(PinBank::Low, 4, Recorder(mosi.clone()));
+
+        let dev = FTx232H::with_transport(emu).unwrap();
+        let mut spi = dev.spi(SpiSpeed::CLK_10MHz).unwrap();
+        let mut cs = dev.pl0().unwrap();
+        cs.set_low().unwrap();
+
+        let image: Vec<u8> = (0..100_000u32).map(|i| (i * 7) as u8).collect();
+        let mut buf = image.clone();
+        spi.transfer(&mut buf).unwrap();
+        assert!(buf.iter().zip(&image).all(|(miso, mosi)| *miso == !*mosi));
+        assert_eq!(*mosi.lock().unwrap(), image);
+
+        mosi.lock().unwrap().clear();
+        spi.write(&image).unwrap();
+        assert_eq!(*mosi.lock().unwrap(), image);
+
+        mosi.lock().unwrap().clear();
+        spi.write_iter(image.iter().copied().chain(image.iter().copied()))
+            .unwrap();
+        let sent = mosi.lock().unwrap();
+        assert_eq!(sent.len(), 2 * image.len());
+        assert!(sent.chunks(image.len()).all(|half| half == image));
+    }
+}
@@ -0,0 +1,368 @@
+//! Enumeration and selection of FTDI devices attached to the host.
+//!
+//! Listing the devices needs `rusb`, so it is only available with the
+//! `enumerate` feature, which is enabled by `rusb-backend` as well.
+
+use crate::chip::ChipType;
+#[cfg(any(feature = "enumerate", feature = "libftdi"))]
+use crate::error::{ErrorKind, Result, X232Error};
+
+#[cfg(feature = "enumerate")]
+use rusb::{Context, Device, DeviceDescriptor, UsbContext, Version};
+#[cfg(feature = "enumerate")]
+use std::time::Duration;
+
+#[cfg(feature = "libftdi")]
+use libftdi1_sys as ffi;
+#[cfg(feature = "libftdi")]
+use std::ffi::CStr;
+#[cfg(feature = "libftdi")]
+use std::os::raw::{c_char, c_int};
+#[cfg(feature = "libftdi")]
+use std::ptr;
+
+/// FTDI USB vendor ID.
+pub const FTDI_VID: u16 = 0x0403;
+
+/// PIDs of the chips with MPSSE: FT232H, FT2232C/D/H and FT4232H.
+#[cfg(all(feature = "libftdi", not(feature = "enumerate")))]
+const MPSSE_PRODUCTS: [u16; 3] = [0x6014, 0x6010, 0x6011];
+
+#[cfg(feature = "enumerate")]
+pub(crate) const STRING_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Description of an attached FTDI device.
+///
+/// String descriptors can be read only if the device can be opened, so they
+/// are not available e.g. for devices without access permissions.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DeviceInfo {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub chip_type: ChipType,
+    pub serial_number: Option<String>,
+    pub manufacturer: Option<String>,
+    pub description: Option<String>,
+    pub bus_number: u8,
+    pub address: u8,
+    /// Port numbers on the path from the root hub to the device.
+    pub port_numbers: Vec<u8>,
+    pub mpsse_interfaces: u8,
+}
+
+/// Criteria to pick one adapter among the attached FTDI devices.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DeviceSelector {
+    /// Serial number string.
+    Serial(String),
+    /// Product description string.
+    Description(String),
+    /// Position in the list returned by `list`, starting from 0. Without the
+    /// `enumerate` feature only the devices with MPSSE PIDs are counted.
+    Index(usize),
+    /// USB path in `<bus>-<port>[.<port>...]` form, e.g. `1-4.2`, see
+    /// [`DeviceInfo::path`]. Needs the `enumerate` feature.
+    Path(String),
+}
+
+#[cfg(feature = "enumerate")]
+impl DeviceSelector {
+    fn matches(&self, index: usize, info: &DeviceInfo) -> bool {
+        match self {
+            DeviceSelector::Serial(serial) => info.serial_number.as_ref() == Some(serial),
+            DeviceSelector::Description(desc) => info.description.as_ref() == Some(desc),
+            DeviceSelector::Index(n) => *n == index,
+            DeviceSelector::Path(path) => info.path() == *path,
+        }
+    }
+}
+
+#[cfg(feature = "enumerate")]
+/// Position of the only device matching `selector`.
+fn select(devices: &[DeviceInfo], selector: &DeviceSelector) -> Result<usize> {
+    let mut found = devices
+        .iter()
+        .enumerate()
+        .filter(|(index, info)| selector.matches(*index, info))
+        .map(|(index, _)| index);
+
+    match (found.next(), found.next()) {
+        (Some(index), None) => Ok(index),
+        (Some(_), Some(_)) => Err(X232Error::HAL(ErrorKind::DeviceAmbiguous)),
+        (None, _) => Err(X232Error::HAL(ErrorKind::DeviceNotFound)),
+    }
+}
+
+#[cfg(feature = "enumerate")]
+/// Raw `bcdDevice` value of the USB device descriptor.
+pub(crate) fn bcd_device(version: Version) -> u16 {
+    let major = version.major() as u16;
+
+    ((major / 10) << 12)
+        | ((major % 10) << 8)
+        | ((version.minor() as u16) << 4)
+        | version.sub_minor() as u16
+}
+
+impl DeviceInfo {
+    #[cfg(feature = "enumerate")]
+    fn new(device: &Device<Context>, desc: &DeviceDescriptor) -> DeviceInfo {
+        let chip_type = ChipType::from_bcd_device(bcd_device(desc.device_version()));
+        let interfaces = device
+            .active_config_descriptor()
+            .map(|config| config.num_interfaces())
+            .unwrap_or(u8::MAX);
+
+        let mut info = DeviceInfo {
+            vendor_id: desc.vendor_id(),
+            product_id: desc.product_id(),
+            chip_type,
+            serial_number: None,
+            manufacturer: None,
+            description: None,
+            bus_number: device.bus_number(),
+            address: device.address(),
+            port_numbers: device.port_numbers().unwrap_or_default(),
+            mpsse_interfaces: chip_type.mpsse_interfaces().min(interfaces),
+        };
+
+        if let Ok(handle) = device.open() {
+            if let Ok(languages) = handle.read_languages(STRING_TIMEOUT) {
+                if let Some(lang) = languages.first() {
+                    info.serial_number = handle
+                        .read_serial_number_string(*lang, desc, STRING_TIMEOUT)
+                        .ok();
+                    info.manufacturer = handle
+                        .read_manufacturer_string(*lang, desc, STRING_TIMEOUT)
+                        .ok();
+                    info.description = handle.read_product_string(*lang, desc, STRING_TIMEOUT).ok();
+                }
+            }
+        }
+
+        info
+    }
+
+    /// USB path of the device in `<bus>-<port>[.<port>...]` form, the same
+    /// as used by Linux sysfs.
+    pub fn path(&self) -> String {
+        let ports: Vec<String> = self.port_numbers.iter().map(|p| p.to_string()).collect();
+        format!("{}-{}", self.bus_number, ports.join("."))
+    }
+}
+
+#[cfg(feature = "enumerate")]
+fn devices() -> Result<Vec<(Device<Context>, DeviceInfo)>> {
+    let context = Context::new()?;
+    let mut devices = Vec::new();
+
+    for device in context.devices()?.iter() {
+        let desc = match device.device_descriptor() {
+            Ok(desc) => desc,
+            Err(_) => continue,
+        };
+
+        if desc.vendor_id() == FTDI_VID {
+            let info = DeviceInfo::new(&device, &desc);
+            devices.push((device, info));
+        }
+    }
+
+    Ok(devices)
+}
+
+#[cfg(feature = "enumerate")]
+/// List all the attached devices with FTDI vendor ID.
+pub fn list() -> Result<Vec<DeviceInfo>> {
+    Ok(devices()?.into_iter().map(|(_, info)| info).collect())
+}
+
+#[cfg(feature = "enumerate")]
+/// Find the only attached device matching `selector`.
+pub(crate) fn find(selector: &DeviceSelector) -> Result<(Device<Context>, DeviceInfo)> {
+    let mut devices = devices()?;
+    let infos: Vec<DeviceInfo> = devices.iter().map(|(_, info)| info.clone()).collect();
+    let index = select(&infos, selector)?;
+
+    Ok(devices.swap_remove(index))
+}
+
+/// Serial number and description of the attached devices with the given
+/// VID/PID, listed by libftdi in the order used by `ftdi::Opener::nth`.
+/// The strings are `None` for devices which can not be opened.
+#[cfg(feature = "libftdi")]
+pub(crate) fn ftdi_strings(
+    vendor: u16,
+    product: u16,
+) -> Result<Vec<(Option<String>, Option<String>)>> {
+    let mut strings = Vec::new();
+
+    // SAFETY: the context and the list are freed before return, the device
+    // list nodes are valid until then
+    unsafe {
+        let context = ffi::ftdi_new();
+        if context.is_null() {
+            return Err(X232Error::FTDI(ftdi::Error::AllocationFailed));
+        }
+
+        let mut list = ptr::null_mut();
+        if ffi::ftdi_usb_find_all(context, &mut list, vendor as c_int, product as c_int) < 0 {
+            ffi::ftdi_free(context);
+            return Err(X232Error::FTDI(ftdi::Error::EnumerationFailed));
+        }
+
+        let mut node = list;
+        while !node.is_null() {
+            let mut description = [0 as c_char; 128];
+            let mut serial = [0 as c_char; 128];
+            let res = ffi::ftdi_usb_get_strings(
+                context,
+                (*node).dev,
+                ptr::null_mut(),
+                0,
+                description.as_mut_ptr(),
+                description.len() as c_int,
+                serial.as_mut_ptr(),
+                serial.len() as c_int,
+            );
+
+            let text = |buf: &[c_char]| {
+                (res == 0).then(|| CStr::from_ptr(buf.as_ptr()).to_string_lossy().into_owned())
+            };
+
+            strings.push((text(&serial), text(&description)));
+            node = (*node).next;
+        }
+
+        ffi::ftdi_list_free(&mut list);
+        ffi::ftdi_free(context);
+    }
+
+    Ok(strings)
+}
+
+/// VID/PID, `ftdi::Opener::nth` index and serial number of the only device
+/// with an MPSSE PID matching `selector`. libftdi does not report USB paths,
+/// and the index counts only the devices with MPSSE PIDs.
+#[cfg(all(feature = "libftdi", not(feature = "enumerate")))]
+pub(crate) fn find_ftdi(selector: &DeviceSelector) -> Result<(u16, u16, u32, Option<String>)> {
+    let mut found = Vec::new();
+    let mut index = 0;
+
+    for &product in MPSSE_PRODUCTS.iter() {
+        for (nth, (serial, description)) in ftdi_strings(FTDI_VID, product)?.into_iter().enumerate()
+        {
+            let matches = match selector {
+                DeviceSelector::Serial(s) => serial.as_ref() == Some(s),
+                DeviceSelector::Description(d) => description.as_ref() == Some(d),
+                DeviceSelector::Index(n) => *n == index,
+                DeviceSelector::Path(_) => {
+                    return Err(X232Error::HAL(ErrorKind::FeatureNotSupported))
+                }
+            };
+
+            if matches {
+                found.push((FTDI_VID, product, nth as u32, serial));
+            }
+
+            index += 1;
+        }
+    }
+
+    match found.len() {
+        1 => Ok(found.remove(0)),
+        0 => Err(X232Error::HAL(ErrorKind::DeviceNotFound)),
+        _ => Err(X232Error::HAL(ErrorKind::DeviceAmbiguous)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(serial: &str, description: &str, ports: &[u8]) -> DeviceInfo {
+        DeviceInfo {
+            vendor_id: FTDI_VID,
+            product_id: 0x6014,
+            chip_type: ChipType::FT232H,
+            serial_number: Some(serial.to_string()),
+            manufacturer: Some("FTDI".to_string()),
+            description: Some(description.to_string()),
+            bus_number: 1,
+            address: ports.len() as u8 + 10,
+            port_numbers: ports.to_vec(),
+            mpsse_interfaces: 1,
+        }
+    }
+
+    fn rack() -> Vec<DeviceInfo> {
+        vec![
+            info("FT0001", "UM232H", &[4, 1]),
+            info("FT0002", "UM232H", &[4, 2]),
+            info("FT0003", "Single RS232-HS", &[4, 3]),
+            info("FT0004", "UM232H", &[5]),
+        ]
+    }
+
+    #[test]
+    fn test_path() {
+        let devices = rack();
+        assert_eq!(devices[0].path(), "1-4.1");
+        assert_eq!(devices[3].path(), "1-5");
+    }
+
+    #[cfg(feature = "enumerate")]
+    #[test]
+    fn test_select() {
+        let devices = rack();
+
+        let sel = DeviceSelector::Serial("FT0002".to_string());
+        assert_eq!(select(&devices, &sel).unwrap(), 1);
+
+        let sel = DeviceSelector::Description("Single RS232-HS".to_string());
+        assert_eq!(select(&devices, &sel).unwrap(), 2);
+
+        let sel = DeviceSelector::Index(3);
+        assert_eq!(select(&devices, &sel).unwrap(), 3);
+
+        let sel = DeviceSelector::Path("1-4.1".to_string());
+        assert_eq!(select(&devices, &sel).unwrap(), 0);
+    }
+
+    #[cfg(feature = "enumerate")]
+    #[test]
+    fn test_select_errors() {
+        let devices = rack();
+
+        for sel in [
+            DeviceSelector::Serial("FT0005".to_string()),
+            DeviceSelector::Description("FT2232H".to_string()),
+            DeviceSelector::Index(4),
+            DeviceSelector::Path("1-4".to_string()),
+        ] {
+            assert!(
+                matches!(
+                    select(&devices, &sel),
+                    Err(X232Error::HAL(ErrorKind::DeviceNotFound))
+                ),
+                "unexpected result for {:?}",
+                sel
+            );
+        }
+
+        let sel = DeviceSelector::Description("UM232H".to_string());
+        assert!(matches!(
+            select(&devices, &sel),
+            Err(X232Error::HAL(ErrorKind::DeviceAmbiguous))
+        ));
+    }
+
+    #[cfg(feature = "enumerate")]
+    #[test]
+    fn test_bcd() {
+        assert_eq!(bcd_device(Version::from_bcd(0x0900)), 0x0900);
+        assert_eq!(bcd_device(Version::from_bcd(0x0700)), 0x0700);
+        assert_eq!(bcd_device(Version::from_bcd(0x1000)), 0x1000);
+        assert_eq!(bcd_device(Version::from_bcd(0x0512)), 0x0512);
+    }
+}
@@ -1,4 +1,3 @@
-use ftdi;
 use std::fmt;
 use std::io;
 
@@ -7,7 +6,10 @@ pub type Result<T> = std::result::Result<T, X232Error>;
 #[derive(Debug)]
 pub enum X232Error {
     Io(io::Error),
+    #[cfg(feature = "libftdi")]
     FTDI(ftdi::Error),
+    #[cfg(feature = "enumerate")]
+    Usb(rusb::Error),
     HAL(ErrorKind),
 }
 
@@ -20,6 +22,16 @@ pub enum ErrorKind {
     GpioPinBusy,
     GpioInvalidPin,
     SpiModeNotSupported,
+    ReplayMismatch,
+    DeviceNotFound,
+    DeviceAmbiguous,
+    MpsseNotSupported,
+    GpioNoUpperByte,
+    FeatureNotSupported,
+    Disconnected,
+    ReconnectNotSupported,
+    BadCommand(u8),
+    SyncFailed,
 }
 
 impl ErrorKind {
@@ -32,6 +44,16 @@ impl ErrorKind {
             ErrorKind::GpioPinBusy => "GPIO pin is already in use",
             ErrorKind::GpioInvalidPin => "No such GPIO pin",
             ErrorKind::SpiModeNotSupported => "Mode not supported",
+            ErrorKind::ReplayMismatch => "Request does not match the capture",
+            ErrorKind::DeviceNotFound => "No device matches the selector",
+            ErrorKind::DeviceAmbiguous => "Several devices match the selector",
+            ErrorKind::MpsseNotSupported => "MPSSE is not supported on this interface",
+            ErrorKind::GpioNoUpperByte => "No upper GPIO byte on this chip",
+            ErrorKind::FeatureNotSupported => "Feature is not supported by this chip",
+            ErrorKind::Disconnected => "Device is disconnected",
+            ErrorKind::ReconnectNotSupported => "Transport can not be reopened",
+            ErrorKind::BadCommand(_) => "MPSSE rejected a command",
+            ErrorKind::SyncFailed => "MPSSE did not answer the sync handshake",
         }
     }
 }
@@ -40,7 +62,16 @@ impl fmt::Display for X232Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             X232Error::Io(ref err) => err.fmt(f),
+            #[cfg(feature = "libftdi")]
             X232Error::FTDI(ref err) => err.fmt(f),
+            #[cfg(feature = "enumerate")]
+            X232Error::Usb(ref err) => err.fmt(f),
+            X232Error::HAL(ref err @ ErrorKind::BadCommand(op)) => write!(
+                f,
+                "A regular error occurred {:?}: opcode {:#04x}",
+                err.as_str(),
+                op
+            ),
             X232Error::HAL(ref err) => write!(f, "A regular error occurred {:?}", err.as_str()),
         }
     }
@@ -51,8 +82,51 @@ impl From<io::Error> for X232Error {
         X232Error::Io(e)
     }
 }
+
+#[cfg(feature = "libftdi")]
 impl From<ftdi::Error> for X232Error {
     fn from(e: ftdi::Error) -> Self {
-        X232Error::FTDI(e)
+        match e {
+            ftdi::Error::Disconnected => X232Error::HAL(ErrorKind::Disconnected),
+            e => X232Error::FTDI(e),
+        }
+    }
+}
+
+#[cfg(feature = "enumerate")]
+impl From<rusb::Error> for X232Error {
+    fn from(e: rusb::Error) -> Self {
+        match e {
+            rusb::Error::NoDevice => X232Error::HAL(ErrorKind::Disconnected),
+            e => X232Error::Usb(e),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl embedded_hal_1::spi::Error for X232Error {
+    fn kind(&self) -> embedded_hal_1::spi::ErrorKind {
+        embedded_hal_1::spi::ErrorKind::Other
+    }
+}
+
+#[cfg(feature = "async")]
+impl embedded_hal_1::i2c::Error for X232Error {
+    fn kind(&self) -> embedded_hal_1::i2c::ErrorKind {
+        use embedded_hal_1::i2c::{ErrorKind as I2cErrorKind, NoAcknowledgeSource};
+
+        match self {
+            X232Error::HAL(ErrorKind::I2cNoAck) => {
+                I2cErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown)
+            }
+            _ => I2cErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl embedded_hal_1::digital::Error for X232Error {
+    fn kind(&self) -> embedded_hal_1::digital::ErrorKind {
+        embedded_hal_1::digital::ErrorKind::Other
     }
 }
@@ -1,6 +1,9 @@
 //#![deny(missing_docs)]
 #![deny(unsafe_code)]
 
+use crate::chip::{ChipType, Clock};
+use crate::error::{ErrorKind, Result, X232Error};
+
 use std::convert::From;
 use std::time::Duration;
 
@@ -46,11 +49,32 @@ pub enum MpsseCmd {
     Enable3PhaseClocking = 0x8C,
     /// Used by [`disable_3phase_data_clocking`][`MpsseCmdBuilder::disable_3phase_data_clocking`].
     Disable3PhaseClocking = 0x8D,
+    /// Used by [`clock_bits_no_data`][`MpsseCmdBuilder::clock_bits_no_data`].
+    ClockBitsNoData = 0x8E,
+    /// Used by [`clock_bytes_no_data`][`MpsseCmdBuilder::clock_bytes_no_data`].
+    ClockBytesNoData = 0x8F,
+    /// Used by [`cpu_read_short`][`MpsseCmdBuilder::cpu_read_short`].
+    CpuReadShort = 0x90,
+    /// Used by [`cpu_read_extended`][`MpsseCmdBuilder::cpu_read_extended`].
+    CpuReadExtended = 0x91,
+    /// Used by [`cpu_write_short`][`MpsseCmdBuilder::cpu_write_short`].
+    CpuWriteShort = 0x92,
+    /// Used by [`cpu_write_extended`][`MpsseCmdBuilder::cpu_write_extended`].
+    CpuWriteExtended = 0x93,
+    /// Used by [`clock_until_io_high`][`MpsseCmdBuilder::clock_until_io_high`].
+    ClockUntilIOHigh = 0x94,
+    /// Used by [`clock_until_io_low`][`MpsseCmdBuilder::clock_until_io_low`].
+    ClockUntilIOLow = 0x95,
     /// Used by [`disable_adaptive_data_clocking`][`MpsseCmdBuilder::disable_adaptive_data_clocking`].
     EnableAdaptiveClocking = 0x96,
     /// Used by [`enable_adaptive_data_clocking`][`MpsseCmdBuilder::enable_adaptive_data_clocking`].
     DisableAdaptiveClocking = 0x97,
-    // EnableDriveOnlyZero = 0x9E,
+    /// Used by [`clock_bytes_or_io_high`][`MpsseCmdBuilder::clock_bytes_or_io_high`].
+    ClockBytesOrIOHigh = 0x9C,
+    /// Used by [`clock_bytes_or_io_low`][`MpsseCmdBuilder::clock_bytes_or_io_low`].
+    ClockBytesOrIOLow = 0x9D,
+    /// Used by [`enable_drive_only_zero`][`MpsseCmdBuilder::enable_drive_only_zero`].
+    EnableDriveOnlyZero = 0x9E,
 }
 
 /// Modes for clocking data out of the FTDI device.
@@ -273,6 +297,50 @@ impl From<ClockBits> for u8 {
     }
 }
 
+/// Modes for clocking bits out on TMS, used for JTAG state transitions.
+///
+/// This is an argument to the [`clock_tms_out`] method.
+///
+/// [`clock_tms_out`]: MpsseCmdBuilder::clock_tms_out
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ClockTMSOut {
+    /// TMS changes on the rising edge of the CLK pin.
+    PosEdge = 0x4A,
+    /// TMS changes on the falling edge of the CLK pin.
+    NegEdge = 0x4B,
+}
+
+impl From<ClockTMSOut> for u8 {
+    fn from(value: ClockTMSOut) -> u8 {
+        value as u8
+    }
+}
+
+/// Modes for clocking bits out on TMS and in on TDO/DI at the same time.
+///
+/// This is an argument to the [`clock_tms`] method.
+///
+/// [`clock_tms`]: MpsseCmdBuilder::clock_tms
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ClockTMS {
+    /// TMS out on positive edge, TDO in on positive edge.
+    PosTMSPosTDO = 0x6A,
+    /// TMS out on negative edge, TDO in on positive edge.
+    NegTMSPosTDO = 0x6B,
+    /// TMS out on positive edge, TDO in on negative edge.
+    PosTMSNegTDO = 0x6E,
+    /// TMS out on negative edge, TDO in on negative edge.
+    NegTMSNegTDO = 0x6F,
+}
+
+impl From<ClockTMS> for u8 {
+    fn from(value: ClockTMS) -> u8 {
+        value as u8
+    }
+}
+
 impl From<MpsseCmd> for u8 {
     fn from(value: MpsseCmd) -> Self {
         value as u8
@@ -281,57 +349,59 @@ impl From<MpsseCmd> for u8 {
 
 /// Initialization settings for the MPSSE.
 ///
-/// Used by [`initialize_mpsse`].
+/// Used by [`init_with_settings`] and [`with_transport_settings`].
 ///
-/// [`initialize_mpsse`]: FtdiMpsse::initialize_mpsse
+/// [`init_with_settings`]: crate::x232h::FTx232H::init_with_settings
+/// [`with_transport_settings`]: crate::x232h::FTx232H::with_transport_settings
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct MpsseSettings {
     /// Reset the MPSSE on initialization.
     ///
     /// This calls [`reset`] if `true`.
     ///
-    /// [`reset`]: FtdiCommon::reset
+    /// [`reset`]: crate::transport::MpsseTransport::reset
     pub reset: bool,
     /// USB in transfer size in bytes.
     ///
-    /// This gets passed to [`set_usb_parameters`].
+    /// This gets passed to [`set_transfer_size`].
     ///
-    /// [`set_usb_parameters`]: FtdiCommon::set_usb_parameters
+    /// [`set_transfer_size`]: crate::transport::MpsseTransport::set_transfer_size
     pub in_transfer_size: u32,
     /// Read timeout.
     ///
     /// This gets passed along with [`write_timeout`] to [`set_timeouts`].
     ///
-    /// [`set_timeouts`]: FtdiCommon::set_timeouts
+    /// [`set_timeouts`]: crate::transport::MpsseTransport::set_timeouts
     /// [`write_timeout`]: MpsseSettings::write_timeout
     pub read_timeout: Duration,
     /// Write timeout.
     ///
     /// This gets passed along with [`read_timeout`] to [`set_timeouts`].
     ///
-    /// [`set_timeouts`]: FtdiCommon::set_timeouts
+    /// [`set_timeouts`]: crate::transport::MpsseTransport::set_timeouts
     /// [`read_timeout`]: MpsseSettings::read_timeout
     pub write_timeout: Duration,
     /// Latency timer.
     ///
     /// This gets passed to [`set_latency_timer`].
     ///
-    /// [`set_latency_timer`]: FtdiCommon::set_latency_timer
+    /// [`set_latency_timer`]: crate::transport::MpsseTransport::set_latency_timer
     pub latency_timer: Duration,
     /// Bitmode mask.
     ///
     /// * A bit value of `0` sets the corresponding pin to an input.
     /// * A bit value of `1` sets the corresponding pin to an output.
     ///
-    /// This gets passed to [`set_bit_mode`].
+    /// This gets passed to [`set_bitmode`].
     ///
-    /// [`set_bit_mode`]: FtdiCommon::set_bit_mode
+    /// [`set_bitmode`]: crate::transport::MpsseTransport::set_bitmode
     pub mask: u8,
     /// Clock frequency.
     ///
-    /// If not `None` this will call [`set_clock`] to set the clock frequency.
+    /// If not `None` the clock is set to the highest frequency not above
+    /// it, see [`clock`].
     ///
-    /// [`set_clock`]: crate::FtdiMpsse::set_clock
+    /// [`clock`]: crate::chip::Capabilities::clock
     pub clock_frequency: Option<u32>,
 }
 
@@ -349,6 +419,9 @@ impl std::default::Default for MpsseSettings {
     }
 }
 
+/// Most bytes a single byte clocking command can move, `u16::MAX + 1`.
+pub const MAX_CLOCK_BYTES: usize = 65536;
+
 /// FTDI Multi-Protocol Synchronous Serial Engine (MPSSE) command builder.
 ///
 /// For details about the MPSSE read the [FTDI MPSSE Basics].
@@ -366,7 +439,7 @@ impl std::default::Default for MpsseSettings {
 /// command `[u8; N]` arrays at compile-time.
 ///
 /// [FTDI MPSSE Basics]: https://www.ftdichip.com/Support/Documents/AppNotes/AN_135_MPSSE_Basics.pdf
-/// [`write_all`]: FtdiCommon::write_all
+/// [`write_all`]: crate::transport::MpsseTransport::write_all
 pub struct MpsseCmdBuilder(pub Vec<u8>);
 
 impl MpsseCmdBuilder {
@@ -375,7 +448,7 @@ impl MpsseCmdBuilder {
     /// # Example
     ///
     /// ```
-    /// use libftd2xx::MpsseCmdBuilder;
+    /// use ftdi_embedded_hal::ftdimpsse::MpsseCmdBuilder;
     ///
     /// MpsseCmdBuilder::new();
     /// ```
@@ -388,7 +461,7 @@ impl MpsseCmdBuilder {
     /// # Example
     ///
     /// ```
-    /// use libftd2xx::MpsseCmdBuilder;
+    /// use ftdi_embedded_hal::ftdimpsse::MpsseCmdBuilder;
     ///
     /// MpsseCmdBuilder::with_vec(Vec::new());
     /// ```
@@ -400,14 +473,18 @@ impl MpsseCmdBuilder {
     ///
     /// # Example
     ///
-    /// ```no_run
-    /// use libftd2xx::{DeviceType, Ft232h, FtdiCommon, MpsseCmdBuilder};
+    /// ```
+    /// use ftdi_embedded_hal::chip::ChipType;
+    /// use ftdi_embedded_hal::emulator::MpsseEmulator;
+    /// use ftdi_embedded_hal::ftdimpsse::MpsseCmdBuilder;
+    /// use ftdi_embedded_hal::transport::{BitMode, MpsseTransport};
     ///
-    /// let cmd = MpsseCmdBuilder::new().set_clock(100_000, DeviceType::FT232H);
+    /// let (cmd, _) = MpsseCmdBuilder::new().set_clock(100_000, ChipType::FT232H)?;
     ///
-    /// let mut ft = Ft232h::with_serial_number("FT5AVX6B")?;
+    /// let mut ft = MpsseEmulator::new();
+    /// ft.set_bitmode(0, BitMode::Mpsse)?;
     /// ft.write_all(cmd.as_slice())?;
-    /// # Ok::<(), std::boxed::Box<dyn std::error::Error>>(())
+    /// # Ok::<(), ftdi_embedded_hal::error::X232Error>(())
     /// ```
     pub fn as_slice(&self) -> &[u8] {
         self.0.as_slice()
@@ -417,15 +494,17 @@ impl MpsseCmdBuilder {
     ///
     /// # Example
     ///
-    /// ```no_run
-    /// use libftd2xx::{Ft232h, FtdiCommon, FtdiMpsse, MpsseCmdBuilder};
+    /// ```
+    /// use ftdi_embedded_hal::emulator::MpsseEmulator;
+    /// use ftdi_embedded_hal::ftdimpsse::MpsseCmdBuilder;
+    /// use ftdi_embedded_hal::transport::{BitMode, MpsseTransport};
     ///
     /// let cmd = MpsseCmdBuilder::new().enable_loopback();
     ///
-    /// let mut ft = Ft232h::with_serial_number("FT5AVX6B")?;
-    /// ft.initialize_mpsse_default()?;
+    /// let mut ft = MpsseEmulator::new();
+    /// ft.set_bitmode(0, BitMode::Mpsse)?;
     /// ft.write_all(cmd.as_slice())?;
-    /// # Ok::<(), std::boxed::Box<dyn std::error::Error>>(())
+    /// # Ok::<(), ftdi_embedded_hal::error::X232Error>(())
     /// ```
     pub fn enable_loopback(mut self) -> Self {
         self.0.push(MpsseCmd::EnableLoopback.into());
@@ -436,21 +515,70 @@ impl MpsseCmdBuilder {
     ///
     /// # Example
     ///
-    /// ```no_run
-    /// use libftd2xx::{Ft232h, FtdiCommon, FtdiMpsse, MpsseCmdBuilder};
+    /// ```
+    /// use ftdi_embedded_hal::emulator::MpsseEmulator;
+    /// use ftdi_embedded_hal::ftdimpsse::MpsseCmdBuilder;
+    /// use ftdi_embedded_hal::transport::{BitMode, MpsseTransport};
     ///
     /// let cmd = MpsseCmdBuilder::new().disable_loopback();
     ///
-    /// let mut ft = Ft232h::with_serial_number("FT5AVX6B")?;
-    /// ft.initialize_mpsse_default()?;
+    /// let mut ft = MpsseEmulator::new();
+    /// ft.set_bitmode(0, BitMode::Mpsse)?;
     /// ft.write_all(cmd.as_slice())?;
-    /// # Ok::<(), std::boxed::Box<dyn std::error::Error>>(())
+    /// # Ok::<(), ftdi_embedded_hal::error::X232Error>(())
     /// ```
     pub fn disable_loopback(mut self) -> Self {
         self.0.push(MpsseCmd::DisableLoopback.into());
         self
     }
 
+    /// Set the clock to the highest frequency not above `hz` the chip can
+    /// make, see [`Capabilities::clock`].
+    ///
+    /// Returns the command together with the frequency actually set in Hz,
+    /// or [`ErrorKind::InvalidClock`] if the chip can not reach `hz`.
+    ///
+    /// The divide-by-5 prescaler is switched by the command as well, on the
+    /// chips which have it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ftdi_embedded_hal::chip::ChipType;
+    /// use ftdi_embedded_hal::ftdimpsse::MpsseCmdBuilder;
+    ///
+    /// let (cmd, hz) = MpsseCmdBuilder::new().set_clock(4_500_000, ChipType::FT232H)?;
+    ///
+    /// assert_eq!(hz, 4_285_714);
+    /// assert_eq!(cmd.as_slice(), [0x8a, 0x86, 0x06, 0x00]);
+    /// # Ok::<(), ftdi_embedded_hal::error::X232Error>(())
+    /// ```
+    ///
+    /// [`Capabilities::clock`]: crate::chip::Capabilities::clock
+    pub fn set_clock(self, hz: u32, chip: ChipType) -> Result<(MpsseCmdBuilder, u32)> {
+        let clock = chip
+            .capabilities()
+            .clock(hz)
+            .ok_or(X232Error::HAL(ErrorKind::InvalidClock))?;
+
+        Ok((self.clock(clock), clock.frequency))
+    }
+
+    pub(crate) fn clock(mut self, clock: Clock) -> Self {
+        match clock.divide_by_5 {
+            Some(true) => self.0.push(MpsseCmd::EnableClockDivide.into()),
+            Some(false) => self.0.push(MpsseCmd::DisableClockDivide.into()),
+            None => {}
+        }
+
+        self.0.extend_from_slice(&[
+            MpsseCmd::SetClockFrequency.into(),
+            (clock.divisor & 0xff) as u8,
+            (clock.divisor >> 8) as u8,
+        ]);
+        self
+    }
+
     /// Disable 3 phase data clocking.
     ///
     /// This is only avaliable on FTx232H devices.
@@ -464,15 +592,17 @@ impl MpsseCmdBuilder {
     ///
     /// # Example
     ///
-    /// ```no_run
-    /// use libftd2xx::{Ft232h, FtdiCommon, FtdiMpsse, MpsseCmdBuilder};
+    /// ```
+    /// use ftdi_embedded_hal::emulator::MpsseEmulator;
+    /// use ftdi_embedded_hal::ftdimpsse::MpsseCmdBuilder;
+    /// use ftdi_embedded_hal::transport::{BitMode, MpsseTransport};
     ///
     /// let cmd = MpsseCmdBuilder::new().disable_3phase_data_clocking();
     ///
-    /// let mut ft = Ft232h::with_serial_number("FT5AVX6B")?;
-    /// ft.initialize_mpsse_default()?;
+    /// let mut ft = MpsseEmulator::new();
+    /// ft.set_bitmode(0, BitMode::Mpsse)?;
     /// ft.write_all(cmd.as_slice())?;
-    /// # Ok::<(), std::boxed::Box<dyn std::error::Error>>(())
+    /// # Ok::<(), ftdi_embedded_hal::error::X232Error>(())
     /// ```
     pub fn disable_3phase_data_clocking(mut self) -> Self {
         self.0.push(MpsseCmd::Disable3PhaseClocking.into());
@@ -495,15 +625,17 @@ impl MpsseCmdBuilder {
     ///
     /// # Example
     ///
-    /// ```no_run
-    /// use libftd2xx::{Ft232h, FtdiCommon, FtdiMpsse, MpsseCmdBuilder};
+    /// ```
+    /// use ftdi_embedded_hal::emulator::MpsseEmulator;
+    /// use ftdi_embedded_hal::ftdimpsse::MpsseCmdBuilder;
+    /// use ftdi_embedded_hal::transport::{BitMode, MpsseTransport};
     ///
     /// let cmd = MpsseCmdBuilder::new().enable_3phase_data_clocking();
     ///
-    /// let mut ft = Ft232h::with_serial_number("FT5AVX6B")?;
-    /// ft.initialize_mpsse_default()?;
+    /// let mut ft = MpsseEmulator::new();
+    /// ft.set_bitmode(0, BitMode::Mpsse)?;
     /// ft.write_all(cmd.as_slice())?;
-    /// # Ok::<(), std::boxed::Box<dyn std::error::Error>>(())
+    /// # Ok::<(), ftdi_embedded_hal::error::X232Error>(())
     /// ```
     pub fn enable_3phase_data_clocking(mut self) -> Self {
         self.0.push(MpsseCmd::Enable3PhaseClocking.into());
@@ -518,7 +650,7 @@ impl MpsseCmdBuilder {
         self
     }
 
-    /// Enable adaptive data clocking.
+    /// Disable adaptive data clocking.
     ///
     /// This is only available on FTx232H devices.
     pub fn disable_adaptive_data_clocking(mut self) -> Self {
@@ -526,6 +658,21 @@ impl MpsseCmdBuilder {
         self
     }
 
+    /// Turn output pins into open-drain ones: a pin with its bit set in the
+    /// mask is only driven low, it is tri-stated when set high.
+    ///
+    /// This is only available on FT232H devices.
+    ///
+    /// # Arguments
+    ///
+    /// * `lower` - Mask for the lower byte (0-7) pins.
+    /// * `upper` - Mask for the upper byte (8-15) pins.
+    pub fn enable_drive_only_zero(mut self, lower: u8, upper: u8) -> Self {
+        self.0
+            .extend_from_slice(&[MpsseCmd::EnableDriveOnlyZero.into(), lower, upper]);
+        self
+    }
+
     /// Set the pin direction and state of the lower byte (0-7) GPIO pins on the
     /// MPSSE interface.
     ///
@@ -540,17 +687,19 @@ impl MpsseCmdBuilder {
     ///
     /// # Example
     ///
-    /// ```no_run
-    /// use libftd2xx::{Ft232h, FtdiCommon, FtdiMpsse, MpsseCmdBuilder};
+    /// ```
+    /// use ftdi_embedded_hal::emulator::MpsseEmulator;
+    /// use ftdi_embedded_hal::ftdimpsse::MpsseCmdBuilder;
+    /// use ftdi_embedded_hal::transport::{BitMode, MpsseTransport};
     ///
     /// let cmd = MpsseCmdBuilder::new()
     ///     .set_gpio_lower(0xFF, 0xFF)
     ///     .set_gpio_lower(0x00, 0xFF);
     ///
-    /// let mut ft = Ft232h::with_serial_number("FT5AVX6B")?;
-    /// ft.initialize_mpsse_default()?;
+    /// let mut ft = MpsseEmulator::new();
+    /// ft.set_bitmode(0, BitMode::Mpsse)?;
     /// ft.write_all(cmd.as_slice())?;
-    /// # Ok::<(), std::boxed::Box<dyn std::error::Error>>(())
+    /// # Ok::<(), ftdi_embedded_hal::error::X232Error>(())
     /// ```
     pub fn set_gpio_lower(mut self, state: u8, direction: u8) -> Self {
         self.0
@@ -578,17 +727,19 @@ impl MpsseCmdBuilder {
     ///
     /// # Example
     ///
-    /// ```no_run
-    /// use libftd2xx::{Ft232h, FtdiCommon, FtdiMpsse, MpsseCmdBuilder};
+    /// ```
+    /// use ftdi_embedded_hal::emulator::MpsseEmulator;
+    /// use ftdi_embedded_hal::ftdimpsse::MpsseCmdBuilder;
+    /// use ftdi_embedded_hal::transport::{BitMode, MpsseTransport};
     ///
     /// let cmd = MpsseCmdBuilder::new()
     ///     .set_gpio_upper(0xFF, 0xFF)
     ///     .set_gpio_upper(0x00, 0xFF);
     ///
-    /// let mut ft = Ft232h::with_serial_number("FT5AVX6B")?;
-    /// ft.initialize_mpsse_default()?;
+    /// let mut ft = MpsseEmulator::new();
+    /// ft.set_bitmode(0, BitMode::Mpsse)?;
     /// ft.write_all(cmd.as_slice())?;
-    /// # Ok::<(), std::boxed::Box<dyn std::error::Error>>(())
+    /// # Ok::<(), ftdi_embedded_hal::error::X232Error>(())
     /// ```
     pub fn set_gpio_upper(mut self, state: u8, direction: u8) -> Self {
         self.0
@@ -601,18 +752,20 @@ impl MpsseCmdBuilder {
     ///
     /// # Example
     ///
-    /// ```no_run
-    /// use libftd2xx::{Ft232h, FtdiCommon, FtdiMpsse, MpsseCmdBuilder};
+    /// ```
+    /// use ftdi_embedded_hal::emulator::MpsseEmulator;
+    /// use ftdi_embedded_hal::ftdimpsse::MpsseCmdBuilder;
+    /// use ftdi_embedded_hal::transport::{BitMode, MpsseTransport};
     ///
     /// let cmd = MpsseCmdBuilder::new().gpio_lower().send_immediate();
     ///
-    /// let mut ft = Ft232h::with_serial_number("FT5AVX6B")?;
-    /// ft.initialize_mpsse_default()?;
+    /// let mut ft = MpsseEmulator::new();
+    /// ft.set_bitmode(0, BitMode::Mpsse)?;
     /// ft.write_all(cmd.as_slice())?;
     /// let mut buf: [u8; 1] = [0; 1];
-    /// ft.read_all(&mut buf)?;
+    /// ft.read_exact(&mut buf)?;
     /// println!("GPIO lower state: 0x{:02X}", buf[0]);
-    /// # Ok::<(), std::boxed::Box<dyn std::error::Error>>(())
+    /// # Ok::<(), ftdi_embedded_hal::error::X232Error>(())
     /// ```
     pub fn gpio_lower(mut self) -> Self {
         self.0.push(MpsseCmd::GetDataBitsLowbyte.into());
@@ -627,21 +780,23 @@ impl MpsseCmdBuilder {
     ///
     /// # Example
     ///
-    /// ```no_run
-    /// use libftd2xx::{Ft232h, FtdiCommon, FtdiMpsse, MpsseCmdBuilder};
+    /// ```
+    /// use ftdi_embedded_hal::emulator::MpsseEmulator;
+    /// use ftdi_embedded_hal::ftdimpsse::MpsseCmdBuilder;
+    /// use ftdi_embedded_hal::transport::{BitMode, MpsseTransport};
     ///
     /// let cmd = MpsseCmdBuilder::new().gpio_upper().send_immediate();
     ///
-    /// let mut ft = Ft232h::with_serial_number("FT5AVX6B")?;
-    /// ft.initialize_mpsse_default()?;
+    /// let mut ft = MpsseEmulator::new();
+    /// ft.set_bitmode(0, BitMode::Mpsse)?;
     /// ft.write_all(cmd.as_slice())?;
     /// let mut buf: [u8; 1] = [0; 1];
-    /// ft.read_all(&mut buf)?;
+    /// ft.read_exact(&mut buf)?;
     /// println!("GPIO upper state: 0x{:02X}", buf[0]);
-    /// # Ok::<(), std::boxed::Box<dyn std::error::Error>>(())
+    /// # Ok::<(), ftdi_embedded_hal::error::X232Error>(())
     /// ```
     ///
-    /// [`set_gpio_upper`]: FtdiMpsse::set_gpio_upper
+    /// [`set_gpio_upper`]: MpsseCmdBuilder::set_gpio_upper
     pub fn gpio_upper(mut self) -> Self {
         self.0.push(MpsseCmd::GetDataBitsHighbyte.into());
         self
@@ -652,7 +807,7 @@ impl MpsseCmdBuilder {
     /// # Example
     ///
     /// ```
-    /// use libftd2xx::MpsseCmdBuilder;
+    /// use ftdi_embedded_hal::ftdimpsse::MpsseCmdBuilder;
     ///
     /// let cmd = MpsseCmdBuilder::new()
     ///     .set_gpio_upper(0xFF, 0xFF)
@@ -669,7 +824,7 @@ impl MpsseCmdBuilder {
     /// # Example
     ///
     /// ```
-    /// use libftd2xx::{ClockData, MpsseCmdBuilder};
+    /// use ftdi_embedded_hal::ftdimpsse::{ClockData, MpsseCmdBuilder};
     ///
     /// // Assume a "chip ready" signal is connected to GPIOL1. This signal is pulled high
     /// // shortly after AD3 (chip select) is pulled low. Data will not be clocked out until
@@ -691,7 +846,7 @@ impl MpsseCmdBuilder {
     /// # Example
     ///
     /// ```
-    /// use libftd2xx::{ClockData, MpsseCmdBuilder};
+    /// use ftdi_embedded_hal::ftdimpsse::{ClockData, MpsseCmdBuilder};
     ///
     /// // Assume a "chip ready" signal is connected to GPIOL1. This signal is pulled low
     /// // shortly after AD3 (chip select) is pulled low. Data will not be clocked out until
@@ -713,17 +868,12 @@ impl MpsseCmdBuilder {
     /// This will clock out bytes on TDI/DO.
     /// No data is clocked into the device on TDO/DI.
     ///
-    /// This will panic for data lengths greater than `u16::MAX + 1`.
+    /// Data longer than [`MAX_CLOCK_BYTES`] is split into several commands.
     pub fn clock_data_out(mut self, mode: ClockDataOut, data: &[u8]) -> Self {
-        let mut len = data.len();
-        assert!(len <= 65536, "data length cannot exceed u16::MAX + 1");
-        if len == 0 {
-            return self;
+        for chunk in data.chunks(MAX_CLOCK_BYTES) {
+            self = self.clock_len(mode.into(), chunk.len());
+            self.0.extend_from_slice(chunk);
         }
-        len -= 1;
-        self.0
-            .extend_from_slice(&[mode.into(), (len & 0xFF) as u8, ((len >> 8) & 0xFF) as u8]);
-        self.0.extend_from_slice(&data);
         self
     }
 
@@ -736,31 +886,25 @@ impl MpsseCmdBuilder {
     ///
     /// * `mode` - Data clocking mode.
     /// * `len` - Number of bytes to clock in.
-    ///           This will panic for values greater than `u16::MAX + 1`.
+    ///   Lengths above [`MAX_CLOCK_BYTES`] are split into several commands.
     pub fn clock_data_in(mut self, mode: ClockDataIn, mut len: usize) -> Self {
-        assert!(len <= 65536, "data length cannot exceed u16::MAX + 1");
-        if len == 0 {
-            return self;
+        while len > 0 {
+            let chunk = len.min(MAX_CLOCK_BYTES);
+            self = self.clock_len(mode.into(), chunk);
+            len -= chunk;
         }
-        len -= 1;
-        self.0
-            .extend_from_slice(&[mode.into(), (len & 0xFF) as u8, ((len >> 8) & 0xFF) as u8]);
         self
     }
 
     /// Clock data in and out simultaneously.
     ///
-    /// This will panic for data lengths greater than `u16::MAX + 1`.
+    /// Data longer than [`MAX_CLOCK_BYTES`] is split into several commands,
+    /// the bytes read back arrive in order.
     pub fn clock_data(mut self, mode: ClockData, data: &[u8]) -> Self {
-        let mut len = data.len();
-        assert!(len <= 65536, "data length cannot exceed u16::MAX + 1");
-        if len == 0 {
-            return self;
+        for chunk in data.chunks(MAX_CLOCK_BYTES) {
+            self = self.clock_len(mode.into(), chunk.len());
+            self.0.extend_from_slice(chunk);
         }
-        len -= 1;
-        self.0
-            .extend_from_slice(&[mode.into(), (len & 0xFF) as u8, ((len >> 8) & 0xFF) as u8]);
-        self.0.extend_from_slice(&data);
         self
     }
 
@@ -815,6 +959,184 @@ impl MpsseCmdBuilder {
         self.0.extend_from_slice(&[mode.into(), len, data]);
         self
     }
+
+    /// Clock bits out on TMS, LSB first, used for JTAG state transitions.
+    ///
+    /// TDI/DO is held at `tdi` while TMS is clocked.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - TMS clocking mode.
+    /// * `data` - TMS bits.
+    /// * `tdi` - TDI/DO level.
+    /// * `len` - Number of bits to clock out, this will panic for values
+    ///   greater than 7.
+    pub fn clock_tms_out(mut self, mode: ClockTMSOut, data: u8, tdi: bool, mut len: u8) -> Self {
+        assert!(len <= 7, "data length cannot exceed 7");
+        if len == 0 {
+            return self;
+        }
+        len -= 1;
+        self.0
+            .extend_from_slice(&[mode.into(), len, tms_byte(data, tdi)]);
+        self
+    }
+
+    /// Clock bits out on TMS and in on TDO/DI, one byte is read back.
+    ///
+    /// TDI/DO is held at `tdi` while TMS is clocked.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - TMS clocking mode.
+    /// * `data` - TMS bits.
+    /// * `tdi` - TDI/DO level.
+    /// * `len` - Number of bits to clock, this will panic for values greater
+    ///   than 7.
+    pub fn clock_tms(mut self, mode: ClockTMS, data: u8, tdi: bool, mut len: u8) -> Self {
+        assert!(len <= 7, "data length cannot exceed 7");
+        if len == 0 {
+            return self;
+        }
+        len -= 1;
+        self.0
+            .extend_from_slice(&[mode.into(), len, tms_byte(data, tdi)]);
+        self
+    }
+
+    /// Clock without transferring data.
+    ///
+    /// This is only available on FTx232H devices.
+    ///
+    /// # Arguments
+    ///
+    /// * `len` - Number of clock pulses, this will panic for values greater
+    ///   than 8.
+    pub fn clock_bits_no_data(mut self, mut len: u8) -> Self {
+        assert!(len <= 8, "data length cannot exceed 8");
+        if len == 0 {
+            return self;
+        }
+        len -= 1;
+        self.0
+            .extend_from_slice(&[MpsseCmd::ClockBitsNoData.into(), len]);
+        self
+    }
+
+    /// Clock `len` times 8 pulses without transferring data.
+    ///
+    /// This is only available on FTx232H devices.
+    ///
+    /// This will panic for lengths greater than `u16::MAX + 1`.
+    pub fn clock_bytes_no_data(self, len: usize) -> Self {
+        self.clock_bytes(MpsseCmd::ClockBytesNoData, len)
+    }
+
+    /// Clock continuously until GPIOL1 is high, no data is transferred.
+    ///
+    /// This is only available on FTx232H devices.
+    pub fn clock_until_io_high(mut self) -> Self {
+        self.0.push(MpsseCmd::ClockUntilIOHigh.into());
+        self
+    }
+
+    /// Clock continuously until GPIOL1 is low, no data is transferred.
+    ///
+    /// This is only available on FTx232H devices.
+    pub fn clock_until_io_low(mut self) -> Self {
+        self.0.push(MpsseCmd::ClockUntilIOLow.into());
+        self
+    }
+
+    /// Clock `len` times 8 pulses or until GPIOL1 is high, no data is
+    /// transferred.
+    ///
+    /// This is only available on FTx232H devices.
+    ///
+    /// This will panic for lengths greater than `u16::MAX + 1`.
+    pub fn clock_bytes_or_io_high(self, len: usize) -> Self {
+        self.clock_bytes(MpsseCmd::ClockBytesOrIOHigh, len)
+    }
+
+    /// Clock `len` times 8 pulses or until GPIOL1 is low, no data is
+    /// transferred.
+    ///
+    /// This is only available on FTx232H devices.
+    ///
+    /// This will panic for lengths greater than `u16::MAX + 1`.
+    pub fn clock_bytes_or_io_low(self, len: usize) -> Self {
+        self.clock_bytes(MpsseCmd::ClockBytesOrIOLow, len)
+    }
+
+    fn clock_bytes(self, cmd: MpsseCmd, len: usize) -> Self {
+        assert!(
+            len <= MAX_CLOCK_BYTES,
+            "data length cannot exceed u16::MAX + 1"
+        );
+        if len == 0 {
+            return self;
+        }
+        self.clock_len(cmd.into(), len)
+    }
+
+    // Opcode and length field of a byte clocking command, `len` in
+    // 1..=MAX_CLOCK_BYTES
+    fn clock_len(mut self, op: u8, len: usize) -> Self {
+        let len = len - 1;
+        self.0
+            .extend_from_slice(&[op, (len & 0xFF) as u8, ((len >> 8) & 0xFF) as u8]);
+        self
+    }
+
+    /// Read a byte at an 8-bit address of the CPU bus, one byte is read
+    /// back.
+    ///
+    /// CPU commands are only available in MCU host bus emulation mode.
+    pub fn cpu_read_short(mut self, addr: u8) -> Self {
+        self.0
+            .extend_from_slice(&[MpsseCmd::CpuReadShort.into(), addr]);
+        self
+    }
+
+    /// Read a byte at a 16-bit address of the CPU bus, one byte is read
+    /// back.
+    ///
+    /// CPU commands are only available in MCU host bus emulation mode.
+    pub fn cpu_read_extended(mut self, addr: u16) -> Self {
+        self.0.extend_from_slice(&[
+            MpsseCmd::CpuReadExtended.into(),
+            (addr >> 8) as u8,
+            (addr & 0xFF) as u8,
+        ]);
+        self
+    }
+
+    /// Write a byte at an 8-bit address of the CPU bus.
+    ///
+    /// CPU commands are only available in MCU host bus emulation mode.
+    pub fn cpu_write_short(mut self, addr: u8, data: u8) -> Self {
+        self.0
+            .extend_from_slice(&[MpsseCmd::CpuWriteShort.into(), addr, data]);
+        self
+    }
+
+    /// Write a byte at a 16-bit address of the CPU bus.
+    ///
+    /// CPU commands are only available in MCU host bus emulation mode.
+    pub fn cpu_write_extended(mut self, addr: u16, data: u8) -> Self {
+        self.0.extend_from_slice(&[
+            MpsseCmd::CpuWriteExtended.into(),
+            (addr >> 8) as u8,
+            (addr & 0xFF) as u8,
+            data,
+        ]);
+        self
+    }
+}
+
+// TMS bits 0..6 are clocked out, bit 7 is held on TDI/DO
+fn tms_byte(data: u8, tdi: bool) -> u8 {
+    (data & 0x7F) | ((tdi as u8) << 7)
 }
 
 /// Construct an MPSSE command array at compile-time.
@@ -824,15 +1146,17 @@ impl MpsseCmdBuilder {
 /// syntax of each block creates a fixed-length `[u8; N]` array that is bound with `let` or
 /// `const`[^const_note].
 ///
+/// The commands are the same bytes as the [`MpsseCmdBuilder`] methods of the same names make.
+///
 /// [^const_note]: In `const` bindings, all values used as command parameters and data must be const.
 ///
 /// # Syntax
 ///
-/// ```compile_fail
+/// ```text
 /// mpsse! { let command_data = { command1(); command2(); /* ... */ commandN(); }; }
 /// ```
 /// or
-/// ```compile_fail
+/// ```text
 /// mpsse! { let (command_data, READ_LEN) = { command1(); command2(); /* ... */ commandN(); }; }
 /// ```
 /// The second form provides the caller with a constant size value of the expected data length to
@@ -844,6 +1168,9 @@ impl MpsseCmdBuilder {
 /// * [`disable_loopback()`][`MpsseCmdBuilder::disable_loopback`]
 /// * [`enable_3phase_data_clocking()`][`MpsseCmdBuilder::enable_3phase_data_clocking`]
 /// * [`disable_3phase_data_clocking()`][`MpsseCmdBuilder::disable_3phase_data_clocking`]
+/// * [`enable_adaptive_data_clocking()`][`MpsseCmdBuilder::enable_adaptive_data_clocking`]
+/// * [`disable_adaptive_data_clocking()`][`MpsseCmdBuilder::disable_adaptive_data_clocking`]
+/// * [`enable_drive_only_zero(lower: u8, upper: u8)`][`MpsseCmdBuilder::enable_drive_only_zero`]
 /// * [`set_gpio_lower(state: u8, direction: u8)`][`MpsseCmdBuilder::set_gpio_lower`]
 /// * [`set_gpio_upper(state: u8, direction: u8)`][`MpsseCmdBuilder::set_gpio_upper`]
 /// * [`gpio_lower() -> usize`][`MpsseCmdBuilder::gpio_lower`]
@@ -852,21 +1179,35 @@ impl MpsseCmdBuilder {
 /// * [`wait_on_io_high()`][`MpsseCmdBuilder::wait_on_io_high`]
 /// * [`wait_on_io_low()`][`MpsseCmdBuilder::wait_on_io_low`]
 /// * [`clock_data_out(mode: ClockDataOut, data: [u8])`][`MpsseCmdBuilder::clock_data_out`]
-/// * [`clock_data_in(mode: ClockDataIn, len: u16) -> std::ops::Range<usize>`][`MpsseCmdBuilder::clock_data_in`]
+/// * [`clock_data_in(mode: ClockDataIn, len: usize) -> std::ops::Range<usize>`][`MpsseCmdBuilder::clock_data_in`]
 /// * [`clock_data(mode: ClockData, data: [u8]) -> std::ops::Range<usize>`][`MpsseCmdBuilder::clock_data`]
 /// * [`clock_bits_out(mode: ClockBitsOut, data: u8, len: u8)`][`MpsseCmdBuilder::clock_bits_out`]
 /// * [`clock_bits_in(mode: ClockBitsIn, len: u8) -> usize`][`MpsseCmdBuilder::clock_bits_in`]
 /// * [`clock_bits(mode: ClockBits, data: u8, len: u8) -> usize`][`MpsseCmdBuilder::clock_bits`]
+/// * [`clock_tms_out(mode: ClockTMSOut, data: u8, tdi: bool, len: u8)`][`MpsseCmdBuilder::clock_tms_out`]
+/// * [`clock_tms(mode: ClockTMS, data: u8, tdi: bool, len: u8) -> usize`][`MpsseCmdBuilder::clock_tms`]
+/// * [`clock_bits_no_data(len: u8)`][`MpsseCmdBuilder::clock_bits_no_data`]
+/// * [`clock_bytes_no_data(len: usize)`][`MpsseCmdBuilder::clock_bytes_no_data`]
+/// * [`clock_until_io_high()`][`MpsseCmdBuilder::clock_until_io_high`]
+/// * [`clock_until_io_low()`][`MpsseCmdBuilder::clock_until_io_low`]
+/// * [`clock_bytes_or_io_high(len: usize)`][`MpsseCmdBuilder::clock_bytes_or_io_high`]
+/// * [`clock_bytes_or_io_low(len: usize)`][`MpsseCmdBuilder::clock_bytes_or_io_low`]
+/// * [`cpu_read_short(addr: u8) -> usize`][`MpsseCmdBuilder::cpu_read_short`]
+/// * [`cpu_read_extended(addr: u16) -> usize`][`MpsseCmdBuilder::cpu_read_extended`]
+/// * [`cpu_write_short(addr: u8, data: u8)`][`MpsseCmdBuilder::cpu_write_short`]
+/// * [`cpu_write_extended(addr: u16, data: u8)`][`MpsseCmdBuilder::cpu_write_extended`]
 ///
 /// Command pseudo-statements that read data from the device may optionally have the form:
 /// ```
-/// # use libftd2xx::{mpsse, ClockDataIn};
+/// # use ftdi_embedded_hal::{ftdimpsse::ClockDataIn, mpsse};
 /// mpsse! {
 ///     // command_data and DATA_IN_RANGE are both declared in the scope of the macro expansion.
 ///     let command_data = {
 ///         const DATA_IN_RANGE = clock_data_in(ClockDataIn::MsbNeg, 3);
 ///     };
 /// }
+/// # assert_eq!(command_data, [0x24, 0x02, 0x00]);
+/// # assert_eq!(DATA_IN_RANGE, 0..3);
 /// ```
 /// This provides a constant [`Range`][`std::ops::Range`] or [`usize`] index value that may be used
 /// to subscript the data read from the device.
@@ -877,10 +1218,21 @@ impl MpsseCmdBuilder {
 ///
 /// # Asserts
 ///
-/// For `let` bindings, the standard [`assert`] macro is used for validating parameter size inputs.
-/// For `const` bindings, [`const_assert`][`static_assertions::const_assert`] is used instead.
+/// Lengths are checked the same way as by [`MpsseCmdBuilder`], but a zero length is an error
+/// instead of an empty command. For `let` bindings, the standard [`assert`] macro is used. For
+/// `const` bindings, the assert is evaluated in a `const` item, so invalid parameters fail to
+/// compile:
+///
+/// ```compile_fail
+/// # use ftdi_embedded_hal::{ftdimpsse::ClockBitsOut, mpsse};
+/// mpsse! {
+///     const COMMAND_DATA = {
+///         clock_bits_out(ClockBitsOut::MsbNeg, 0xFF, 9);
+///     };
+/// }
+/// ```
 ///
-/// `const_assert` lacks the ability to provide meaningful compile errors, so it may be useful
+/// The compile error of a `const` assert points into the macro expansion, so it may be useful
 /// to temporarily use a `let` binding within function scope to diagnose failing macro expansions.
 ///
 /// # User Abstractions
@@ -895,7 +1247,6 @@ impl MpsseCmdBuilder {
 /// device-specific abstractions.
 ///
 /// ```
-/// # use libftd2xx::mpsse;
 /// macro_rules! mpsse {
 ///     // Practical abstraction of CS line for SPI devices.
 ///     ($passthru:tt {cs_low(); $($tail:tt)*} -> [$($out:tt)*]) => {
@@ -911,21 +1262,25 @@ impl MpsseCmdBuilder {
 ///         } -> [$($out)*]);
 ///     };
 ///
-///     // Hypothetical device-specific command. Leverages both user and libftd2xx commands.
+///     // Hypothetical device-specific command. Leverages both user and crate commands.
 ///     ($passthru:tt
 ///      {const $idx_id:ident = command_42([$($data:expr),* $(,)*]); $($tail:tt)*} ->
 ///      [$($out:tt)*]) => {
 ///         mpsse!($passthru {
 ///             cs_low();
-///             const $idx_id = clock_data(::libftd2xx::ClockData::MsbPosIn, [0x42, $($data,)*]);
+///             const $idx_id = clock_data(
+///                 ::ftdi_embedded_hal::ftdimpsse::ClockData::MsbPosIn,
+///                 [0x42, $($data,)*]
+///             );
 ///             cs_high();
 ///             $($tail)*
 ///         } -> [$($out)*]);
 ///     };
 ///
-///     // Everything else handled by libftd2xx crate implementation.
+///     // Everything else handled by ftdi-embedded-hal crate implementation, braces make the
+///     // invocation valid both as an item and as an expression.
 ///     ($($tokens:tt)*) => {
-///         ::libftd2xx::mpsse!($($tokens)*);
+///         ::ftdi_embedded_hal::mpsse! { $($tokens)* }
 ///     };
 /// }
 ///
@@ -936,31 +1291,41 @@ impl MpsseCmdBuilder {
 ///         send_immediate();
 ///     };
 /// }
+/// # assert_eq!(
+/// #     COMMAND_DATA,
+/// #     [0x88, 0x80, 0x00, 0x0b, 0x31, 0x03, 0x00, 0x42, 11, 22, 33, 0x80, 0x08, 0x0b, 0x87]
+/// # );
+/// # assert_eq!(COMMAND_42_RESULT_RANGE, 0..4);
+/// # assert_eq!(READ_LEN, 4);
 /// ```
 ///
 /// # Example
 ///
-/// ```no_run
-/// use libftd2xx::{mpsse, ClockDataIn, ClockDataOut, Ft232h, FtdiCommon, FtdiMpsse};
+/// ```
+/// use ftdi_embedded_hal::emulator::MpsseEmulator;
+/// use ftdi_embedded_hal::ftdimpsse::{ClockData, ClockDataOut};
+/// use ftdi_embedded_hal::mpsse;
+/// use ftdi_embedded_hal::transport::{BitMode, MpsseTransport};
 ///
 /// mpsse! {
 ///     const (COMMAND_DATA, READ_LEN) = {
+///         enable_loopback();
 ///         set_gpio_lower(0xFA, 0xFB);
 ///         set_gpio_lower(0xF2, 0xFB);
 ///         clock_data_out(ClockDataOut::MsbNeg, [0x12, 0x34, 0x56]);
-///         const DATA_IN_RANGE = clock_data_in(ClockDataIn::MsbNeg, 3);
+///         const DATA_IN_RANGE = clock_data(ClockData::MsbNegIn, [0x78, 0x9A]);
 ///         set_gpio_lower(0xFA, 0xFB);
 ///         send_immediate();
 ///     };
 /// }
 ///
-/// let mut ft = Ft232h::with_serial_number("FT5AVX6B")?;
-/// ft.initialize_mpsse_default()?;
+/// let mut ft = MpsseEmulator::new();
+/// ft.set_bitmode(0, BitMode::Mpsse)?;
 /// ft.write_all(&COMMAND_DATA)?;
 /// let mut buf: [u8; READ_LEN] = [0; READ_LEN];
-/// ft.read_all(&mut buf)?;
-/// println!("Data slice in: {:?}", &buf[DATA_IN_RANGE]);
-/// # Ok::<(), std::boxed::Box<dyn std::error::Error>>(())
+/// ft.read_exact(&mut buf)?;
+/// assert_eq!(buf[DATA_IN_RANGE], [0x78, 0x9A]);
+/// # Ok::<(), ftdi_embedded_hal::error::X232Error>(())
 /// ```
 #[macro_export]
 macro_rules! mpsse {
@@ -971,15 +1336,13 @@ macro_rules! mpsse {
 
     // Assert that is selectively compile-time depending on let vs. const expansion.
     //
-    // Unfortunately, the compile-time error is not very helpful due to the lack of message and
-    // macro depth, but still ensures safe command construction.
-    //
-    // Temporarily running a let expansion can be helpful to diagnose errors.
+    // The compile-time error points into the macro expansion, temporarily running a let
+    // expansion can be helpful to diagnose errors.
     (@assert ((let, $_user_passthru:tt), $_read_len:expr), $e:expr, $msg:expr) => {
         ::std::assert!($e, $msg);
     };
-    (@assert ((const, $_user_passthru:tt), $_read_len:expr), $e:expr, $_msg:expr) => {
-        ::static_assertions::const_assert!($e);
+    (@assert ((const, $_user_passthru:tt), $_read_len:expr), $e:expr, $msg:expr) => {
+        const _: () = ::std::assert!($e, $msg);
     };
 
     // Unit rule
@@ -1026,72 +1389,75 @@ macro_rules! mpsse {
     // (($passthru:tt, $read_len:tt) {const $idx_id:ident = <FUNCTION NAME>(); $($tail:tt)*} -> [$($out:tt)*])
 
     ($passthru:tt {enable_loopback(); $($tail:tt)*} -> [$($out:tt)*]) => {
-        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::MpsseCmd::EnableLoopback as u8,]);
+        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::ftdimpsse::MpsseCmd::EnableLoopback as u8,]);
     };
     ($passthru:tt {disable_loopback(); $($tail:tt)*} -> [$($out:tt)*]) => {
-        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::MpsseCmd::DisableLoopback as u8,]);
+        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::ftdimpsse::MpsseCmd::DisableLoopback as u8,]);
     };
     ($passthru:tt {enable_3phase_data_clocking(); $($tail:tt)*} -> [$($out:tt)*]) => {
-        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::MpsseCmd::Enable3PhaseClocking as u8,]);
+        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::ftdimpsse::MpsseCmd::Enable3PhaseClocking as u8,]);
     };
     ($passthru:tt {disable_3phase_data_clocking(); $($tail:tt)*} -> [$($out:tt)*]) => {
-        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::MpsseCmd::Disable3PhaseClocking as u8,]);
+        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::ftdimpsse::MpsseCmd::Disable3PhaseClocking as u8,]);
     };
     ($passthru:tt {enable_adaptive_data_clocking(); $($tail:tt)*} -> [$($out:tt)*]) => {
-        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::MpsseCmd::EnableDataClocking as u8,]);
+        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::ftdimpsse::MpsseCmd::EnableAdaptiveClocking as u8,]);
     };
     ($passthru:tt {disable_adaptive_data_clocking(); $($tail:tt)*} -> [$($out:tt)*]) => {
-        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::MpsseCmd::DisableDataClocking as u8,]);
+        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::ftdimpsse::MpsseCmd::DisableAdaptiveClocking as u8,]);
+    };
+    ($passthru:tt {enable_drive_only_zero($lower:expr, $upper:expr); $($tail:tt)*} -> [$($out:tt)*]) => {
+        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::ftdimpsse::MpsseCmd::EnableDriveOnlyZero as u8, $lower as u8, $upper as u8,]);
     };
     ($passthru:tt {set_gpio_lower($state:expr, $direction:expr); $($tail:tt)*} -> [$($out:tt)*]) => {
-        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::MpsseCmd::SetDataBitsLowbyte as u8, $state as u8, $direction as u8,]);
+        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::ftdimpsse::MpsseCmd::SetDataBitsLowbyte as u8, $state as u8, $direction as u8,]);
     };
     ($passthru:tt {set_gpio_upper($state:expr, $direction:expr); $($tail:tt)*} -> [$($out:tt)*]) => {
-        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::MpsseCmd::SetDataBitsHighbyte as u8, $state as u8, $direction as u8,]);
+        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::ftdimpsse::MpsseCmd::SetDataBitsHighbyte as u8, $state as u8, $direction as u8,]);
     };
     (($passthru:tt, $read_len:tt) {gpio_lower(); $($tail:tt)*} -> [$($out:tt)*]) => {
-        mpsse!(($passthru, ($read_len + 1)) {$($tail)*} -> [$($out)* $crate::MpsseCmd::GetDataBitsLowbyte as u8,]);
+        mpsse!(($passthru, ($read_len + 1)) {$($tail)*} -> [$($out)* $crate::ftdimpsse::MpsseCmd::GetDataBitsLowbyte as u8,]);
     };
     (($passthru:tt, $read_len:tt) {const $idx_id:ident = gpio_lower(); $($tail:tt)*} -> [$($out:tt)*]) => {
         const $idx_id: usize = $read_len;
         mpsse!(($passthru, $read_len) {gpio_lower(); $($tail)*} -> [$($out)*]);
     };
     (($passthru:tt, $read_len:tt) {gpio_upper(); $($tail:tt)*} -> [$($out:tt)*]) => {
-        mpsse!(($passthru, ($read_len + 1)) {$($tail)*} -> [$($out)* $crate::MpsseCmd::GetDataBitsHighbyte as u8,]);
+        mpsse!(($passthru, ($read_len + 1)) {$($tail)*} -> [$($out)* $crate::ftdimpsse::MpsseCmd::GetDataBitsHighbyte as u8,]);
     };
     (($passthru:tt, $read_len:tt) {const $idx_id:ident = gpio_upper(); $($tail:tt)*} -> [$($out:tt)*]) => {
         const $idx_id: usize = $read_len;
         mpsse!(($passthru, $read_len) {gpio_upper(); $($tail)*} -> [$($out)*]);
     };
     ($passthru:tt {send_immediate(); $($tail:tt)*} -> [$($out:tt)*]) => {
-        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::MpsseCmd::SendImmediate as u8,]);
+        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::ftdimpsse::MpsseCmd::SendImmediate as u8,]);
     };
     ($passthru:tt {wait_on_io_high(); $($tail:tt)*} -> [$($out:tt)*]) => {
-        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::MpsseCmd::WaitOnIOHigh as u8,]);
+        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::ftdimpsse::MpsseCmd::WaitOnIOHigh as u8,]);
     };
     ($passthru:tt {wait_on_io_low(); $($tail:tt)*} -> [$($out:tt)*]) => {
-        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::MpsseCmd::WaitOnIOLow as u8,]);
+        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::ftdimpsse::MpsseCmd::WaitOnIOLow as u8,]);
     };
     ($passthru:tt {clock_data_out($mode:expr, [$($data:expr),* $(,)*]); $($tail:tt)*} -> [$($out:tt)*]) => {
-        mpsse!(@assert $passthru, (mpsse!(@count_elements $($data,)*) as usize > 0_usize && mpsse!(@count_elements $($data,)*) as usize <= 65536_usize), "data length must be in 1..=(u16::MAX + 1)");
-        mpsse!($passthru {$($tail)*} -> [$($out)* $mode as $crate::ClockDataOut as u8,
+        mpsse!(@assert $passthru, (mpsse!(@count_elements $($data,)*) > 0_usize && mpsse!(@count_elements $($data,)*) <= 65536_usize), "data length must be in 1..=(u16::MAX + 1)");
+        mpsse!($passthru {$($tail)*} -> [$($out)* $mode as $crate::ftdimpsse::ClockDataOut as u8,
         ((mpsse!(@count_elements $($data,)*) - 1) & 0xFF_usize) as u8,
         (((mpsse!(@count_elements $($data,)*) - 1) >> 8) & 0xFF_usize) as u8,
         $($data as u8,)*]);
     };
     (($passthru:tt, $read_len:tt) {clock_data_in($mode:expr, $len:expr); $($tail:tt)*} -> [$($out:tt)*]) => {
         mpsse!(@assert ($passthru, $read_len), (($len) as usize > 0_usize && ($len) as usize <= 65536_usize), "data length must be in 1..=(u16::MAX + 1)");
-        mpsse!(($passthru, ($read_len + ($len))) {$($tail)*} -> [$($out)* $mode as $crate::ClockDataIn as u8,
-        ((($len) - 1) & 0xFF_usize) as u8,
-        (((($len) - 1) >> 8) & 0xFF_usize) as u8,]);
+        mpsse!(($passthru, ($read_len + ($len) as usize)) {$($tail)*} -> [$($out)* $mode as $crate::ftdimpsse::ClockDataIn as u8,
+        ((($len) as usize - 1) & 0xFF_usize) as u8,
+        (((($len) as usize - 1) >> 8) & 0xFF_usize) as u8,]);
     };
     (($passthru:tt, $read_len:tt) {const $range_id:ident = clock_data_in($mode:expr, $len:expr); $($tail:tt)*} -> [$($out:tt)*]) => {
-        const $range_id: ::std::ops::Range<usize> = $read_len..$read_len + ($len);
+        const $range_id: ::std::ops::Range<usize> = $read_len..$read_len + ($len) as usize;
         mpsse!(($passthru, $read_len) {clock_data_in($mode, $len); $($tail)*} -> [$($out)*]);
     };
     (($passthru:tt, $read_len:tt) {clock_data($mode:expr, [$($data:expr),* $(,)*]); $($tail:tt)*} -> [$($out:tt)*]) => {
-        mpsse!(@assert ($passthru, $read_len), (mpsse!(@count_elements $($data,)*) as usize > 0_usize && mpsse!(@count_elements $($data,)*) as usize <= 65536_usize), "data length must be in 1..=(u16::MAX + 1)");
-        mpsse!(($passthru, ($read_len + mpsse!(@count_elements $($data,)*))) {$($tail)*} -> [$($out)* $mode as $crate::ClockData as u8,
+        mpsse!(@assert ($passthru, $read_len), (mpsse!(@count_elements $($data,)*) > 0_usize && mpsse!(@count_elements $($data,)*) <= 65536_usize), "data length must be in 1..=(u16::MAX + 1)");
+        mpsse!(($passthru, ($read_len + mpsse!(@count_elements $($data,)*))) {$($tail)*} -> [$($out)* $mode as $crate::ftdimpsse::ClockData as u8,
         ((mpsse!(@count_elements $($data,)*) - 1) & 0xFF_usize) as u8,
         (((mpsse!(@count_elements $($data,)*) - 1) >> 8) & 0xFF_usize) as u8,
         $($data as u8,)*]);
@@ -1101,25 +1467,86 @@ macro_rules! mpsse {
         mpsse!(($passthru, $read_len) {clock_data($mode, [$($data,)*]); $($tail)*} -> [$($out)*]);
     };
     ($passthru:tt {clock_bits_out($mode:expr, $data:expr, $len:expr); $($tail:tt)*} -> [$($out:tt)*]) => {
-        mpsse!(@assert $passthru, ($len as u8 > 0_u8 && $len as u8 <= 8_u8), "data length must be in 1..=8");
-        mpsse!($passthru {$($tail)*} -> [$($out)* $mode as $crate::ClockBitsOut as u8, (($len) - 1) as u8, $data as u8,]);
+        mpsse!(@assert $passthru, (($len) as u8 > 0_u8 && ($len) as u8 <= 8_u8), "data length must be in 1..=8");
+        mpsse!($passthru {$($tail)*} -> [$($out)* $mode as $crate::ftdimpsse::ClockBitsOut as u8, (($len) as u8 - 1), $data as u8,]);
     };
     (($passthru:tt, $read_len:tt) {clock_bits_in($mode:expr, $len:expr); $($tail:tt)*} -> [$($out:tt)*]) => {
-        mpsse!(@assert ($passthru, $read_len), ($len as u8 > 0_u8 && $len as u8 <= 8_u8), "data length must be in 1..=8");
-        mpsse!(($passthru, ($read_len + 1)) {$($tail)*} -> [$($out)* $mode as $crate::ClockBitsIn as u8, (($len) - 1) as u8,]);
+        mpsse!(@assert ($passthru, $read_len), (($len) as u8 > 0_u8 && ($len) as u8 <= 8_u8), "data length must be in 1..=8");
+        mpsse!(($passthru, ($read_len + 1)) {$($tail)*} -> [$($out)* $mode as $crate::ftdimpsse::ClockBitsIn as u8, (($len) as u8 - 1),]);
     };
     (($passthru:tt, $read_len:tt) {const $idx_id:ident = clock_bits_in($mode:expr, $len:expr); $($tail:tt)*} -> [$($out:tt)*]) => {
         const $idx_id: usize = $read_len;
         mpsse!(($passthru, $read_len) {clock_bits_in($mode, $len); $($tail)*} -> [$($out)*]);
     };
     (($passthru:tt, $read_len:tt) {clock_bits($mode:expr, $data:expr, $len:expr); $($tail:tt)*} -> [$($out:tt)*]) => {
-        mpsse!(@assert ($passthru, $read_len), ($len as u8 > 0_u8 && $len as u8 <= 8_u8), "data length must be in 1..=8");
-        mpsse!(($passthru, ($read_len + 1)) {$($tail)*} -> [$($out)* $mode as $crate::ClockBits as u8, (($len) - 1) as u8, $data as u8,]);
+        mpsse!(@assert ($passthru, $read_len), (($len) as u8 > 0_u8 && ($len) as u8 <= 8_u8), "data length must be in 1..=8");
+        mpsse!(($passthru, ($read_len + 1)) {$($tail)*} -> [$($out)* $mode as $crate::ftdimpsse::ClockBits as u8, (($len) as u8 - 1), $data as u8,]);
     };
     (($passthru:tt, $read_len:tt) {const $idx_id:ident = clock_bits($mode:expr, $data:expr, $len:expr); $($tail:tt)*} -> [$($out:tt)*]) => {
         const $idx_id: usize = $read_len;
         mpsse!(($passthru, $read_len) {clock_bits($mode, $data, $len); $($tail)*} -> [$($out)*]);
     };
+    ($passthru:tt {clock_tms_out($mode:expr, $data:expr, $tdi:expr, $len:expr); $($tail:tt)*} -> [$($out:tt)*]) => {
+        mpsse!(@assert $passthru, (($len) as u8 > 0_u8 && ($len) as u8 <= 7_u8), "data length must be in 1..=7");
+        mpsse!($passthru {$($tail)*} -> [$($out)* $mode as $crate::ftdimpsse::ClockTMSOut as u8, (($len) as u8 - 1),
+        (($data as u8) & 0x7F_u8) | (($tdi as u8) << 7),]);
+    };
+    (($passthru:tt, $read_len:tt) {clock_tms($mode:expr, $data:expr, $tdi:expr, $len:expr); $($tail:tt)*} -> [$($out:tt)*]) => {
+        mpsse!(@assert ($passthru, $read_len), (($len) as u8 > 0_u8 && ($len) as u8 <= 7_u8), "data length must be in 1..=7");
+        mpsse!(($passthru, ($read_len + 1)) {$($tail)*} -> [$($out)* $mode as $crate::ftdimpsse::ClockTMS as u8, (($len) as u8 - 1),
+        (($data as u8) & 0x7F_u8) | (($tdi as u8) << 7),]);
+    };
+    (($passthru:tt, $read_len:tt) {const $idx_id:ident = clock_tms($mode:expr, $data:expr, $tdi:expr, $len:expr); $($tail:tt)*} -> [$($out:tt)*]) => {
+        const $idx_id: usize = $read_len;
+        mpsse!(($passthru, $read_len) {clock_tms($mode, $data, $tdi, $len); $($tail)*} -> [$($out)*]);
+    };
+    ($passthru:tt {clock_bits_no_data($len:expr); $($tail:tt)*} -> [$($out:tt)*]) => {
+        mpsse!(@assert $passthru, (($len) as u8 > 0_u8 && ($len) as u8 <= 8_u8), "data length must be in 1..=8");
+        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::ftdimpsse::MpsseCmd::ClockBitsNoData as u8, (($len) as u8 - 1),]);
+    };
+    ($passthru:tt {clock_bytes_no_data($len:expr); $($tail:tt)*} -> [$($out:tt)*]) => {
+        mpsse!($passthru {@clock_bytes($crate::ftdimpsse::MpsseCmd::ClockBytesNoData, $len); $($tail)*} -> [$($out)*]);
+    };
+    ($passthru:tt {clock_until_io_high(); $($tail:tt)*} -> [$($out:tt)*]) => {
+        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::ftdimpsse::MpsseCmd::ClockUntilIOHigh as u8,]);
+    };
+    ($passthru:tt {clock_until_io_low(); $($tail:tt)*} -> [$($out:tt)*]) => {
+        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::ftdimpsse::MpsseCmd::ClockUntilIOLow as u8,]);
+    };
+    ($passthru:tt {clock_bytes_or_io_high($len:expr); $($tail:tt)*} -> [$($out:tt)*]) => {
+        mpsse!($passthru {@clock_bytes($crate::ftdimpsse::MpsseCmd::ClockBytesOrIOHigh, $len); $($tail)*} -> [$($out)*]);
+    };
+    ($passthru:tt {clock_bytes_or_io_low($len:expr); $($tail:tt)*} -> [$($out:tt)*]) => {
+        mpsse!($passthru {@clock_bytes($crate::ftdimpsse::MpsseCmd::ClockBytesOrIOLow, $len); $($tail)*} -> [$($out)*]);
+    };
+    ($passthru:tt {@clock_bytes($cmd:expr, $len:expr); $($tail:tt)*} -> [$($out:tt)*]) => {
+        mpsse!(@assert $passthru, (($len) as usize > 0_usize && ($len) as usize <= 65536_usize), "data length must be in 1..=(u16::MAX + 1)");
+        mpsse!($passthru {$($tail)*} -> [$($out)* $cmd as u8,
+        ((($len) as usize - 1) & 0xFF_usize) as u8,
+        (((($len) as usize - 1) >> 8) & 0xFF_usize) as u8,]);
+    };
+    (($passthru:tt, $read_len:tt) {cpu_read_short($addr:expr); $($tail:tt)*} -> [$($out:tt)*]) => {
+        mpsse!(($passthru, ($read_len + 1)) {$($tail)*} -> [$($out)* $crate::ftdimpsse::MpsseCmd::CpuReadShort as u8, $addr as u8,]);
+    };
+    (($passthru:tt, $read_len:tt) {const $idx_id:ident = cpu_read_short($addr:expr); $($tail:tt)*} -> [$($out:tt)*]) => {
+        const $idx_id: usize = $read_len;
+        mpsse!(($passthru, $read_len) {cpu_read_short($addr); $($tail)*} -> [$($out)*]);
+    };
+    (($passthru:tt, $read_len:tt) {cpu_read_extended($addr:expr); $($tail:tt)*} -> [$($out:tt)*]) => {
+        mpsse!(($passthru, ($read_len + 1)) {$($tail)*} -> [$($out)* $crate::ftdimpsse::MpsseCmd::CpuReadExtended as u8,
+        (($addr as u16) >> 8) as u8, (($addr as u16) & 0xFF_u16) as u8,]);
+    };
+    (($passthru:tt, $read_len:tt) {const $idx_id:ident = cpu_read_extended($addr:expr); $($tail:tt)*} -> [$($out:tt)*]) => {
+        const $idx_id: usize = $read_len;
+        mpsse!(($passthru, $read_len) {cpu_read_extended($addr); $($tail)*} -> [$($out)*]);
+    };
+    ($passthru:tt {cpu_write_short($addr:expr, $data:expr); $($tail:tt)*} -> [$($out:tt)*]) => {
+        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::ftdimpsse::MpsseCmd::CpuWriteShort as u8, $addr as u8, $data as u8,]);
+    };
+    ($passthru:tt {cpu_write_extended($addr:expr, $data:expr); $($tail:tt)*} -> [$($out:tt)*]) => {
+        mpsse!($passthru {$($tail)*} -> [$($out)* $crate::ftdimpsse::MpsseCmd::CpuWriteExtended as u8,
+        (($addr as u16) >> 8) as u8, (($addr as u16) & 0xFF_u16) as u8, $data as u8,]);
+    };
 
     // Emit command_data
     ((($const_let:tt, ($id:tt, _)), $read_len:expr) {} -> [$($out:tt)*]) => {
@@ -1132,3 +1559,359 @@ macro_rules! mpsse {
         const $read_len_id: usize = $read_len;
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::MpsseOp;
+
+    #[test]
+    fn test_drive_only_zero() {
+        let cmd = MpsseCmdBuilder::new().enable_drive_only_zero(0b0000_0011, 0x80);
+        assert_eq!(cmd.as_slice(), [0x9e, 0x03, 0x80]);
+    }
+
+    #[test]
+    fn test_clock_no_data() {
+        let cmd = MpsseCmdBuilder::new()
+            .clock_bits_no_data(1)
+            .clock_bits_no_data(8)
+            .clock_bits_no_data(0)
+            .clock_bytes_no_data(1)
+            .clock_bytes_no_data(0x1234)
+            .clock_bytes_no_data(65536)
+            .clock_bytes_no_data(0);
+
+        assert_eq!(
+            cmd.as_slice(),
+            [0x8e, 0x00, 0x8e, 0x07, 0x8f, 0x00, 0x00, 0x8f, 0x33, 0x12, 0x8f, 0xff, 0xff]
+        );
+    }
+
+    #[test]
+    fn test_clock_until_io() {
+        let cmd = MpsseCmdBuilder::new()
+            .clock_until_io_high()
+            .clock_until_io_low()
+            .clock_bytes_or_io_high(0x100)
+            .clock_bytes_or_io_low(2)
+            .clock_bytes_or_io_low(0);
+
+        assert_eq!(
+            cmd.as_slice(),
+            [0x94, 0x95, 0x9c, 0xff, 0x00, 0x9d, 0x01, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_tms() {
+        let cmd = MpsseCmdBuilder::new()
+            .clock_tms_out(ClockTMSOut::PosEdge, 0b0001_1111, false, 5)
+            .clock_tms_out(ClockTMSOut::NegEdge, 0xff, true, 7)
+            .clock_tms_out(ClockTMSOut::PosEdge, 0x1, true, 0);
+
+        assert_eq!(cmd.as_slice(), [0x4a, 0x04, 0x1f, 0x4b, 0x06, 0xff]);
+
+        let cmd = MpsseCmdBuilder::new()
+            .clock_tms(ClockTMS::PosTMSPosTDO, 0b0000_0011, false, 2)
+            .clock_tms(ClockTMS::NegTMSPosTDO, 0b0000_0001, true, 1)
+            .clock_tms(ClockTMS::PosTMSNegTDO, 0b1000_0110, false, 3)
+            .clock_tms(ClockTMS::NegTMSNegTDO, 0x0, true, 7);
+
+        assert_eq!(
+            cmd.as_slice(),
+            [0x6a, 0x01, 0x03, 0x6b, 0x00, 0x81, 0x6e, 0x02, 0x06, 0x6f, 0x06, 0x80]
+        );
+    }
+
+    #[test]
+    fn test_clock_data_split() {
+        let data: Vec<u8> = (0..=u8::MAX).cycle().take(MAX_CLOCK_BYTES + 2).collect();
+        let cmd = MpsseCmdBuilder::new()
+            .clock_data(ClockData::MsbPosIn, &data)
+            .clock_data_in(ClockDataIn::MsbPos, 2 * MAX_CLOCK_BYTES)
+            .clock_data_out(ClockDataOut::MsbNeg, &data[..MAX_CLOCK_BYTES]);
+
+        let ops = cmd.disassemble().ops;
+        assert_eq!(ops.len(), 5);
+        assert_eq!(
+            ops[0],
+            MpsseOp::ClockData {
+                mode: ClockData::MsbPosIn,
+                len: MAX_CLOCK_BYTES,
+                data: data[..MAX_CLOCK_BYTES].to_vec(),
+            }
+        );
+        assert_eq!(
+            ops[1],
+            MpsseOp::ClockData {
+                mode: ClockData::MsbPosIn,
+                len: 2,
+                data: vec![0, 1],
+            }
+        );
+        for op in &ops[2..4] {
+            assert_eq!(
+                *op,
+                MpsseOp::ClockDataIn {
+                    mode: ClockDataIn::MsbPos,
+                    len: MAX_CLOCK_BYTES,
+                }
+            );
+        }
+        assert_eq!(cmd.disassemble().response_len, 3 * MAX_CLOCK_BYTES + 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "data length cannot exceed 7")]
+    fn test_tms_length() {
+        MpsseCmdBuilder::new().clock_tms_out(ClockTMSOut::PosEdge, 0x0, false, 8);
+    }
+
+    #[test]
+    #[should_panic(expected = "data length cannot exceed u16::MAX + 1")]
+    fn test_clock_no_data_length() {
+        MpsseCmdBuilder::new().clock_bytes_no_data(65537);
+    }
+
+    #[test]
+    fn test_cpu() {
+        let cmd = MpsseCmdBuilder::new()
+            .cpu_read_short(0x12)
+            .cpu_read_extended(0x3456)
+            .cpu_write_short(0x78, 0x9a)
+            .cpu_write_extended(0xbcde, 0xf0);
+
+        assert_eq!(
+            cmd.as_slice(),
+            [0x90, 0x12, 0x91, 0x34, 0x56, 0x92, 0x78, 0x9a, 0x93, 0xbc, 0xde, 0xf0]
+        );
+    }
+
+    #[test]
+    fn test_set_clock() {
+        let (cmd, hz) = MpsseCmdBuilder::new()
+            .set_clock(1_000_000, ChipType::FT232H)
+            .unwrap();
+        assert_eq!(hz, 1_000_000);
+        assert_eq!(cmd.as_slice(), [0x8a, 0x86, 0x1d, 0x00]);
+
+        let (cmd, hz) = MpsseCmdBuilder::new()
+            .set_clock(100, ChipType::FT2232H)
+            .unwrap();
+        assert_eq!(hz, 100);
+        assert_eq!(cmd.as_slice(), [0x8b, 0x86, 0x5f, 0xea]);
+
+        // no prescaler on FT2232C
+        let (cmd, hz) = MpsseCmdBuilder::new()
+            .set_clock(1_000_000, ChipType::FT2232C)
+            .unwrap();
+        assert_eq!(hz, 1_000_000);
+        assert_eq!(cmd.as_slice(), [0x86, 0x05, 0x00]);
+
+        assert!(matches!(
+            MpsseCmdBuilder::new().set_clock(10_000_000, ChipType::FT2232C),
+            Err(X232Error::HAL(ErrorKind::InvalidClock))
+        ));
+    }
+
+    // Every command of the macro, in the const form
+    mpsse! {
+        const (ALL, ALL_READ_LEN) = {
+            enable_loopback();
+            disable_loopback();
+            enable_3phase_data_clocking();
+            disable_3phase_data_clocking();
+            enable_adaptive_data_clocking();
+            disable_adaptive_data_clocking();
+            enable_drive_only_zero(0x03, 0x80);
+            set_gpio_lower(0xFA, 0xFB);
+            set_gpio_upper(0x01, 0x0F);
+            const LOWER_INDEX = gpio_lower();
+            const UPPER_INDEX = gpio_upper();
+            wait_on_io_high();
+            wait_on_io_low();
+            clock_data_out(ClockDataOut::MsbNeg, [0x12, 0x34, 0x56]);
+            const DATA_IN_RANGE = clock_data_in(ClockDataIn::LsbPos, 300);
+            const DATA_RANGE = clock_data(ClockData::MsbPosIn, [0x78, 0x9A]);
+            clock_bits_out(ClockBitsOut::LsbNeg, 0x5, 3);
+            const BITS_IN_INDEX = clock_bits_in(ClockBitsIn::MsbNeg, 8);
+            const BITS_INDEX = clock_bits(ClockBits::LsbPosIn, 0x80, 1);
+            clock_tms_out(ClockTMSOut::NegEdge, 0x1F, true, 5);
+            const TMS_INDEX = clock_tms(ClockTMS::PosTMSNegTDO, 0x83, false, 7);
+            clock_bits_no_data(4);
+            clock_bytes_no_data(65536);
+            clock_until_io_high();
+            clock_until_io_low();
+            clock_bytes_or_io_high(1);
+            clock_bytes_or_io_low(0x1234);
+            const CPU_SHORT_INDEX = cpu_read_short(0x10);
+            const CPU_EXTENDED_INDEX = cpu_read_extended(0x1234);
+            cpu_write_short(0x20, 0xEE);
+            cpu_write_extended(0x5678, 0xDD);
+            gpio_lower();
+            clock_data_in(ClockDataIn::MsbPos, 1);
+            send_immediate();
+        };
+    }
+
+    fn all() -> MpsseCmdBuilder {
+        MpsseCmdBuilder::new()
+            .enable_loopback()
+            .disable_loopback()
+            .enable_3phase_data_clocking()
+            .disable_3phase_data_clocking()
+            .enable_adaptive_data_clocking()
+            .disable_adaptive_data_clocking()
+            .enable_drive_only_zero(0x03, 0x80)
+            .set_gpio_lower(0xFA, 0xFB)
+            .set_gpio_upper(0x01, 0x0F)
+            .gpio_lower()
+            .gpio_upper()
+            .wait_on_io_high()
+            .wait_on_io_low()
+            .clock_data_out(ClockDataOut::MsbNeg, &[0x12, 0x34, 0x56])
+            .clock_data_in(ClockDataIn::LsbPos, 300)
+            .clock_data(ClockData::MsbPosIn, &[0x78, 0x9A])
+            .clock_bits_out(ClockBitsOut::LsbNeg, 0x5, 3)
+            .clock_bits_in(ClockBitsIn::MsbNeg, 8)
+            .clock_bits(ClockBits::LsbPosIn, 0x80, 1)
+            .clock_tms_out(ClockTMSOut::NegEdge, 0x1F, true, 5)
+            .clock_tms(ClockTMS::PosTMSNegTDO, 0x83, false, 7)
+            .clock_bits_no_data(4)
+            .clock_bytes_no_data(65536)
+            .clock_until_io_high()
+            .clock_until_io_low()
+            .clock_bytes_or_io_high(1)
+            .clock_bytes_or_io_low(0x1234)
+            .cpu_read_short(0x10)
+            .cpu_read_extended(0x1234)
+            .cpu_write_short(0x20, 0xEE)
+            .cpu_write_extended(0x5678, 0xDD)
+            .gpio_lower()
+            .clock_data_in(ClockDataIn::MsbPos, 1)
+            .send_immediate()
+    }
+
+    #[test]
+    fn test_mpsse_const() {
+        assert_eq!(ALL, all().as_slice());
+        assert_eq!(ALL_READ_LEN, all().disassemble().response_len);
+        assert_eq!(ALL_READ_LEN, 311);
+
+        assert_eq!(LOWER_INDEX, 0);
+        assert_eq!(UPPER_INDEX, 1);
+        assert_eq!(DATA_IN_RANGE, 2..302);
+        assert_eq!(DATA_RANGE, 302..304);
+        assert_eq!(BITS_IN_INDEX, 304);
+        assert_eq!(BITS_INDEX, 305);
+        assert_eq!(TMS_INDEX, 306);
+        assert_eq!(CPU_SHORT_INDEX, 307);
+        assert_eq!(CPU_EXTENDED_INDEX, 308);
+    }
+
+    #[test]
+    fn test_mpsse_let() {
+        let (value, dir, len) = (0xF2, 0xFB, 3u8);
+        let data = 0xA5;
+
+        mpsse! {
+            let (command, READ_LEN) = {
+                set_gpio_lower(value, dir);
+                clock_bits_out(ClockBitsOut::MsbNeg, data, len);
+                const DATA_RANGE = clock_data(ClockData::LsbNegIn, [data, !data]);
+                clock_tms_out(ClockTMSOut::PosEdge, data, len > 2, len);
+                send_immediate();
+            };
+            let plain = {
+                set_gpio_lower(value | 0x8, dir);
+            };
+        }
+
+        let cmd = MpsseCmdBuilder::new()
+            .set_gpio_lower(value, dir)
+            .clock_bits_out(ClockBitsOut::MsbNeg, data, len)
+            .clock_data(ClockData::LsbNegIn, &[data, !data])
+            .clock_tms_out(ClockTMSOut::PosEdge, data, len > 2, len)
+            .send_immediate();
+
+        assert_eq!(command, cmd.as_slice());
+        assert_eq!(READ_LEN, 2);
+        assert_eq!(DATA_RANGE, 0..2);
+        assert_eq!(plain, [0x80, 0xFA, 0xFB]);
+    }
+
+    #[test]
+    #[should_panic(expected = "data length must be in 1..=8")]
+    fn test_mpsse_let_bits() {
+        let len = 9;
+
+        mpsse! {
+            let _command = {
+                clock_bits_out(ClockBitsOut::MsbNeg, 0xFF, len);
+            };
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "data length must be in 1..=(u16::MAX + 1)")]
+    fn test_mpsse_let_bytes() {
+        let len = 0;
+
+        mpsse! {
+            let _command = {
+                clock_bytes_no_data(len);
+            };
+        }
+    }
+
+    #[test]
+    fn test_mpsse_shadowing() {
+        macro_rules! mpsse {
+            ($passthru:tt {cs_low(); $($tail:tt)*} -> [$($out:tt)*]) => {
+                mpsse!($passthru {set_gpio_lower(0x0, 0xB); $($tail)*} -> [$($out)*]);
+            };
+            ($passthru:tt {cs_high(); $($tail:tt)*} -> [$($out:tt)*]) => {
+                mpsse!($passthru {set_gpio_lower(0x8, 0xB); $($tail)*} -> [$($out)*]);
+            };
+            ($passthru:tt
+             {const $range_id:ident = read_register($reg:expr, $len:expr); $($tail:tt)*} ->
+             [$($out:tt)*]) => {
+                mpsse!($passthru {
+                    cs_low();
+                    clock_data_out(ClockDataOut::MsbNeg, [$reg | 0x80]);
+                    const $range_id = clock_data_in(ClockDataIn::MsbPos, $len);
+                    cs_high();
+                    $($tail)*
+                } -> [$($out)*]);
+            };
+            ($($tokens:tt)*) => {
+                crate::mpsse! { $($tokens)* }
+            };
+        }
+
+        mpsse! {
+            const (COMMAND, READ_LEN) = {
+                const ID_RANGE = read_register(0x0F, 2);
+                const STATUS_RANGE = read_register(0x27, 1);
+                send_immediate();
+            };
+        }
+
+        let cmd = MpsseCmdBuilder::new()
+            .set_gpio_lower(0x0, 0xB)
+            .clock_data_out(ClockDataOut::MsbNeg, &[0x8F])
+            .clock_data_in(ClockDataIn::MsbPos, 2)
+            .set_gpio_lower(0x8, 0xB)
+            .set_gpio_lower(0x0, 0xB)
+            .clock_data_out(ClockDataOut::MsbNeg, &[0xA7])
+            .clock_data_in(ClockDataIn::MsbPos, 1)
+            .set_gpio_lower(0x8, 0xB)
+            .send_immediate();
+
+        assert_eq!(COMMAND, cmd.as_slice());
+        assert_eq!(READ_LEN, 3);
+        assert_eq!(ID_RANGE, 0..2);
+        assert_eq!(STATUS_RANGE, 2..3);
+    }
+}
@@ -1,11 +1,12 @@
 use crate::error::{Result, X232Error};
 use crate::ftdimpsse::MpsseCmdBuilder;
+use crate::transport::{MpsseContext, MpsseTransport};
+use crate::x232h::{DeviceRef, FTx232H};
 
-use embedded_hal::digital::v2::OutputPin;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
 use std::cell::RefCell;
 use std::fmt;
-use std::io::{Read, Write};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum PinBank {
@@ -22,31 +23,48 @@ impl fmt::Display for PinBank {
     }
 }
 
+/// Initial state of a GPIO pin, see [`FTx232HBuilder::pin`].
+///
+/// [`FTx232HBuilder::pin`]: crate::builder::FTx232HBuilder::pin
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PinState {
+    /// Input, the pin is not driven (default).
+    Input,
+    /// Output driven low.
+    Low,
+    /// Output driven high.
+    High,
+}
+
 #[macro_export]
 macro_rules! declare_gpio_pin {
     ($pin: ident, $bit: expr, $bank: expr) => {
-        pub fn $pin(&self) -> Result<GpioPin> {
-            if !*self.$pin.borrow() {
-                return Err(X232Error::HAL(ErrorKind::GpioPinBusy));
-            }
-
+        pub fn $pin(&self) -> Result<GpioPin<T>> {
             if $bit > 7 {
                 return Err(X232Error::HAL(ErrorKind::GpioInvalidPin));
             }
 
-            self.$pin.replace(false);
+            if $bank == PinBank::High && !self.has_gpio_upper() {
+                return Err(X232Error::HAL(ErrorKind::GpioNoUpperByte));
+            }
+
+            // claim the pin: only one of concurrent callers gets it
+            if !self.$pin.swap(false, Ordering::AcqRel) {
+                return Err(X232Error::HAL(ErrorKind::GpioPinBusy));
+            }
+
             Ok(GpioPin::new(&self.mtx, $bit, $bank))
         }
     };
 }
 
-pub struct GpioPin<'a> {
-    ctx: &'a Mutex<RefCell<ftdi::Device>>,
+pub struct GpioPin<'a, T: MpsseTransport> {
+    ctx: DeviceRef<'a, T>,
     bank: PinBank,
     bit: u8,
 }
 
-impl<'a> fmt::Display for GpioPin<'a> {
+impl<'a, T: MpsseTransport> fmt::Display for GpioPin<'a, T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.bank {
             PinBank::Low => write!(f, "P{}{}", self.bank, self.bit - 4),
@@ -55,9 +73,24 @@ impl<'a> fmt::Display for GpioPin<'a> {
     }
 }
 
-impl<'a> GpioPin<'a> {
-    pub fn new(ctx: &'a Mutex<RefCell<ftdi::Device>>, bit: u8, bank: PinBank) -> GpioPin {
-        GpioPin { ctx, bank, bit }
+impl<'a, T: MpsseTransport> GpioPin<'a, T> {
+    pub fn new(ctx: &'a Mutex<RefCell<MpsseContext<T>>>, bit: u8, bank: PinBank) -> GpioPin<T> {
+        GpioPin {
+            ctx: DeviceRef::Borrowed(ctx),
+            bank,
+            bit,
+        }
+    }
+
+    pub(crate) fn shared(dev: Arc<FTx232H<T>>, bit: u8, bank: PinBank) -> GpioPin<'static, T>
+    where
+        T: 'static,
+    {
+        GpioPin {
+            ctx: DeviceRef::Shared(dev),
+            bank,
+            bit,
+        }
     }
 
     pub fn get_bit(&self) -> u8 {
@@ -79,7 +112,7 @@ impl<'a> GpioPin<'a> {
         let lock = self.ctx.lock().unwrap();
         let mut ftdi = lock.borrow_mut();
 
-        ftdi.usb_purge_buffers()?;
+        ftdi.purge()?;
         ftdi.write_all(read.as_slice())?;
         ftdi.read_exact(&mut value)?;
 
@@ -89,23 +122,61 @@ impl<'a> GpioPin<'a> {
             value[0] & (!(1 << self.bit))
         };
 
+        // the pin becomes an output, the other GPIO pins keep their direction
+        let dir = match self.bank {
+            PinBank::Low => ftdi.lower.1 | (1 << self.bit),
+            PinBank::High => ftdi.upper.1 | (1 << self.bit),
+        };
+
         let write = match self.bank {
-            PinBank::Low => MpsseCmdBuilder::new()
-                .set_gpio_lower(v, 0b1111_1011)
-                .send_immediate(),
-            PinBank::High => MpsseCmdBuilder::new()
-                .set_gpio_upper(v, 0b1111_1111)
-                .send_immediate(),
+            PinBank::Low => ftdi.set_lower(MpsseCmdBuilder::new(), v, dir),
+            PinBank::High => ftdi.set_upper(MpsseCmdBuilder::new(), v, dir),
         };
 
-        ftdi.usb_purge_buffers()?;
-        ftdi.write_all(write.as_slice())?;
+        ftdi.purge()?;
+        ftdi.write_all(write.send_immediate().as_slice())
+    }
 
-        Ok(())
+    /// Switch the pin to input mode, `set_high`/`set_low` make it an output
+    /// again.
+    pub fn set_input(&mut self) -> Result<()> {
+        let lock = self.ctx.lock().unwrap();
+        let mut ftdi = lock.borrow_mut();
+
+        let write = match self.bank {
+            PinBank::Low => {
+                let (v, dir) = (ftdi.lower.0, ftdi.lower.1 & !(1 << self.bit));
+                ftdi.set_lower(MpsseCmdBuilder::new(), v, dir)
+            }
+            PinBank::High => {
+                let (v, dir) = (ftdi.upper.0, ftdi.upper.1 & !(1 << self.bit));
+                ftdi.set_upper(MpsseCmdBuilder::new(), v, dir)
+            }
+        };
+
+        ftdi.write_all(write.as_slice())
+    }
+
+    fn get_pin(&self) -> Result<bool> {
+        let mut value: [u8; 1] = [0];
+
+        let read = match self.bank {
+            PinBank::Low => MpsseCmdBuilder::new().gpio_lower().send_immediate(),
+            PinBank::High => MpsseCmdBuilder::new().gpio_upper().send_immediate(),
+        };
+
+        let lock = self.ctx.lock().unwrap();
+        let mut ftdi = lock.borrow_mut();
+
+        ftdi.purge()?;
+        ftdi.write_all(read.as_slice())?;
+        ftdi.read_exact(&mut value)?;
+
+        Ok(value[0] & (1 << self.bit) != 0)
     }
 }
 
-impl<'a> OutputPin for GpioPin<'a> {
+impl<'a, T: MpsseTransport> OutputPin for GpioPin<'a, T> {
     type Error = X232Error;
 
     fn set_low(&mut self) -> Result<()> {
@@ -118,3 +189,15 @@ impl<'a> OutputPin for GpioPin<'a> {
         Ok(())
     }
 }
+
+impl<'a, T: MpsseTransport> InputPin for GpioPin<'a, T> {
+    type Error = X232Error;
+
+    fn is_high(&self) -> Result<bool> {
+        self.get_pin()
+    }
+
+    fn is_low(&self) -> Result<bool> {
+        Ok(!self.get_pin()?)
+    }
+}
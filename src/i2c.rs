@@ -2,10 +2,12 @@
 
 use crate::error::{ErrorKind, Result, X232Error};
 use crate::ftdimpsse::{ClockBitsIn, ClockDataIn, ClockDataOut, MpsseCmdBuilder};
+use crate::transport::{MpsseContext, MpsseTransport};
+use crate::x232h::{DeviceRef, FTx232H};
+use embedded_hal::blocking::i2c::Operation;
 
 use std::cell::RefCell;
-use std::io::{Read, Write};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 #[allow(non_camel_case_types)]
@@ -13,19 +15,51 @@ pub enum I2cSpeed {
     CLK_AUTO,
     CLK_100kHz,
     CLK_400kHz,
+    /// Arbitrary SCL frequency in Hz, the chip runs at the highest one
+    /// not above it.
+    Hz(u32),
 }
 
-pub struct I2cBus<'a> {
-    ctx: &'a Mutex<RefCell<ftdi::Device>>,
+impl I2cSpeed {
+    /// SCL frequency in Hz, `CLK_AUTO` stands for 100kHz.
+    pub(crate) fn frequency(self) -> u32 {
+        match self {
+            I2cSpeed::CLK_100kHz | I2cSpeed::CLK_AUTO => 100_000,
+            I2cSpeed::CLK_400kHz => 400_000,
+            I2cSpeed::Hz(hz) => hz,
+        }
+    }
+
+    // A bus clocked at `self` serves the requests for `speed`: the same
+    // frequency or `CLK_AUTO`
+    pub(crate) fn serves(self, speed: I2cSpeed) -> bool {
+        speed == I2cSpeed::CLK_AUTO
+            || (self != I2cSpeed::CLK_AUTO && self.frequency() == speed.frequency())
+    }
 }
 
-impl<'a> I2cBus<'a> {
-    pub fn new(ctx: &'a Mutex<RefCell<ftdi::Device>>) -> I2cBus {
-        I2cBus { ctx }
+pub struct I2cBus<'a, T: MpsseTransport> {
+    ctx: DeviceRef<'a, T>,
+}
+
+impl<'a, T: MpsseTransport> I2cBus<'a, T> {
+    pub fn new(ctx: &'a Mutex<RefCell<MpsseContext<T>>>) -> I2cBus<T> {
+        I2cBus {
+            ctx: DeviceRef::Borrowed(ctx),
+        }
+    }
+
+    pub(crate) fn shared(dev: Arc<FTx232H<T>>) -> I2cBus<'static, T>
+    where
+        T: 'static,
+    {
+        I2cBus {
+            ctx: DeviceRef::Shared(dev),
+        }
     }
 }
 
-impl<'a> I2cBus<'a> {
+impl<'a, T: MpsseTransport> I2cBus<'a, T> {
     fn i2c_write_to(addr: u8) -> u8 {
         (addr << 1) | 0x0
     }
@@ -35,69 +69,91 @@ impl<'a> I2cBus<'a> {
     }
 }
 
-impl<'a> I2cBus<'a> {
-    fn i2c_start(&self, mut cmd: MpsseCmdBuilder, pins: u8) -> MpsseCmdBuilder {
+impl<'a, T: MpsseTransport> I2cBus<'a, T> {
+    // SCL (AD0) and SDA (AD1 out, AD2 in) set to `value` and `dir`, the other
+    // lower byte pins keep the value and direction they have
+    fn i2c_lower(
+        ftdi: &mut MpsseContext<T>,
+        cmd: MpsseCmdBuilder,
+        value: u8,
+        dir: u8,
+    ) -> MpsseCmdBuilder {
+        let (lower, lower_dir) = ftdi.lower;
+        ftdi.set_lower(
+            cmd,
+            (lower & 0b1111_1000) | value,
+            (lower_dir & 0b1111_1000) | dir,
+        )
+    }
+
+    fn i2c_start(ftdi: &mut MpsseContext<T>, mut cmd: MpsseCmdBuilder) -> MpsseCmdBuilder {
         for _ in 0..4 {
-            cmd = cmd.set_gpio_lower((pins & 0b1111_1000) | 0b11, 0b1111_1011);
+            cmd = Self::i2c_lower(ftdi, cmd, 0b11, 0b011);
         }
 
         for _ in 0..4 {
-            cmd = cmd.set_gpio_lower((pins & 0b1111_1000) | 0b01, 0b1111_1011);
+            cmd = Self::i2c_lower(ftdi, cmd, 0b01, 0b011);
         }
 
         for _ in 0..4 {
-            cmd = cmd.set_gpio_lower((pins & 0b1111_1000) | 0b00, 0b1111_1011);
+            cmd = Self::i2c_lower(ftdi, cmd, 0b00, 0b011);
         }
 
         cmd
     }
 
-    fn i2c_stop(&self, mut cmd: MpsseCmdBuilder, pins: u8) -> MpsseCmdBuilder {
+    fn i2c_stop(ftdi: &mut MpsseContext<T>, mut cmd: MpsseCmdBuilder) -> MpsseCmdBuilder {
         for _ in 0..4 {
-            cmd = cmd.set_gpio_lower((pins & 0b1111_1000) | 0b01, 0b1111_1011);
+            cmd = Self::i2c_lower(ftdi, cmd, 0b01, 0b011);
         }
 
         for _ in 0..4 {
-            cmd = cmd.set_gpio_lower((pins & 0b1111_1000) | 0b11, 0b1111_1011);
+            cmd = Self::i2c_lower(ftdi, cmd, 0b11, 0b011);
         }
 
         for _ in 0..4 {
-            cmd = cmd.set_gpio_lower((pins & 0b1111_1100) | 0b00, 0b1111_1000);
+            cmd = Self::i2c_lower(ftdi, cmd, 0b00, 0b000);
         }
 
         cmd
     }
 
-    fn i2c_write_byte_ack(&self, cmd: MpsseCmdBuilder, byte: u8, pins: u8) -> MpsseCmdBuilder {
+    fn i2c_write_byte_ack(
+        ftdi: &mut MpsseContext<T>,
+        cmd: MpsseCmdBuilder,
+        byte: u8,
+    ) -> MpsseCmdBuilder {
+        // make sure no occasional SP: SDA output(1) SCL output(0)
+        let cmd = Self::i2c_lower(ftdi, cmd, 0b10, 0b011);
+        // send single byte using MPSSE
+        let cmd = cmd.clock_data_out(ClockDataOut::MsbNeg, &[byte]);
+        // get pins ready for SAK: DO input, DI input, SK output(0)
+        let cmd = Self::i2c_lower(ftdi, cmd, 0b00, 0b001);
+
         cmd
-            // make sure no occasional SP: SDA output(1) SCL output(0)
-            .set_gpio_lower((pins & 0b1111_1000) | 0b10, 0b1111_1011)
-            // send single byte using MPSSE
-            .clock_data_out(ClockDataOut::MsbNeg, &[byte])
-            // get pins ready for SAK: DO input, DI input, SK output(0)
-            .set_gpio_lower((pins & 0b1111_1000) | 0b00, 0b1111_1001)
             // SAK: recv using MPSSE
             .clock_bits_in(ClockBitsIn::MsbPos, 1)
             // request immediate response from FTDI to host
             .send_immediate()
     }
 
-    fn i2c_read_byte(&self, cmd: MpsseCmdBuilder, nack: bool, pins: u8) -> MpsseCmdBuilder {
-        let state = if nack {
-            (pins & 0b1111_1000) | 0b10
-        } else {
-            (pins & 0b1111_1000) | 0b00
-        };
+    fn i2c_read_byte(
+        ftdi: &mut MpsseContext<T>,
+        cmd: MpsseCmdBuilder,
+        nack: bool,
+    ) -> MpsseCmdBuilder {
+        let state = if nack { 0b10 } else { 0b00 };
+
+        // make sure no occasional SP: SDA output(1), SCL output(0)
+        let cmd = Self::i2c_lower(ftdi, cmd, 0b10, 0b011);
+        // prepare to read: SDA input, SCL output(0)
+        let cmd = Self::i2c_lower(ftdi, cmd, 0b00, 0b001);
+        // read byte using MPSSE
+        let cmd = cmd.clock_data_in(ClockDataIn::MsbNeg, 1);
+        // prepare SDA for NACK/ACK
+        let cmd = Self::i2c_lower(ftdi, cmd, state, 0b011);
 
         cmd
-            // make sure no occasional SP: SDA output(1), SCL output(0)
-            .set_gpio_lower((pins & 0b1111_1000) | 0b10, 0b1111_1011)
-            // prepare to read: SDA input, SCL output(0)
-            .set_gpio_lower((pins & 0b1111_1000) | 0b00, 0b1111_1001)
-            // read byte using MPSSE
-            .clock_data_in(ClockDataIn::MsbNeg, 1)
-            // prepare SDA for NACK/ACK
-            .set_gpio_lower(state, 0b1111_1011)
             // NACK/ACK to slave: we pretend we read it
             .clock_bits_in(ClockBitsIn::MsbPos, 1)
             // request immediate response from FTDI to PC
@@ -105,7 +161,7 @@ impl<'a> I2cBus<'a> {
     }
 }
 
-impl<'a> embedded_hal::blocking::i2c::Read for I2cBus<'a> {
+impl<'a, T: MpsseTransport> embedded_hal::blocking::i2c::Read for I2cBus<'a, T> {
     type Error = X232Error;
 
     fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<()> {
@@ -116,24 +172,17 @@ impl<'a> embedded_hal::blocking::i2c::Read for I2cBus<'a> {
         let lock = self.ctx.lock().unwrap();
         let mut ftdi = lock.borrow_mut();
 
-        let cmd_read_low_pins = MpsseCmdBuilder::new().gpio_lower().send_immediate();
         let mut cmd = MpsseCmdBuilder::new();
-        let mut pins: Vec<u8> = vec![0];
         let mut ack: Vec<u8> = vec![0];
 
-        // get current state of low pins
-        ftdi.usb_purge_buffers()?;
-        ftdi.write_all(cmd_read_low_pins.as_slice())?;
-        ftdi.read_exact(&mut pins)?;
-
         // ST: send using bit-banging
-        cmd = self.i2c_start(cmd, pins[0]);
+        cmd = Self::i2c_start(&mut ftdi, cmd);
 
         // SAD + R: send using MPSSE
-        cmd = self.i2c_write_byte_ack(cmd, I2cBus::i2c_read_from(address), pins[0]);
+        cmd = Self::i2c_write_byte_ack(&mut ftdi, cmd, Self::i2c_read_from(address));
 
         // send command and read back one bit
-        ftdi.usb_purge_buffers()?;
+        ftdi.purge()?;
         ftdi.write_all(cmd.as_slice())?;
         ftdi.read_exact(&mut ack)?;
 
@@ -148,9 +197,9 @@ impl<'a> embedded_hal::blocking::i2c::Read for I2cBus<'a> {
             let mut data: Vec<u8> = vec![0, 0];
             let nack: bool = i == (buffer.len() - 1);
 
-            cmd = self.i2c_read_byte(cmd, nack, pins[0]);
+            cmd = Self::i2c_read_byte(&mut ftdi, cmd, nack);
 
-            ftdi.usb_purge_buffers()?;
+            ftdi.purge()?;
             ftdi.write_all(cmd.as_slice())?;
             ftdi.read_exact(&mut data)?;
 
@@ -160,16 +209,16 @@ impl<'a> embedded_hal::blocking::i2c::Read for I2cBus<'a> {
         let mut cmd = MpsseCmdBuilder::new();
 
         // SP: send using bit-banging
-        cmd = self.i2c_stop(cmd, pins[0]);
+        cmd = Self::i2c_stop(&mut ftdi, cmd);
 
-        ftdi.usb_purge_buffers()?;
+        ftdi.purge()?;
         ftdi.write_all(cmd.as_slice())?;
 
         Ok(())
     }
 }
 
-impl<'a> embedded_hal::blocking::i2c::Write for I2cBus<'a> {
+impl<'a, T: MpsseTransport> embedded_hal::blocking::i2c::Write for I2cBus<'a, T> {
     type Error = X232Error;
 
     fn write(&mut self, address: u8, bytes: &[u8]) -> Result<()> {
@@ -180,24 +229,17 @@ impl<'a> embedded_hal::blocking::i2c::Write for I2cBus<'a> {
         let lock = self.ctx.lock().unwrap();
         let mut ftdi = lock.borrow_mut();
 
-        let cmd_read_low_pins = MpsseCmdBuilder::new().gpio_lower().send_immediate();
         let mut cmd = MpsseCmdBuilder::new();
-        let mut pins: Vec<u8> = vec![0];
         let mut ack: Vec<u8> = vec![0];
 
-        // get current state of low pins
-        ftdi.usb_purge_buffers()?;
-        ftdi.write_all(cmd_read_low_pins.as_slice())?;
-        ftdi.read_exact(&mut pins)?;
-
         // ST: send using bit-banging
-        cmd = self.i2c_start(cmd, pins[0]);
+        cmd = Self::i2c_start(&mut ftdi, cmd);
 
         // SAD + W: send using MPSSE
-        cmd = self.i2c_write_byte_ack(cmd, I2cBus::i2c_write_to(address), pins[0]);
+        cmd = Self::i2c_write_byte_ack(&mut ftdi, cmd, Self::i2c_write_to(address));
 
         // send command and read back one bit
-        ftdi.usb_purge_buffers()?;
+        ftdi.purge()?;
         ftdi.write_all(cmd.as_slice())?;
         ftdi.read_exact(&mut ack)?;
 
@@ -210,10 +252,10 @@ impl<'a> embedded_hal::blocking::i2c::Write for I2cBus<'a> {
         for byte in bytes {
             let mut cmd = MpsseCmdBuilder::new();
 
-            cmd = self.i2c_write_byte_ack(cmd, *byte, pins[0]);
+            cmd = Self::i2c_write_byte_ack(&mut ftdi, cmd, *byte);
 
             // send command and read back one bit
-            ftdi.usb_purge_buffers()?;
+            ftdi.purge()?;
             ftdi.write_all(cmd.as_slice())?;
             ftdi.read_exact(&mut ack)?;
 
@@ -226,16 +268,16 @@ impl<'a> embedded_hal::blocking::i2c::Write for I2cBus<'a> {
         let mut cmd = MpsseCmdBuilder::new();
 
         // SP: send using bit-banging
-        cmd = self.i2c_stop(cmd, pins[0]);
+        cmd = Self::i2c_stop(&mut ftdi, cmd);
 
-        ftdi.usb_purge_buffers()?;
+        ftdi.purge()?;
         ftdi.write_all(cmd.as_slice())?;
 
         Ok(())
     }
 }
 
-impl<'a> embedded_hal::blocking::i2c::WriteRead for I2cBus<'a> {
+impl<'a, T: MpsseTransport> embedded_hal::blocking::i2c::WriteRead for I2cBus<'a, T> {
     type Error = X232Error;
 
     fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<()> {
@@ -247,24 +289,17 @@ impl<'a> embedded_hal::blocking::i2c::WriteRead for I2cBus<'a> {
         let lock = self.ctx.lock().unwrap();
         let mut ftdi = lock.borrow_mut();
 
-        let cmd_read_low_pins = MpsseCmdBuilder::new().gpio_lower().send_immediate();
         let mut cmd = MpsseCmdBuilder::new();
-        let mut pins: Vec<u8> = vec![0];
         let mut ack: Vec<u8> = vec![0];
 
-        // get current state of low pins
-        ftdi.usb_purge_buffers()?;
-        ftdi.write_all(cmd_read_low_pins.as_slice())?;
-        ftdi.read_exact(&mut pins)?;
-
         // ST: send using bit-banging
-        cmd = self.i2c_start(cmd, pins[0]);
+        cmd = Self::i2c_start(&mut ftdi, cmd);
 
         // SAD + W: send using MPSSE
-        cmd = self.i2c_write_byte_ack(cmd, I2cBus::i2c_write_to(address), pins[0]);
+        cmd = Self::i2c_write_byte_ack(&mut ftdi, cmd, Self::i2c_write_to(address));
 
         // send command and read back one bit
-        ftdi.usb_purge_buffers()?;
+        ftdi.purge()?;
         ftdi.write_all(cmd.as_slice())?;
         ftdi.read_exact(&mut ack)?;
 
@@ -277,10 +312,10 @@ impl<'a> embedded_hal::blocking::i2c::WriteRead for I2cBus<'a> {
         for byte in bytes {
             let mut cmd = MpsseCmdBuilder::new();
 
-            cmd = self.i2c_write_byte_ack(cmd, *byte, pins[0]);
+            cmd = Self::i2c_write_byte_ack(&mut ftdi, cmd, *byte);
 
             // send command and read back one bit
-            ftdi.usb_purge_buffers()?;
+            ftdi.purge()?;
             ftdi.write_all(cmd.as_slice())?;
             ftdi.read_exact(&mut ack)?;
 
@@ -294,13 +329,13 @@ impl<'a> embedded_hal::blocking::i2c::WriteRead for I2cBus<'a> {
         let mut ack: Vec<u8> = vec![0];
 
         // SR: send using bit-banging
-        cmd = self.i2c_start(cmd, pins[0]);
+        cmd = Self::i2c_start(&mut ftdi, cmd);
 
         // SAD + R: send using MPSSE
-        cmd = self.i2c_write_byte_ack(cmd, I2cBus::i2c_read_from(address), pins[0]);
+        cmd = Self::i2c_write_byte_ack(&mut ftdi, cmd, Self::i2c_read_from(address));
 
         // send command and read back one bit
-        ftdi.usb_purge_buffers()?;
+        ftdi.purge()?;
         ftdi.write_all(cmd.as_slice())?;
         ftdi.read_exact(&mut ack)?;
 
@@ -315,9 +350,9 @@ impl<'a> embedded_hal::blocking::i2c::WriteRead for I2cBus<'a> {
             let mut data: Vec<u8> = vec![0, 0];
             let nack: bool = i == (buffer.len() - 1);
 
-            cmd = self.i2c_read_byte(cmd, nack, pins[0]);
+            cmd = Self::i2c_read_byte(&mut ftdi, cmd, nack);
 
-            ftdi.usb_purge_buffers()?;
+            ftdi.purge()?;
             ftdi.write_all(cmd.as_slice())?;
             ftdi.read_exact(&mut data)?;
 
@@ -327,10 +362,114 @@ impl<'a> embedded_hal::blocking::i2c::WriteRead for I2cBus<'a> {
         let mut cmd = MpsseCmdBuilder::new();
 
         // SP: send using bit-banging
-        cmd = self.i2c_stop(cmd, pins[0]);
+        cmd = Self::i2c_stop(&mut ftdi, cmd);
+
+        ftdi.purge()?;
+        ftdi.write_all(cmd.as_slice())?;
+
+        Ok(())
+    }
+}
+
+impl<'a, T: MpsseTransport> embedded_hal::blocking::i2c::Transactional for I2cBus<'a, T> {
+    type Error = X232Error;
+
+    /// All the operations are sent to the chip at once, the ACK bits are
+    /// checked when the responses come back, after the final SP.
+    fn exec(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<()> {
+        // the last byte of a read is NACKed, so there must be one
+        if operations
+            .iter()
+            .any(|op| matches!(op, Operation::Read(buf) if buf.is_empty()))
+        {
+            return Err(X232Error::HAL(ErrorKind::InvalidParams));
+        }
+
+        if operations.is_empty() {
+            return Ok(());
+        }
+
+        let reads: Vec<bool> = operations
+            .iter()
+            .map(|op| matches!(op, Operation::Read(_)))
+            .collect();
+        // ST/SR + SAD go before the first operation and on a kind change
+        let starts = |i: usize| i == 0 || reads[i] != reads[i - 1];
+
+        let lock = self.ctx.lock().unwrap();
+        let mut ftdi = lock.borrow_mut();
+
+        let mut cmd = MpsseCmdBuilder::new();
+        let mut len = 0;
+
+        for (i, op) in operations.iter().enumerate() {
+            if starts(i) {
+                let sad = if reads[i] {
+                    Self::i2c_read_from(address)
+                } else {
+                    Self::i2c_write_to(address)
+                };
+
+                // ST/SR: send using bit-banging
+                cmd = Self::i2c_start(&mut ftdi, cmd);
+                // SAD + R/W: send using MPSSE
+                cmd = Self::i2c_write_byte_ack(&mut ftdi, cmd, sad);
+                len += 1;
+            }
+
+            match op {
+                Operation::Write(bytes) => {
+                    for byte in bytes.iter() {
+                        cmd = Self::i2c_write_byte_ack(&mut ftdi, cmd, *byte);
+                        len += 1;
+                    }
+                }
+                Operation::Read(buf) => {
+                    // NACK the last byte before SR or SP
+                    let last = i + 1 == operations.len() || starts(i + 1);
+                    for n in 0..buf.len() {
+                        let nack = last && n == buf.len() - 1;
+                        cmd = Self::i2c_read_byte(&mut ftdi, cmd, nack);
+                        len += 2;
+                    }
+                }
+            }
+        }
+
+        // SP: send using bit-banging
+        cmd = Self::i2c_stop(&mut ftdi, cmd);
 
-        ftdi.usb_purge_buffers()?;
+        let mut data = vec![0; len];
+        ftdi.purge()?;
         ftdi.write_all(cmd.as_slice())?;
+        ftdi.read_exact(&mut data)?;
+
+        // ACK bits of SAD and written bytes, read bytes followed by our ACK
+        let mut data = data.into_iter();
+        let ack = |data: &mut std::vec::IntoIter<u8>| match data.next() {
+            Some(bit) if bit & 0x1 == 0x0 => Ok(()),
+            _ => Err(X232Error::HAL(ErrorKind::I2cNoAck)),
+        };
+
+        for (i, op) in operations.iter_mut().enumerate() {
+            if starts(i) {
+                ack(&mut data)?;
+            }
+
+            match op {
+                Operation::Write(bytes) => {
+                    for _ in bytes.iter() {
+                        ack(&mut data)?;
+                    }
+                }
+                Operation::Read(buf) => {
+                    for byte in buf.iter_mut() {
+                        *byte = data.next().unwrap_or_default();
+                        data.next();
+                    }
+                }
+            }
+        }
 
         Ok(())
     }
@@ -1,19 +1,40 @@
+#[cfg(feature = "async")]
+pub mod asynch;
+pub mod builder;
+pub mod chip;
+pub mod disasm;
+pub mod dual;
+pub mod emulator;
+pub mod enumerate;
 pub mod error;
 pub mod ftdimpsse;
 pub mod gpio;
 pub mod i2c;
+pub mod record;
+pub mod shared;
 pub mod spi;
+pub mod transport;
+#[cfg(feature = "rusb-backend")]
+pub mod usb;
 pub mod x232h;
 
+#[cfg(not(any(feature = "libftdi", feature = "rusb-backend")))]
+compile_error!("either `libftdi` or `rusb-backend` feature must be enabled");
+
 #[cfg(test)]
 mod tests {
     macro_rules! ftdi_test_suite {
         ($vendor: expr, $product: expr, $channel: expr) => {
+            use crate::x232h::Interface;
+
+            ftdi_test_suite!(@open FTx232H::init_chan($vendor, $product, $channel));
+        };
+        (@open $open: expr) => {
+            use crate::error::{ErrorKind, X232Error};
             use crate::gpio::PinBank;
             use crate::i2c::I2cSpeed;
             use crate::spi::SpiSpeed;
             use crate::x232h::FTx232H;
-            use crate::x232h::Interface;
             use embedded_hal::blocking::spi::Transfer;
             use embedded_hal::spi::{MODE_0, MODE_1, MODE_2, MODE_3};
             use itertools::iproduct;
@@ -21,7 +42,7 @@ mod tests {
 
             #[test]
             fn test_init_t1() {
-                let mut dev = FTx232H::init_chan($vendor, $product, $channel).unwrap();
+                let mut dev = $open.unwrap();
                 assert_eq!(dev.is_loopback(), false);
 
                 dev.loopback(true).unwrap();
@@ -30,7 +51,7 @@ mod tests {
 
             #[test]
             fn test_init_t2() {
-                let dev = FTx232H::init_chan($vendor, $product, $channel).unwrap();
+                let dev = $open.unwrap();
 
                 let pl0 = dev.pl0().unwrap();
                 assert_eq!(pl0.get_bit(), 4);
@@ -55,7 +76,11 @@ mod tests {
 
             #[test]
             fn test_init_t3() {
-                let dev = FTx232H::init_chan($vendor, $product, $channel).unwrap();
+                let dev = $open.unwrap();
+
+                if !dev.has_gpio_upper() {
+                    return;
+                }
 
                 let ph0 = dev.ph0().unwrap();
                 assert_eq!(ph0.get_bit(), 0);
@@ -100,9 +125,13 @@ mod tests {
 
             #[test]
             fn test_init_t4() {
-                let dev = FTx232H::init_chan($vendor, $product, $channel).unwrap();
+                let dev = $open.unwrap();
                 assert_eq!(dev.is_loopback(), false);
 
+                if !dev.has_gpio_upper() {
+                    return;
+                }
+
                 let ph0_0 = dev.ph0();
                 let ph0_1 = dev.ph0();
                 let ph0_2 = dev.ph0();
@@ -114,7 +143,7 @@ mod tests {
 
             #[test]
             fn test_init_t5() {
-                let dev = FTx232H::init_chan($vendor, $product, $channel).unwrap();
+                let dev = $open.unwrap();
                 assert_eq!(dev.is_loopback(), false);
 
                 let mut spidev = dev.spi(SpiSpeed::CLK_AUTO).unwrap();
@@ -134,7 +163,7 @@ mod tests {
 
             #[test]
             fn test_init_t6() {
-                let dev = FTx232H::init_chan($vendor, $product, $channel).unwrap();
+                let dev = $open.unwrap();
                 assert_eq!(dev.is_loopback(), false);
 
                 let spi1 = dev.spi(SpiSpeed::CLK_AUTO);
@@ -149,7 +178,7 @@ mod tests {
 
             #[test]
             fn test_init_t7() {
-                let dev = FTx232H::init_chan($vendor, $product, $channel).unwrap();
+                let dev = $open.unwrap();
                 assert_eq!(dev.is_loopback(), false);
 
                 let i2c1 = dev.i2c(I2cSpeed::CLK_AUTO);
@@ -164,7 +193,7 @@ mod tests {
 
             #[test]
             fn test_init_t8() {
-                let dev = FTx232H::init_chan($vendor, $product, $channel).unwrap();
+                let dev = $open.unwrap();
                 assert_eq!(dev.is_loopback(), false);
 
                 let spi1 = dev.spi(SpiSpeed::CLK_1MHz);
@@ -188,7 +217,7 @@ mod tests {
 
             #[test]
             fn test_init_t9() {
-                let dev = FTx232H::init_chan($vendor, $product, $channel).unwrap();
+                let dev = $open.unwrap();
                 assert_eq!(dev.is_loopback(), false);
 
                 let spi1 = dev.spi(SpiSpeed::CLK_AUTO);
@@ -206,7 +235,7 @@ mod tests {
 
             #[test]
             fn test_init_t10() {
-                let dev = FTx232H::init_chan($vendor, $product, $channel).unwrap();
+                let dev = $open.unwrap();
                 assert_eq!(dev.is_loopback(), false);
 
                 let i2c1 = dev.i2c(I2cSpeed::CLK_100kHz);
@@ -230,7 +259,7 @@ mod tests {
 
             #[test]
             fn test_init_t11() {
-                let dev = FTx232H::init_chan($vendor, $product, $channel).unwrap();
+                let dev = $open.unwrap();
                 assert_eq!(dev.is_loopback(), false);
 
                 let i2c1 = dev.i2c(I2cSpeed::CLK_AUTO);
@@ -246,9 +275,60 @@ mod tests {
                 assert!(i2c3.is_ok(), "3rd i2c with AUTO clock should be ok");
             }
 
+            #[test]
+            fn test_init_t12() {
+                let dev = $open.unwrap();
+
+                if dev.has_gpio_upper() {
+                    return;
+                }
+
+                match dev.ph0() {
+                    Err(X232Error::HAL(ErrorKind::GpioNoUpperByte)) => {}
+                    _ => panic!("PH0 should not be available without upper byte"),
+                }
+
+                match dev.ph7() {
+                    Err(X232Error::HAL(ErrorKind::GpioNoUpperByte)) => {}
+                    _ => panic!("PH7 should not be available without upper byte"),
+                }
+
+                assert!(dev.pl0().is_ok(), "PL0 should be available");
+            }
+
+            #[test]
+            fn test_init_t13() {
+                let dev = $open.unwrap();
+                let caps = dev.capabilities();
+
+                for (supported, res) in [
+                    (caps.three_phase_clocking, dev.set_3phase_clocking(false)),
+                    (caps.adaptive_clocking, dev.set_adaptive_clocking(false)),
+                    (caps.drive_only_zero, dev.set_drive_only_zero(0x0, 0x0)),
+                ] {
+                    match res {
+                        Ok(()) => assert!(supported, "unsupported feature should fail"),
+                        Err(X232Error::HAL(ErrorKind::FeatureNotSupported)) => {
+                            assert!(!supported, "supported feature should be ok")
+                        }
+                        Err(err) => panic!("unexpected error: {:?}", err),
+                    }
+                }
+
+                // SPI clock can not exceed half of the base clock
+                let spi = dev.spi(SpiSpeed::CLK_20MHz);
+                match spi {
+                    Ok(_) => assert!(caps.base_clock >= 40_000_000),
+                    Err(X232Error::HAL(ErrorKind::InvalidClock)) => {
+                        assert!(caps.base_clock < 40_000_000)
+                    }
+                    Err(err) => panic!("unexpected error: {:?}", err),
+                }
+            }
+
             #[test]
             fn test_loopback_t1() {
-                let mut dev = FTx232H::init_chan($vendor, $product, $channel).unwrap();
+                let mut dev = $open.unwrap();
                 dev.loopback(true).unwrap();
                 assert_eq!(dev.is_loopback(), true);
 
@@ -266,7 +346,7 @@ mod tests {
 
             #[test]
             fn test_loopback_t2() {
-                let mut dev = FTx232H::init_chan($vendor, $product, $channel).unwrap();
+                let mut dev = $open.unwrap();
                 dev.loopback(true).unwrap();
                 assert_eq!(dev.is_loopback(), true);
 
@@ -283,7 +363,7 @@ mod tests {
 
             #[test]
             fn test_loopback_t3() {
-                let mut dev = FTx232H::init_chan($vendor, $product, $channel).unwrap();
+                let mut dev = $open.unwrap();
                 dev.loopback(true).unwrap();
                 assert_eq!(dev.is_loopback(), true);
 
@@ -306,7 +386,7 @@ mod tests {
 
             #[test]
             fn test_loopback_multi_bus_t1() {
-                let mut dev = FTx232H::init_chan($vendor, $product, $channel).unwrap();
+                let mut dev = $open.unwrap();
                 dev.loopback(true).unwrap();
                 assert_eq!(dev.is_loopback(), true);
 
@@ -342,4 +422,32 @@ mod tests {
     mod ft2232h_b {
         ftdi_test_suite!(0x0403, 0x6010, Interface::B);
     }
+
+    mod ft4232h_a {
+        ftdi_test_suite!(0x0403, 0x6011, Interface::A);
+    }
+
+    mod ft4232h_b {
+        ftdi_test_suite!(0x0403, 0x6011, Interface::B);
+    }
+
+    mod emulator {
+        use crate::emulator::MpsseEmulator;
+
+        ftdi_test_suite!(@open FTx232H::with_transport(MpsseEmulator::new()));
+    }
+
+    mod emulator_ft4232h {
+        use crate::chip::ChipType;
+        use crate::emulator::MpsseEmulator;
+
+        ftdi_test_suite!(@open FTx232H::with_transport(MpsseEmulator::with_chip(ChipType::FT4232H)));
+    }
+
+    mod emulator_ft2232c {
+        use crate::chip::ChipType;
+        use crate::emulator::MpsseEmulator;
+
+        ftdi_test_suite!(@open FTx232H::with_transport(MpsseEmulator::with_chip(ChipType::FT2232C)));
+    }
 }
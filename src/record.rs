@@ -0,0 +1,586 @@
+//! Capture and replay of the traffic between [`FTx232H`] and the chip.
+//!
+//! [`Recorder`] wraps any [`MpsseTransport`] and logs every request with a
+//! timestamp relative to the start of the capture. The log is a plain text
+//! file, one request per line:
+//!
+//! ```text
+//! # MPSSE capture
+//! 0.000012 reset
+//! 0.000020 latency 5
+//! 0.000031 bitmode 00 mpsse
+//! 0.000040 purge
+//! 0.001207 write 80 00 fb 81 87
+//! 0.001911 read 3f
+//! 0.002305 error read timed out
+//! ```
+//!
+//! Data bytes are hex encoded. Failed requests are logged as `error` lines
+//! with the message of the original error.
+//!
+//! [`Replay`] reads such a log back and plays the role of the chip: written
+//! commands are checked against the capture and reads are served from the
+//! recorded responses, so a captured session can be re-run as a regression
+//! test without hardware. Timestamps are informational and do not affect the
+//! replay.
+//!
+//! [`FTx232H`]: crate::x232h::FTx232H
+
+use crate::chip::ChipType;
+use crate::error::{ErrorKind, Result, X232Error};
+use crate::transport::{BitMode, MpsseTransport};
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Request {
+    Write(Vec<u8>),
+    Read(Vec<u8>),
+    Purge,
+    Reset,
+    Latency(u8),
+    Bitmode(u8, BitMode),
+    TransferSize(u32),
+    // read and write timeouts in ms
+    Timeouts(u64, u64),
+    Close,
+    Reopen,
+}
+
+impl Request {
+    fn name(&self) -> &'static str {
+        match self {
+            Request::Write(_) => "write",
+            Request::Read(_) => "read",
+            Request::Purge => "purge",
+            Request::Reset => "reset",
+            Request::Latency(_) => "latency",
+            Request::Bitmode(_, _) => "bitmode",
+            Request::TransferSize(_) => "transfer-size",
+            Request::Timeouts(_, _) => "timeouts",
+            Request::Close => "close",
+            Request::Reopen => "reopen",
+        }
+    }
+}
+
+fn mode_name(mode: BitMode) -> &'static str {
+    match mode {
+        BitMode::Reset => "reset",
+        BitMode::Bitbang => "bitbang",
+        BitMode::Mpsse => "mpsse",
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    let mut s = String::with_capacity(data.len() * 3);
+
+    for b in data {
+        let _ = write!(s, " {:02x}", b);
+    }
+
+    s
+}
+
+/// Transport wrapper logging all the traffic to a capture file.
+pub struct Recorder<T: MpsseTransport, W: Write = File> {
+    inner: T,
+    log: W,
+    start: Instant,
+}
+
+impl<T: MpsseTransport> Recorder<T, File> {
+    /// Start a capture into a new file at `path`, an existing file is truncated.
+    pub fn create<P: AsRef<Path>>(inner: T, path: P) -> Result<Recorder<T, File>> {
+        let log = File::create(path)?;
+        Recorder::new(inner, log)
+    }
+}
+
+impl<T: MpsseTransport, W: Write> Recorder<T, W> {
+    /// Start a capture into an arbitrary writer.
+    pub fn new(inner: T, mut log: W) -> Result<Recorder<T, W>> {
+        writeln!(log, "# MPSSE capture")?;
+
+        Ok(Recorder {
+            inner,
+            log,
+            start: Instant::now(),
+        })
+    }
+
+    /// Stop the capture and return the wrapped transport and the log.
+    pub fn into_inner(self) -> (T, W) {
+        (self.inner, self.log)
+    }
+
+    fn log(&mut self, request: &Request, res: &Result<()>) -> Result<()> {
+        let time = self.start.elapsed().as_secs_f64();
+
+        let line = match (request, res) {
+            (req, Err(err)) => format!("error {} {}", req.name(), err),
+            (Request::Write(data), _) | (Request::Read(data), _) => {
+                format!("{}{}", request.name(), hex(data))
+            }
+            (Request::Latency(value), _) => format!("latency {}", value),
+            (Request::Bitmode(mask, mode), _) => {
+                format!("bitmode {:02x} {}", mask, mode_name(*mode))
+            }
+            (Request::TransferSize(size), _) => format!("transfer-size {}", size),
+            (Request::Timeouts(read, write), _) => format!("timeouts {} {}", read, write),
+            (req, _) => req.name().to_string(),
+        };
+
+        // flush every line: the capture is most useful when something crashes
+        writeln!(self.log, "{:.6} {}", time, line)?;
+        self.log.flush()?;
+
+        Ok(())
+    }
+
+    fn record(&mut self, request: Request, res: Result<()>) -> Result<()> {
+        self.log(&request, &res)?;
+        res
+    }
+}
+
+impl<T: MpsseTransport, W: Write> MpsseTransport for Recorder<T, W> {
+    fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        let res = self.inner.write_all(data);
+        self.record(Request::Write(data.to_vec()), res)
+    }
+
+    fn read_exact(&mut self, data: &mut [u8]) -> Result<()> {
+        let res = self.inner.read_exact(data);
+        self.record(Request::Read(data.to_vec()), res)
+    }
+
+    fn purge(&mut self) -> Result<()> {
+        let res = self.inner.purge();
+        self.record(Request::Purge, res)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        let res = self.inner.reset();
+        self.record(Request::Reset, res)
+    }
+
+    fn set_latency_timer(&mut self, value: u8) -> Result<()> {
+        let res = self.inner.set_latency_timer(value);
+        self.record(Request::Latency(value), res)
+    }
+
+    fn set_bitmode(&mut self, mask: u8, mode: BitMode) -> Result<()> {
+        let res = self.inner.set_bitmode(mask, mode);
+        self.record(Request::Bitmode(mask, mode), res)
+    }
+
+    fn set_transfer_size(&mut self, in_size: u32) -> Result<()> {
+        let res = self.inner.set_transfer_size(in_size);
+        self.record(Request::TransferSize(in_size), res)
+    }
+
+    fn set_timeouts(&mut self, read: Duration, write: Duration) -> Result<()> {
+        let res = self.inner.set_timeouts(read, write);
+        let timeouts = Request::Timeouts(read.as_millis() as u64, write.as_millis() as u64);
+        self.record(timeouts, res)
+    }
+
+    fn chip_type(&mut self) -> Option<ChipType> {
+        self.inner.chip_type()
+    }
+
+    fn close(&mut self) -> Result<()> {
+        let res = self.inner.close();
+        self.record(Request::Close, res)
+    }
+
+    fn reopen(&mut self) -> Result<()> {
+        let res = self.inner.reopen();
+        self.record(Request::Reopen, res)
+    }
+}
+
+#[derive(Debug)]
+enum Entry {
+    Done(Request),
+    Failed(&'static str, String),
+}
+
+/// Transport serving a capture made by [`Recorder`].
+///
+/// Writes must match the captured commands byte for byte and reads must ask
+/// for the same amount of data as during the capture, otherwise the request
+/// fails with [`ErrorKind::ReplayMismatch`]. Purge, reset, latency and bitmode
+/// requests are consumed when they match the next captured entry and are
+/// accepted silently otherwise, so a replayed session may be shorter than the
+/// capture or drop the device at a different point.
+pub struct Replay {
+    entries: VecDeque<Entry>,
+}
+
+impl Replay {
+    /// Load a capture file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Replay> {
+        let file = File::open(path)?;
+        Replay::from_reader(BufReader::new(file))
+    }
+
+    /// Load a capture from an arbitrary reader.
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<Replay> {
+        let mut entries = VecDeque::new();
+
+        for (n, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let entry = parse_entry(line).ok_or_else(|| {
+                X232Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid capture entry at line {}: {}", n + 1, line),
+                ))
+            })?;
+
+            entries.push_back(entry);
+        }
+
+        Ok(Replay { entries })
+    }
+
+    /// Number of captured entries not consumed yet.
+    pub fn remaining(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if the whole capture has been replayed.
+    pub fn is_finished(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn next(&mut self, name: &str) -> Result<Request> {
+        match self.entries.front() {
+            Some(Entry::Done(req)) if req.name() == name => {}
+            Some(Entry::Failed(op, _)) if *op == name => {}
+            _ => return Err(X232Error::HAL(ErrorKind::ReplayMismatch)),
+        }
+
+        match self.entries.pop_front() {
+            Some(Entry::Done(req)) => Ok(req),
+            Some(Entry::Failed(_, msg)) => Err(X232Error::Io(io::Error::other(msg))),
+            None => unreachable!(),
+        }
+    }
+
+    fn control(&mut self, request: Request) -> Result<()> {
+        let matches = match self.entries.front() {
+            Some(Entry::Done(req)) => *req == request,
+            Some(Entry::Failed(op, _)) => *op == request.name(),
+            None => false,
+        };
+
+        if matches {
+            self.next(request.name())?;
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_entry(line: &str) -> Option<Entry> {
+    let mut fields = line.split_whitespace();
+
+    // timestamp is not used by the replay
+    fields.next()?.parse::<f64>().ok()?;
+
+    let name = fields.next()?;
+
+    if name == "error" {
+        let op = match fields.next()? {
+            "write" => "write",
+            "read" => "read",
+            "purge" => "purge",
+            "reset" => "reset",
+            "latency" => "latency",
+            "bitmode" => "bitmode",
+            "transfer-size" => "transfer-size",
+            "timeouts" => "timeouts",
+            "close" => "close",
+            "reopen" => "reopen",
+            _ => return None,
+        };
+        let msg = fields.collect::<Vec<_>>().join(" ");
+
+        return Some(Entry::Failed(op, msg));
+    }
+
+    let request = match name {
+        "write" | "read" => {
+            let data = fields
+                .map(|b| u8::from_str_radix(b, 16).ok())
+                .collect::<Option<Vec<u8>>>()?;
+
+            if name == "write" {
+                Request::Write(data)
+            } else {
+                Request::Read(data)
+            }
+        }
+        "purge" => Request::Purge,
+        "reset" => Request::Reset,
+        "close" => Request::Close,
+        "reopen" => Request::Reopen,
+        "latency" => Request::Latency(fields.next()?.parse().ok()?),
+        "transfer-size" => Request::TransferSize(fields.next()?.parse().ok()?),
+        "timeouts" => {
+            let read = fields.next()?.parse().ok()?;
+            let write = fields.next()?.parse().ok()?;
+
+            Request::Timeouts(read, write)
+        }
+        "bitmode" => {
+            let mask = u8::from_str_radix(fields.next()?, 16).ok()?;
+            let mode = match fields.next()? {
+                "reset" => BitMode::Reset,
+                "bitbang" => BitMode::Bitbang,
+                "mpsse" => BitMode::Mpsse,
+                _ => return None,
+            };
+
+            Request::Bitmode(mask, mode)
+        }
+        _ => return None,
+    };
+
+    Some(Entry::Done(request))
+}
+
+impl MpsseTransport for Replay {
+    fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        match self.entries.front() {
+            Some(Entry::Done(Request::Write(cmd))) if cmd.as_slice() != data => {
+                Err(X232Error::HAL(ErrorKind::ReplayMismatch))
+            }
+            _ => self.next("write").map(|_| ()),
+        }
+    }
+
+    fn read_exact(&mut self, data: &mut [u8]) -> Result<()> {
+        match self.entries.front() {
+            Some(Entry::Done(Request::Read(resp))) if resp.len() != data.len() => {
+                Err(X232Error::HAL(ErrorKind::ReplayMismatch))
+            }
+            _ => {
+                if let Request::Read(resp) = self.next("read")? {
+                    data.copy_from_slice(&resp);
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    fn purge(&mut self) -> Result<()> {
+        self.control(Request::Purge)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.control(Request::Reset)
+    }
+
+    fn set_latency_timer(&mut self, value: u8) -> Result<()> {
+        self.control(Request::Latency(value))
+    }
+
+    fn set_bitmode(&mut self, mask: u8, mode: BitMode) -> Result<()> {
+        self.control(Request::Bitmode(mask, mode))
+    }
+
+    fn set_transfer_size(&mut self, in_size: u32) -> Result<()> {
+        self.control(Request::TransferSize(in_size))
+    }
+
+    fn set_timeouts(&mut self, read: Duration, write: Duration) -> Result<()> {
+        self.control(Request::Timeouts(
+            read.as_millis() as u64,
+            write.as_millis() as u64,
+        ))
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.control(Request::Close)
+    }
+
+    fn reopen(&mut self) -> Result<()> {
+        self.control(Request::Reopen)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::emulator::i2c::Eeprom24c04;
+    use crate::emulator::MpsseEmulator;
+    use crate::i2c::I2cSpeed;
+    use crate::spi::SpiSpeed;
+    use crate::x232h::FTx232H;
+    use embedded_hal::blocking::i2c::{Write as I2cWrite, WriteRead};
+    use embedded_hal::blocking::spi::Transfer;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+
+    /// In-memory capture for a [`Recorder`], readable while it is in use.
+    #[derive(Clone, Default)]
+    pub(crate) struct Log(Arc<Mutex<Vec<u8>>>);
+
+    impl Log {
+        /// Recorded requests without the header and the timestamps.
+        pub(crate) fn requests(&self) -> Vec<String> {
+            requests(&self.0.lock().unwrap())
+        }
+    }
+
+    impl Write for Log {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn requests(log: &[u8]) -> Vec<String> {
+        let log = std::str::from_utf8(log).unwrap();
+        assert_eq!(log.lines().next(), Some("# MPSSE capture"));
+
+        log.lines()
+            .skip(1)
+            .map(|l| l.split_once(' ').unwrap().1.to_string())
+            .collect()
+    }
+
+    fn capture_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ftdi-{}-{}.log", name, std::process::id()))
+    }
+
+    fn i2c_session<T: MpsseTransport>(transport: T) -> Result<[u8; 4]> {
+        let dev = FTx232H::with_transport(transport)?;
+        let mut i2c = dev.i2c(I2cSpeed::CLK_400kHz)?;
+        let mut data = [0; 4];
+
+        i2c.write(0x50, &[0x20, 0x01, 0x02, 0x03, 0x04])?;
+        i2c.write_read(0x50, &[0x20], &mut data)?;
+
+        Ok(data)
+    }
+
+    fn spi_session<T: MpsseTransport>(transport: T, tx: [u8; 3]) -> Result<[u8; 3]> {
+        let mut dev = FTx232H::with_transport(transport)?;
+        dev.loopback(true)?;
+
+        let mut spi = dev.spi(SpiSpeed::CLK_AUTO)?;
+        let mut data = tx;
+
+        spi.transfer(&mut data)?;
+
+        Ok(data)
+    }
+
+    #[test]
+    fn test_record_format() {
+        let (_, log) = {
+            let mut rec = Recorder::new(MpsseEmulator::new(), Vec::new()).unwrap();
+            rec.reset().unwrap();
+            rec.set_bitmode(0, BitMode::Mpsse).unwrap();
+            rec.set_transfer_size(4096).unwrap();
+            rec.set_timeouts(Duration::from_millis(500), Duration::from_secs(1))
+                .unwrap();
+            rec.write_all(&[0x81, 0x87]).unwrap();
+            rec.read_exact(&mut [0; 1]).unwrap();
+            assert!(rec.read_exact(&mut [0; 1]).is_err());
+            rec.reopen().unwrap();
+            rec.close().unwrap();
+            rec.into_inner()
+        };
+
+        assert_eq!(
+            requests(&log),
+            [
+                "reset",
+                "bitmode 00 mpsse",
+                "transfer-size 4096",
+                "timeouts 500 1000",
+                "write 81 87",
+                "read 00",
+                "error read not enough MPSSE response data",
+                "reopen",
+                "close",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_replay_i2c() {
+        let path = capture_path("i2c");
+        let emu = MpsseEmulator::new();
+        emu.add_i2c_target(Eeprom24c04::with_default_address());
+
+        let recorder = Recorder::create(emu, &path).unwrap();
+        assert_eq!(i2c_session(recorder).unwrap(), [0x01, 0x02, 0x03, 0x04]);
+
+        // no EEPROM this time: all the responses come from the capture
+        let replay = Replay::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(i2c_session(replay).unwrap(), [0x01, 0x02, 0x03, 0x04]);
+    }
+
+    #[test]
+    fn test_replay_spi() {
+        let path = capture_path("spi");
+        let tx = [0x5a, 0xa5, 0x3c];
+
+        let recorder = Recorder::create(MpsseEmulator::new(), &path).unwrap();
+        assert_eq!(spi_session(recorder, tx).unwrap(), tx);
+
+        let replay = Replay::open(&path).unwrap();
+        assert_eq!(spi_session(replay, tx).unwrap(), tx);
+
+        // different data on the bus
+        let replay = Replay::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            spi_session(replay, [0x00, 0x00, 0x00]),
+            Err(X232Error::HAL(ErrorKind::ReplayMismatch))
+        ));
+    }
+
+    #[test]
+    fn test_replay_errors() {
+        let capture = "# MPSSE capture\n0.1 write 87\n0.2 error read timed out\n";
+        let mut replay = Replay::from_reader(capture.as_bytes()).unwrap();
+
+        // control requests not in the capture are accepted
+        replay.purge().unwrap();
+        assert_eq!(replay.remaining(), 2);
+        replay.write_all(&[0x87]).unwrap();
+
+        assert!(matches!(
+            replay.read_exact(&mut [0; 2]),
+            Err(X232Error::Io(err)) if err.to_string() == "timed out"
+        ));
+
+        assert!(replay.is_finished());
+        assert!(Replay::from_reader("0.1 write zz".as_bytes()).is_err());
+    }
+}
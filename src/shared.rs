@@ -0,0 +1,174 @@
+//! Reference-counted device handing out bus and pin handles that do not
+//! borrow it.
+//!
+//! [`FTx232H`] buses and pins borrow the device, so a driver owning them can
+//! not be stored next to the device without self-referential tricks. The
+//! handles of [`FTx232HShared`] are `'static` instead: each of them holds a
+//! reference to the device and keeps it open until the last one is dropped.
+
+use crate::error::Result;
+use crate::gpio::GpioPin;
+use crate::i2c::{I2cBus, I2cSpeed};
+use crate::spi::{SpiBus, SpiSpeed};
+use crate::transport::MpsseTransport;
+use crate::x232h::{Device, FTx232H};
+
+use std::ops::Deref;
+use std::sync::Arc;
+
+macro_rules! declare_shared_gpio_pin {
+    ($pin: ident) => {
+        pub fn $pin(&self) -> Result<GpioPin<'static, T>> {
+            let (bit, bank) = {
+                let pin = self.dev.$pin()?;
+                (pin.get_bit(), pin.get_bank())
+            };
+
+            Ok(GpioPin::shared(self.dev.clone(), bit, bank))
+        }
+    };
+}
+
+/// [`FTx232H`] behind an [`Arc`], its clones refer to the same device.
+///
+/// The other methods of [`FTx232H`] are available through `Deref`, loopback
+/// has to be configured before the device is shared.
+pub struct FTx232HShared<T: MpsseTransport = Device> {
+    dev: Arc<FTx232H<T>>,
+}
+
+impl<T: MpsseTransport> Clone for FTx232HShared<T> {
+    fn clone(&self) -> Self {
+        FTx232HShared {
+            dev: self.dev.clone(),
+        }
+    }
+}
+
+impl<T: MpsseTransport> From<FTx232H<T>> for FTx232HShared<T> {
+    fn from(dev: FTx232H<T>) -> Self {
+        FTx232HShared::new(dev)
+    }
+}
+
+impl<T: MpsseTransport> Deref for FTx232HShared<T> {
+    type Target = FTx232H<T>;
+
+    fn deref(&self) -> &FTx232H<T> {
+        &self.dev
+    }
+}
+
+impl<T: MpsseTransport> FTx232HShared<T> {
+    pub fn new(dev: FTx232H<T>) -> FTx232HShared<T> {
+        FTx232HShared { dev: Arc::new(dev) }
+    }
+}
+
+impl<T: MpsseTransport + 'static> FTx232HShared<T> {
+    // spi/i2c buses
+
+    pub fn spi(&self, speed: SpiSpeed) -> Result<SpiBus<'static, T>> {
+        self.dev.spi(speed)?;
+        Ok(SpiBus::shared(self.dev.clone()))
+    }
+
+    pub fn i2c(&self, speed: I2cSpeed) -> Result<I2cBus<'static, T>> {
+        self.dev.i2c(speed)?;
+        Ok(I2cBus::shared(self.dev.clone()))
+    }
+
+    // gpio pins: low bank
+    declare_shared_gpio_pin!(pl0);
+    declare_shared_gpio_pin!(pl1);
+    declare_shared_gpio_pin!(pl2);
+    declare_shared_gpio_pin!(pl3);
+
+    // gpio pins: high bank
+    declare_shared_gpio_pin!(ph0);
+    declare_shared_gpio_pin!(ph1);
+    declare_shared_gpio_pin!(ph2);
+    declare_shared_gpio_pin!(ph3);
+    declare_shared_gpio_pin!(ph4);
+    declare_shared_gpio_pin!(ph5);
+    declare_shared_gpio_pin!(ph6);
+    declare_shared_gpio_pin!(ph7);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::spi::Mcp3008;
+    use crate::emulator::MpsseEmulator;
+    use crate::error::{ErrorKind, X232Error};
+    use crate::gpio::PinBank;
+    use embedded_hal::blocking::spi::Transfer;
+    use embedded_hal::digital::v2::OutputPin;
+
+    // Driver owning its bus and chip select, stored without the device
+    struct Adc {
+        spi: SpiBus<'static, MpsseEmulator>,
+        cs: GpioPin<'static, MpsseEmulator>,
+    }
+
+    impl Adc {
+        fn read(&mut self, ch: u8) -> u16 {
+            let mut buf = [0x01, 0x80 | ch << 4, 0x00];
+
+            self.cs.set_low().unwrap();
+            self.spi.transfer(&mut buf).unwrap();
+            self.cs.set_high().unwrap();
+
+            ((buf[1] as u16 & 0x3) << 8) | buf[2] as u16
+        }
+    }
+
+    fn adc(emu: &MpsseEmulator) -> Adc {
+        let dev = FTx232HShared::new(FTx232H::with_transport(emu.clone()).unwrap());
+        let spi = dev.spi(SpiSpeed::CLK_1MHz).unwrap();
+        let mut cs = dev.pl1().unwrap();
+        cs.set_high().unwrap();
+
+        Adc { spi, cs }
+    }
+
+    #[test]
+    fn test_owned_handles() {
+        let emu = MpsseEmulator::new();
+        let values = [0, 1, 0x155, 0x2aa, 0x3ff, 512, 100, 1000];
+        emu.add_spi_target(PinBank::Low, 5, Mcp3008::new(values));
+
+        // the device is kept alive by the handles
+        let mut adc = adc(&emu);
+
+        for (ch, value) in values.iter().enumerate() {
+            assert_eq!(adc.read(ch as u8), *value);
+        }
+
+        assert_eq!(format!("{}", adc.cs), "PL1");
+    }
+
+    #[test]
+    fn test_clones() {
+        let emu = MpsseEmulator::new();
+        let dev = FTx232HShared::from(FTx232H::with_transport(emu.clone()).unwrap());
+        let other = dev.clone();
+
+        // bookkeeping is shared by the clones and the borrowed API
+        let _ph0 = dev.ph0().unwrap();
+        assert!(matches!(
+            other.ph0(),
+            Err(X232Error::HAL(ErrorKind::GpioPinBusy))
+        ));
+        assert!(other.ph1().is_ok());
+        assert!(dev.ph1().is_err());
+
+        let _spi = dev.spi(SpiSpeed::CLK_AUTO).unwrap();
+        assert!(matches!(
+            other.i2c(I2cSpeed::CLK_AUTO),
+            Err(X232Error::HAL(ErrorKind::BusBusy))
+        ));
+        assert!(other.has_gpio_upper());
+        assert_eq!(emu.clock_frequency(), 1_000_000);
+    }
+}
@@ -2,13 +2,14 @@ pub use embedded_hal::spi::{Mode, Phase, Polarity};
 pub use embedded_hal::spi::{MODE_0, MODE_1, MODE_2, MODE_3};
 
 use crate::error::{ErrorKind, Result, X232Error};
-use crate::ftdimpsse::{ClockData, ClockDataIn, ClockDataOut, MpsseCmdBuilder};
+use crate::ftdimpsse::{ClockData, ClockDataIn, ClockDataOut, MpsseCmdBuilder, MAX_CLOCK_BYTES};
+use crate::transport::{MpsseContext, MpsseTransport};
+use crate::x232h::{DeviceRef, FTx232H};
 
 use nb;
 
 use std::cell::RefCell;
-use std::io::{Read, Write};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 #[allow(non_camel_case_types)]
@@ -22,18 +23,56 @@ pub enum SpiSpeed {
     CLK_5MHz,
     CLK_10MHz,
     CLK_20MHz,
+    /// Arbitrary SCK frequency in Hz, the chip runs at the highest one
+    /// not above it.
+    Hz(u32),
 }
 
-pub struct SpiBus<'a> {
-    ctx: &'a Mutex<RefCell<ftdi::Device>>,
+impl SpiSpeed {
+    /// SCK frequency in Hz, `CLK_AUTO` stands for 1MHz.
+    pub(crate) fn frequency(self) -> u32 {
+        match self {
+            SpiSpeed::CLK_500kHz => 500_000,
+            SpiSpeed::CLK_1MHz | SpiSpeed::CLK_AUTO => 1_000_000,
+            SpiSpeed::CLK_2MHz => 2_000_000,
+            SpiSpeed::CLK_2_5MHz => 2_500_000,
+            SpiSpeed::CLK_3MHz => 3_000_000,
+            SpiSpeed::CLK_5MHz => 5_000_000,
+            SpiSpeed::CLK_10MHz => 10_000_000,
+            SpiSpeed::CLK_20MHz => 20_000_000,
+            SpiSpeed::Hz(hz) => hz,
+        }
+    }
+
+    // A bus clocked at `self` serves the requests for `speed`: the same
+    // frequency or `CLK_AUTO`
+    pub(crate) fn serves(self, speed: SpiSpeed) -> bool {
+        speed == SpiSpeed::CLK_AUTO
+            || (self != SpiSpeed::CLK_AUTO && self.frequency() == speed.frequency())
+    }
+}
+
+pub struct SpiBus<'a, T: MpsseTransport> {
+    ctx: DeviceRef<'a, T>,
     mode: Mode,
     cmd_r: ClockDataIn,
     cmd_w: ClockDataOut,
     cmd_rw: ClockData,
 }
 
-impl<'a> SpiBus<'a> {
-    pub fn new(ctx: &'a Mutex<RefCell<ftdi::Device>>) -> SpiBus {
+impl<'a, T: MpsseTransport> SpiBus<'a, T> {
+    pub fn new(ctx: &'a Mutex<RefCell<MpsseContext<T>>>) -> SpiBus<T> {
+        SpiBus::with_device(DeviceRef::Borrowed(ctx))
+    }
+
+    pub(crate) fn shared(dev: Arc<FTx232H<T>>) -> SpiBus<'static, T>
+    where
+        T: 'static,
+    {
+        SpiBus::with_device(DeviceRef::Shared(dev))
+    }
+
+    fn with_device(ctx: DeviceRef<'a, T>) -> SpiBus<'a, T> {
         SpiBus {
             ctx,
             mode: MODE_0,
@@ -71,7 +110,7 @@ impl<'a> SpiBus<'a> {
     }
 }
 
-impl<'a> embedded_hal::blocking::spi::Transfer<u8> for SpiBus<'a> {
+impl<'a, T: MpsseTransport> embedded_hal::blocking::spi::Transfer<u8> for SpiBus<'a, T> {
     type Error = X232Error;
 
     fn transfer<'b>(&mut self, buffer: &'b mut [u8]) -> Result<&'b [u8]> {
@@ -79,22 +118,27 @@ impl<'a> embedded_hal::blocking::spi::Transfer<u8> for SpiBus<'a> {
             return Ok(buffer);
         }
 
-        let cmd: MpsseCmdBuilder = MpsseCmdBuilder::new()
-            .clock_data(self.cmd_rw, buffer)
-            .send_immediate();
-
         let lock = self.ctx.lock().unwrap();
         let mut ftdi = lock.borrow_mut();
 
-        ftdi.usb_purge_buffers()?;
-        ftdi.write_all(cmd.as_slice())?;
-        ftdi.read_exact(buffer)?;
+        ftdi.purge()?;
+
+        // one clock command per chunk, read back before sending the next so
+        // neither the command nor the chip's TX buffer grows with the buffer
+        for chunk in buffer.chunks_mut(MAX_CLOCK_BYTES) {
+            let cmd: MpsseCmdBuilder = MpsseCmdBuilder::new()
+                .clock_data(self.cmd_rw, chunk)
+                .send_immediate();
+
+            ftdi.write_all(cmd.as_slice())?;
+            ftdi.read_exact(chunk)?;
+        }
 
         Ok(buffer)
     }
 }
 
-impl<'a> embedded_hal::blocking::spi::Write<u8> for SpiBus<'a> {
+impl<'a, T: MpsseTransport> embedded_hal::blocking::spi::Write<u8> for SpiBus<'a, T> {
     type Error = X232Error;
 
     fn write(&mut self, buffer: &[u8]) -> Result<()> {
@@ -102,21 +146,55 @@ impl<'a> embedded_hal::blocking::spi::Write<u8> for SpiBus<'a> {
             return Ok(());
         }
 
-        let cmd: MpsseCmdBuilder = MpsseCmdBuilder::new()
-            .clock_data_out(self.cmd_w, buffer)
-            .send_immediate();
+        let lock = self.ctx.lock().unwrap();
+        let mut ftdi = lock.borrow_mut();
+
+        ftdi.purge()?;
+
+        for chunk in buffer.chunks(MAX_CLOCK_BYTES) {
+            let cmd: MpsseCmdBuilder = MpsseCmdBuilder::new()
+                .clock_data_out(self.cmd_w, chunk)
+                .send_immediate();
 
+            ftdi.write_all(cmd.as_slice())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a, T: MpsseTransport> embedded_hal::blocking::spi::WriteIter<u8> for SpiBus<'a, T> {
+    type Error = X232Error;
+
+    /// Stream `words` out in clock commands of at most [`MAX_CLOCK_BYTES`],
+    /// only one command is held in memory at a time.
+    fn write_iter<WI>(&mut self, words: WI) -> Result<()>
+    where
+        WI: IntoIterator<Item = u8>,
+    {
         let lock = self.ctx.lock().unwrap();
         let mut ftdi = lock.borrow_mut();
+        let mut words = words.into_iter().peekable();
+        let mut chunk: Vec<u8> = Vec::new();
 
-        ftdi.usb_purge_buffers()?;
-        ftdi.write_all(cmd.as_slice())?;
+        ftdi.purge()?;
+
+        while words.peek().is_some() {
+            chunk.clear();
+            chunk.extend(words.by_ref().take(MAX_CLOCK_BYTES));
+
+            let cmd: MpsseCmdBuilder = MpsseCmdBuilder::new()
+                .clock_data_out(self.cmd_w, &chunk)
+                .send_immediate();
+
+            ftdi.write_all(cmd.as_slice())?;
+        }
 
         Ok(())
     }
 }
 
-impl<'a> embedded_hal::spi::FullDuplex<u8> for SpiBus<'a> {
+impl<'a, T: MpsseTransport> embedded_hal::spi::FullDuplex<u8> for SpiBus<'a, T> {
     type Error = X232Error;
 
     fn read(&mut self) -> nb::Result<u8, X232Error> {
@@ -129,12 +207,9 @@ impl<'a> embedded_hal::spi::FullDuplex<u8> for SpiBus<'a> {
         let lock = self.ctx.lock().unwrap();
         let mut ftdi = lock.borrow_mut();
 
-        ftdi.usb_purge_buffers()
-            .map_err(|e| nb::Error::Other(X232Error::FTDI(e)))?;
-        ftdi.write_all(cmd.as_slice())
-            .map_err(|e| nb::Error::Other(X232Error::Io(e)))?;
-        ftdi.read_exact(&mut buffer)
-            .map_err(|e| nb::Error::Other(X232Error::Io(e)))?;
+        ftdi.purge().map_err(nb::Error::Other)?;
+        ftdi.write_all(cmd.as_slice()).map_err(nb::Error::Other)?;
+        ftdi.read_exact(&mut buffer).map_err(nb::Error::Other)?;
 
         Ok(buffer[0])
     }
@@ -147,10 +222,8 @@ impl<'a> embedded_hal::spi::FullDuplex<u8> for SpiBus<'a> {
         let lock = self.ctx.lock().unwrap();
         let mut ftdi = lock.borrow_mut();
 
-        ftdi.usb_purge_buffers()
-            .map_err(|e| nb::Error::Other(X232Error::FTDI(e)))?;
-        ftdi.write_all(cmd.as_slice())
-            .map_err(|e| nb::Error::Other(X232Error::Io(e)))?;
+        ftdi.purge().map_err(nb::Error::Other)?;
+        ftdi.write_all(cmd.as_slice()).map_err(nb::Error::Other)?;
 
         Ok(())
     }
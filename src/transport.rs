@@ -0,0 +1,803 @@
+use crate::chip::{ChipType, Clock};
+use crate::disasm;
+use crate::error::{ErrorKind, Result, X232Error};
+use crate::ftdimpsse::{MpsseCmd, MpsseCmdBuilder};
+
+use std::collections::VecDeque;
+use std::io;
+#[cfg(feature = "libftdi")]
+use std::io::{Read, Write};
+use std::ops::{Deref, DerefMut};
+#[cfg(feature = "libftdi")]
+use std::os::raw::c_int;
+use std::time::Duration;
+
+/// Interfaces (channels) of multi-channel FTDI chips.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Interface {
+    A,
+    B,
+    C,
+    D,
+}
+
+impl Interface {
+    /// USB interface number of the channel.
+    pub fn number(self) -> u8 {
+        match self {
+            Interface::A => 0,
+            Interface::B => 1,
+            Interface::C => 2,
+            Interface::D => 3,
+        }
+    }
+}
+
+/// Operating modes of the FTDI chip selected by [`set_bitmode`].
+///
+/// [`set_bitmode`]: MpsseTransport::set_bitmode
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BitMode {
+    /// Reset to the default (UART/FIFO) mode.
+    Reset,
+    /// Asynchronous bit-bang mode.
+    Bitbang,
+    /// Multi-Protocol Synchronous Serial Engine mode.
+    Mpsse,
+}
+
+/// Byte-level access to an FTDI chip operated in MPSSE mode.
+///
+/// All the buses and pins of this crate talk to the chip through this trait,
+/// so an alternative USB stack, a traffic recorder or a simulator can be used
+/// in place of libftdi by implementing it and passing the result to
+/// [`FTx232H::with_transport`].
+///
+/// [`FTx232H::with_transport`]: crate::x232h::FTx232H::with_transport
+pub trait MpsseTransport {
+    /// Write the whole buffer of MPSSE commands to the chip.
+    fn write_all(&mut self, data: &[u8]) -> Result<()>;
+
+    /// Read exactly `data.len()` bytes of MPSSE responses from the chip.
+    fn read_exact(&mut self, data: &mut [u8]) -> Result<()>;
+
+    /// Purge both USB receive and transmit buffers.
+    fn purge(&mut self) -> Result<()>;
+
+    /// Reset the USB port of the chip.
+    fn reset(&mut self) -> Result<()>;
+
+    /// Set the latency timer in milliseconds.
+    fn set_latency_timer(&mut self, value: u8) -> Result<()>;
+
+    /// Select the chip operating mode, `mask` sets pin directions (`1` is output).
+    fn set_bitmode(&mut self, mask: u8, mode: BitMode) -> Result<()>;
+
+    /// Set the size of USB IN transfers in bytes. Transports without USB
+    /// buffers ignore it.
+    fn set_transfer_size(&mut self, _in_size: u32) -> Result<()> {
+        Ok(())
+    }
+
+    /// Set timeouts for reading MPSSE responses and for writing commands.
+    /// Transports which never block ignore them.
+    fn set_timeouts(&mut self, _read: Duration, _write: Duration) -> Result<()> {
+        Ok(())
+    }
+
+    /// Type of the chip, if the transport is able to tell.
+    fn chip_type(&mut self) -> Option<ChipType> {
+        None
+    }
+
+    /// Release the USB interface, nothing is sent to the chip afterwards.
+    /// Transports release it on drop anyway, this only reports errors.
+    fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Open the same chip again after it has been disconnected. The chip
+    /// is expected to be in the power-on state afterwards.
+    fn reopen(&mut self) -> Result<()> {
+        Err(X232Error::HAL(ErrorKind::ReconnectNotSupported))
+    }
+}
+
+/// Transport shared by the buses and pins of one MPSSE channel, together
+/// with the settings to restore after [`reopen`].
+///
+/// [`reopen`]: MpsseTransport::reopen
+pub struct MpsseContext<T: MpsseTransport> {
+    device: T,
+    /// Last value and direction of the lower GPIO byte.
+    pub(crate) lower: (u8, u8),
+    /// Last value and direction of the upper GPIO byte.
+    pub(crate) upper: (u8, u8),
+    /// Clock selected for the SPI/I2C bus.
+    pub(crate) clock: Option<Clock>,
+    /// Sync probes written and not read back yet, oldest first.
+    probes: VecDeque<Probe>,
+}
+
+/// Sync probe following the commands of one write answered by the chip.
+struct Probe {
+    /// Response offset and opcode of the commands.
+    sent: Vec<(usize, u8)>,
+    /// Number of response bytes not read yet.
+    expected: usize,
+    /// Response bytes read so far.
+    received: Vec<u8>,
+}
+
+// Bogus opcode for the chip to echo, see `MpsseContext::sync`
+const SYNC: u8 = 0xAA;
+
+// Response of the chip to an unknown opcode, followed by the opcode
+const BAD_COMMAND: u8 = 0xFA;
+
+impl<T: MpsseTransport> MpsseContext<T> {
+    pub(crate) fn new(device: T) -> MpsseContext<T> {
+        MpsseContext {
+            device,
+            lower: (0x0, 0x0),
+            upper: (0x0, 0x0),
+            clock: None,
+            probes: VecDeque::new(),
+        }
+    }
+
+    /// Check that MPSSE is in sync: it must echo the bogus opcode `0xAA`
+    /// with `0xFA 0xAA`. An engine left in the middle of a command takes
+    /// it for data instead.
+    pub(crate) fn sync(&mut self) -> Result<()> {
+        let mut echo = [0; 2];
+
+        self.purge()?;
+        self.device
+            .write_all(&[SYNC, MpsseCmd::SendImmediate.into()])?;
+
+        match self.device.read_exact(&mut echo) {
+            Ok(()) if echo == [BAD_COMMAND, SYNC] => Ok(()),
+            Ok(()) => Err(X232Error::HAL(ErrorKind::SyncFailed)),
+            // libftdi reports no data at all as the end of file
+            Err(X232Error::Io(e))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::TimedOut | io::ErrorKind::UnexpectedEof
+                ) =>
+            {
+                Err(X232Error::HAL(ErrorKind::SyncFailed))
+            }
+            #[cfg(feature = "rusb-backend")]
+            Err(X232Error::Usb(rusb::Error::Timeout)) => Err(X232Error::HAL(ErrorKind::SyncFailed)),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write MPSSE commands. Commands answered by the chip are followed by
+    /// a sync probe, so that [`read_exact`] can tell whether the responses
+    /// are the expected ones.
+    ///
+    /// [`read_exact`]: MpsseContext::read_exact
+    pub(crate) fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        let mut sent = Vec::new();
+        let mut expected = 0;
+
+        for (op, len) in disasm::responses(data) {
+            sent.push((expected, op));
+            expected += len;
+        }
+
+        if expected == 0 {
+            return self.device.write_all(data);
+        }
+
+        let mut cmd = data.to_vec();
+        cmd.extend_from_slice(&[SYNC, MpsseCmd::SendImmediate.into()]);
+        self.device.write_all(&cmd)?;
+
+        self.probes.push_back(Probe {
+            sent,
+            expected,
+            received: Vec::with_capacity(expected),
+        });
+
+        Ok(())
+    }
+
+    /// Read MPSSE responses. If a probe does not follow the responses of
+    /// its write, the chip rejected a command and the read fails with
+    /// [`ErrorKind::BadCommand`], or with [`ErrorKind::SyncFailed`] if the
+    /// responses are off for another reason.
+    pub(crate) fn read_exact(&mut self, data: &mut [u8]) -> Result<()> {
+        let mut pos = 0;
+
+        while pos < data.len() {
+            let probe = match self.probes.front_mut() {
+                Some(probe) => probe,
+                None => return self.device.read_exact(&mut data[pos..]),
+            };
+
+            let len = probe.expected.min(data.len() - pos);
+            let chunk = &mut data[pos..pos + len];
+            self.device.read_exact(chunk)?;
+            probe.received.extend_from_slice(chunk);
+            probe.expected -= len;
+            pos += len;
+
+            if probe.expected == 0 {
+                self.check_probe()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Read the probe following the responses of the oldest write
+    fn check_probe(&mut self) -> Result<()> {
+        let probe = match self.probes.pop_front() {
+            Some(probe) => probe,
+            None => return Ok(()),
+        };
+
+        let mut echo = [0; 2];
+        self.device.read_exact(&mut echo)?;
+
+        if echo == [BAD_COMMAND, SYNC] {
+            return Ok(());
+        }
+
+        let mut stream = probe.received;
+        stream.extend_from_slice(&echo);
+        let echoed = |pos: usize, op: u8| stream.get(pos..pos + 2) == Some(&[BAD_COMMAND, op][..]);
+
+        // a rejected command is echoed in place of its response
+        let err = match probe.sent.iter().find(|&&(pos, op)| echoed(pos, op)) {
+            Some(&(_, op)) => ErrorKind::BadCommand(op),
+            None => ErrorKind::SyncFailed,
+        };
+
+        self.purge()?;
+        Err(X232Error::HAL(err))
+    }
+
+    /// Append setting the lower GPIO byte to `cmd` and record it, so that
+    /// pins, buses and [`reopen`] start from the state left on the chip.
+    ///
+    /// [`reopen`]: MpsseTransport::reopen
+    pub(crate) fn set_lower(
+        &mut self,
+        cmd: MpsseCmdBuilder,
+        value: u8,
+        dir: u8,
+    ) -> MpsseCmdBuilder {
+        self.lower = (value, dir);
+        cmd.set_gpio_lower(value, dir)
+    }
+
+    /// Append setting the upper GPIO byte to `cmd` and record it, see
+    /// [`set_lower`](MpsseContext::set_lower).
+    pub(crate) fn set_upper(
+        &mut self,
+        cmd: MpsseCmdBuilder,
+        value: u8,
+        dir: u8,
+    ) -> MpsseCmdBuilder {
+        self.upper = (value, dir);
+        cmd.set_gpio_upper(value, dir)
+    }
+
+    /// Purge the USB buffers, responses of the written commands are lost.
+    pub(crate) fn purge(&mut self) -> Result<()> {
+        self.probes.clear();
+        self.device.purge()
+    }
+}
+
+impl<T: MpsseTransport> Deref for MpsseContext<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.device
+    }
+}
+
+impl<T: MpsseTransport> DerefMut for MpsseContext<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.device
+    }
+}
+
+#[cfg(feature = "libftdi")]
+impl From<Interface> for ftdi::Interface {
+    fn from(intf: Interface) -> ftdi::Interface {
+        match intf {
+            Interface::A => ftdi::Interface::A,
+            Interface::B => ftdi::Interface::B,
+            Interface::C => ftdi::Interface::C,
+            Interface::D => ftdi::Interface::D,
+        }
+    }
+}
+
+#[cfg(feature = "libftdi")]
+impl From<BitMode> for ftdi::BitMode {
+    fn from(mode: BitMode) -> ftdi::BitMode {
+        match mode {
+            BitMode::Reset => ftdi::BitMode::Reset,
+            BitMode::Bitbang => ftdi::BitMode::Bitbang,
+            BitMode::Mpsse => ftdi::BitMode::Mpsse,
+        }
+    }
+}
+
+// The ftdi crate reports libusb errors of bulk transfers by their code in
+// the message only, LIBUSB_ERROR_NO_DEVICE is -4
+#[cfg(feature = "libftdi")]
+fn ftdi_io_error(e: io::Error) -> X232Error {
+    if e.to_string() == "libusb error code -4" {
+        X232Error::HAL(ErrorKind::Disconnected)
+    } else {
+        X232Error::Io(e)
+    }
+}
+
+// Bare libftdi device, it is wrapped in `FtdiDevice` to be moved to another
+// thread or to be reopened after unplug
+#[cfg(feature = "libftdi")]
+impl MpsseTransport for ftdi::Device {
+    fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        Write::write_all(self, data).map_err(ftdi_io_error)
+    }
+
+    fn read_exact(&mut self, data: &mut [u8]) -> Result<()> {
+        Read::read_exact(self, data).map_err(ftdi_io_error)
+    }
+
+    fn purge(&mut self) -> Result<()> {
+        self.usb_purge_buffers()?;
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.usb_reset()?;
+        Ok(())
+    }
+
+    fn set_latency_timer(&mut self, value: u8) -> Result<()> {
+        ftdi::Device::set_latency_timer(self, value)?;
+        Ok(())
+    }
+
+    fn set_bitmode(&mut self, mask: u8, mode: BitMode) -> Result<()> {
+        ftdi::Device::set_bitmode(self, mask, mode.into())?;
+        Ok(())
+    }
+
+    fn set_transfer_size(&mut self, in_size: u32) -> Result<()> {
+        self.set_read_chunksize(in_size);
+        Ok(())
+    }
+
+    fn set_timeouts(&mut self, read: Duration, write: Duration) -> Result<()> {
+        let millis = |t: Duration| t.as_millis().min(c_int::MAX as u128) as c_int;
+        let ctx = self.libftdi_context();
+
+        // SAFETY: the context is valid while the device is open, libftdi
+        // reads the timeouts on every USB transfer
+        unsafe {
+            (*ctx).usb_read_timeout = millis(read);
+            (*ctx).usb_write_timeout = millis(write);
+        }
+
+        Ok(())
+    }
+
+    fn chip_type(&mut self) -> Option<ChipType> {
+        Some(ftdi_chip_type(self))
+    }
+
+    fn close(&mut self) -> Result<()> {
+        // SAFETY: the context stays valid until drop, closing it again on
+        // drop does nothing as the USB handle is cleared
+        match unsafe { libftdi1_sys::ftdi_usb_close(self.libftdi_context()) } {
+            0 => Ok(()),
+            _ => Err(X232Error::FTDI(ftdi::Error::RequestFailed)),
+        }
+    }
+}
+
+/// Chip type detected by libftdi from the USB descriptor on open.
+#[cfg(feature = "libftdi")]
+pub(crate) fn ftdi_chip_type(device: &mut ftdi::Device) -> ChipType {
+    use libftdi1_sys::ftdi_chip_type as chip;
+
+    // SAFETY: the context is valid while the device is open
+    match unsafe { (*device.libftdi_context()).type_ } {
+        chip::TYPE_AM => ChipType::FT232AM,
+        chip::TYPE_BM => ChipType::FT232BM,
+        chip::TYPE_2232C => ChipType::FT2232C,
+        chip::TYPE_R => ChipType::FT232R,
+        chip::TYPE_2232H => ChipType::FT2232H,
+        chip::TYPE_4232H => ChipType::FT4232H,
+        chip::TYPE_232H => ChipType::FT232H,
+        chip::TYPE_230X => ChipType::FT230X,
+        _ => ChipType::Unknown,
+    }
+}
+
+/// libftdi device which can be moved to another thread.
+///
+/// `ftdi::Device` wraps a raw libftdi context pointer, so it is not `Send`.
+/// The context is not bound to the thread it has been created on, it only
+/// must not be used concurrently, and `FTx232H` serializes all the requests
+/// with its lock.
+///
+/// libftdi reports most of the failures of an unplugged adapter as generic
+/// request errors. If the adapter is known by [`with_id`], such errors are
+/// reported as [`ErrorKind::Disconnected`] once it is gone, and [`reopen`]
+/// finds it again by its serial number.
+///
+/// [`with_id`]: FtdiDevice::with_id
+/// [`reopen`]: MpsseTransport::reopen
+#[cfg(feature = "libftdi")]
+pub struct FtdiDevice {
+    device: ftdi::Device,
+    id: Option<FtdiId>,
+}
+
+/// USB identity of an adapter opened with libftdi.
+#[cfg(feature = "libftdi")]
+struct FtdiId {
+    vendor: u16,
+    product: u16,
+    interface: Interface,
+    serial: Option<String>,
+}
+
+// SAFETY: the libftdi context and its libusb handle may be used from any
+// thread, one at a time: the wrapper is not `Sync`
+#[cfg(feature = "libftdi")]
+unsafe impl Send for FtdiDevice {}
+
+#[cfg(feature = "libftdi")]
+impl FtdiDevice {
+    pub fn new(device: ftdi::Device) -> FtdiDevice {
+        FtdiDevice { device, id: None }
+    }
+
+    /// Wrap an interface of the adapter with the given VID/PID and serial
+    /// number, it can be reopened only if the serial number is known.
+    pub fn with_id(
+        device: ftdi::Device,
+        vendor: u16,
+        product: u16,
+        interface: Interface,
+        serial: Option<String>,
+    ) -> FtdiDevice {
+        let id = FtdiId {
+            vendor,
+            product,
+            interface,
+            serial,
+        };
+
+        FtdiDevice {
+            device,
+            id: Some(id),
+        }
+    }
+
+    /// Serial number of the adapter, if it is known.
+    pub fn serial_number(&self) -> Option<&str> {
+        self.id.as_ref().and_then(|id| id.serial.as_deref())
+    }
+
+    pub fn into_inner(self) -> ftdi::Device {
+        self.device
+    }
+
+    /// Open another interface of the same adapter with the same USB
+    /// settings. The adapter is found by its serial number, or as the only
+    /// one with its VID/PID, otherwise [`ErrorKind::DeviceAmbiguous`] is
+    /// returned.
+    pub fn open_interface(&mut self, interface: Interface) -> Result<FtdiDevice> {
+        let (vendor, product, serial) = match self.id {
+            Some(ref id) => (id.vendor, id.product, id.serial.clone()),
+            None => return Err(X232Error::HAL(ErrorKind::DeviceAmbiguous)),
+        };
+
+        let devices = crate::enumerate::ftdi_strings(vendor, product)?;
+        let opener = match same_adapter(&devices, serial.as_deref())? {
+            Some(serial) => ftdi::find_by_vid_pid(vendor, product).serial(serial),
+            None => ftdi::find_by_vid_pid(vendor, product).nth(0),
+        };
+
+        let mut device = opener.interface(interface.into()).open()?;
+        self.copy_settings(&mut device);
+
+        Ok(FtdiDevice::with_id(
+            device, vendor, product, interface, serial,
+        ))
+    }
+
+    // Chunk sizes and timeouts of the USB transfers
+    fn copy_settings(&mut self, device: &mut ftdi::Device) {
+        device.set_read_chunksize(self.device.read_chunksize());
+        device.set_write_chunksize(self.device.write_chunksize());
+
+        // SAFETY: both contexts are valid while the devices are alive
+        unsafe {
+            let (old, new) = (self.device.libftdi_context(), device.libftdi_context());
+            (*new).usb_read_timeout = (*old).usb_read_timeout;
+            (*new).usb_write_timeout = (*old).usb_write_timeout;
+        }
+    }
+
+    // The adapter is assumed to be attached if it can not be checked
+    fn is_attached(&self) -> bool {
+        let id = match self.id {
+            Some(ref id) => id,
+            None => return true,
+        };
+
+        match crate::enumerate::ftdi_strings(id.vendor, id.product) {
+            // the serial number of a busy device may be unreadable
+            Ok(devices) => devices
+                .iter()
+                .any(|(serial, _)| serial.is_none() || id.serial.is_none() || *serial == id.serial),
+            Err(_) => true,
+        }
+    }
+
+    fn check<R>(&self, res: Result<R>) -> Result<R> {
+        match res {
+            Err(X232Error::Io(_)) | Err(X232Error::FTDI(_)) if !self.is_attached() => {
+                Err(X232Error::HAL(ErrorKind::Disconnected))
+            }
+            res => res,
+        }
+    }
+}
+
+#[cfg(feature = "libftdi")]
+impl Deref for FtdiDevice {
+    type Target = ftdi::Device;
+
+    fn deref(&self) -> &ftdi::Device {
+        &self.device
+    }
+}
+
+#[cfg(feature = "libftdi")]
+impl DerefMut for FtdiDevice {
+    fn deref_mut(&mut self) -> &mut ftdi::Device {
+        &mut self.device
+    }
+}
+
+#[cfg(feature = "libftdi")]
+impl MpsseTransport for FtdiDevice {
+    fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        let res = MpsseTransport::write_all(&mut self.device, data);
+        self.check(res)
+    }
+
+    fn read_exact(&mut self, data: &mut [u8]) -> Result<()> {
+        let res = MpsseTransport::read_exact(&mut self.device, data);
+        self.check(res)
+    }
+
+    fn purge(&mut self) -> Result<()> {
+        let res = MpsseTransport::purge(&mut self.device);
+        self.check(res)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        let res = MpsseTransport::reset(&mut self.device);
+        self.check(res)
+    }
+
+    fn set_latency_timer(&mut self, value: u8) -> Result<()> {
+        let res = MpsseTransport::set_latency_timer(&mut self.device, value);
+        self.check(res)
+    }
+
+    fn set_bitmode(&mut self, mask: u8, mode: BitMode) -> Result<()> {
+        let res = MpsseTransport::set_bitmode(&mut self.device, mask, mode);
+        self.check(res)
+    }
+
+    fn set_transfer_size(&mut self, in_size: u32) -> Result<()> {
+        MpsseTransport::set_transfer_size(&mut self.device, in_size)
+    }
+
+    fn set_timeouts(&mut self, read: Duration, write: Duration) -> Result<()> {
+        MpsseTransport::set_timeouts(&mut self.device, read, write)
+    }
+
+    fn chip_type(&mut self) -> Option<ChipType> {
+        MpsseTransport::chip_type(&mut self.device)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        MpsseTransport::close(&mut self.device)
+    }
+
+    fn reopen(&mut self) -> Result<()> {
+        let (vendor, product, interface, serial) = match self.id {
+            Some(FtdiId {
+                vendor,
+                product,
+                interface,
+                serial: Some(ref serial),
+            }) => (vendor, product, interface, serial.clone()),
+            _ => return Err(X232Error::HAL(ErrorKind::ReconnectNotSupported)),
+        };
+
+        // the adapter may come back at another bus address
+        let mut device = match ftdi::find_by_vid_pid(vendor, product)
+            .serial(&serial)
+            .interface(interface.into())
+            .open()
+        {
+            Ok(device) => device,
+            Err(ftdi::Error::DeviceNotFound) => {
+                return Err(X232Error::HAL(ErrorKind::Disconnected))
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        self.copy_settings(&mut device);
+        self.device = device;
+
+        Ok(())
+    }
+}
+
+/// How to open the adapter with `serial` among the listed `devices` with
+/// its VID/PID: by the serial number if it is unique, or as the only one.
+#[cfg(feature = "libftdi")]
+fn same_adapter<'a>(
+    devices: &[(Option<String>, Option<String>)],
+    serial: Option<&'a str>,
+) -> Result<Option<&'a str>> {
+    let count = |serial: &str| {
+        devices
+            .iter()
+            .filter(|(s, _)| s.as_deref() == Some(serial))
+            .count()
+    };
+
+    match serial {
+        Some(serial) if count(serial) == 1 => Ok(Some(serial)),
+        _ if devices.len() == 1 => Ok(None),
+        _ if devices.is_empty() => Err(X232Error::HAL(ErrorKind::Disconnected)),
+        _ => Err(X232Error::HAL(ErrorKind::DeviceAmbiguous)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::MpsseEmulator;
+
+    fn context(chip_type: ChipType) -> MpsseContext<MpsseEmulator> {
+        let mut emu = MpsseEmulator::with_chip(chip_type);
+        emu.set_bitmode(0, BitMode::Mpsse).unwrap();
+        MpsseContext::new(emu)
+    }
+
+    #[test]
+    fn test_probes() {
+        let read_lower = MpsseCmdBuilder::new().gpio_lower().send_immediate();
+        let read_upper = MpsseCmdBuilder::new().gpio_upper().send_immediate();
+
+        // every write answered by the chip is checked
+        let mut ctx = context(ChipType::FT232H);
+        ctx.set_input_lower(0x5a);
+        ctx.set_input_upper(0xa5);
+        ctx.write_all(read_lower.as_slice()).unwrap();
+        ctx.write_all(read_upper.as_slice()).unwrap();
+
+        let mut data = [0; 2];
+        ctx.read_exact(&mut data).unwrap();
+        assert_eq!(data, [0x5a, 0xa5]);
+        assert!(ctx.probes.is_empty());
+
+        // the second write is rejected: FT4232H has no upper byte
+        let mut ctx = context(ChipType::FT4232H);
+        ctx.write_all(read_lower.as_slice()).unwrap();
+        ctx.write_all(read_upper.as_slice()).unwrap();
+
+        assert!(matches!(
+            ctx.read_exact(&mut data),
+            Err(X232Error::HAL(ErrorKind::BadCommand(0x83)))
+        ));
+        assert!(ctx.probes.is_empty());
+    }
+
+    // Transport answering reads the way libftdi does when no data comes
+    struct NoData;
+
+    impl MpsseTransport for NoData {
+        fn write_all(&mut self, _data: &[u8]) -> Result<()> {
+            Ok(())
+        }
+
+        fn read_exact(&mut self, _data: &mut [u8]) -> Result<()> {
+            Err(io::Error::from(io::ErrorKind::UnexpectedEof).into())
+        }
+
+        fn purge(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn reset(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn set_latency_timer(&mut self, _value: u8) -> Result<()> {
+            Ok(())
+        }
+
+        fn set_bitmode(&mut self, _mask: u8, _mode: BitMode) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_sync_no_data() {
+        assert!(matches!(
+            MpsseContext::new(NoData).sync(),
+            Err(X232Error::HAL(ErrorKind::SyncFailed))
+        ));
+    }
+
+    #[cfg(feature = "libftdi")]
+    #[test]
+    fn test_ftdi_disconnected() {
+        let unplugged = io::Error::other("libusb error code -4");
+        assert!(matches!(
+            ftdi_io_error(unplugged),
+            X232Error::HAL(ErrorKind::Disconnected)
+        ));
+
+        let timeout = io::Error::other("libusb error code -7");
+        assert!(matches!(ftdi_io_error(timeout), X232Error::Io(_)));
+
+        assert!(matches!(
+            X232Error::from(ftdi::Error::Disconnected),
+            X232Error::HAL(ErrorKind::Disconnected)
+        ));
+    }
+
+    #[cfg(feature = "libftdi")]
+    #[test]
+    fn test_same_adapter() {
+        let device = |serial: Option<&str>| (serial.map(String::from), None);
+
+        let single = [device(None)];
+        assert_eq!(same_adapter(&single, None).unwrap(), None);
+        assert_eq!(same_adapter(&single, Some("FT01")).unwrap(), None);
+
+        let rack = [device(Some("FT01")), device(Some("FT02")), device(None)];
+        assert_eq!(same_adapter(&rack, Some("FT02")).unwrap(), Some("FT02"));
+        assert!(matches!(
+            same_adapter(&rack, None),
+            Err(X232Error::HAL(ErrorKind::DeviceAmbiguous))
+        ));
+
+        // clones with the same serial number can not be told apart
+        let clones = [device(Some("FT01")), device(Some("FT01"))];
+        assert!(matches!(
+            same_adapter(&clones, Some("FT01")),
+            Err(X232Error::HAL(ErrorKind::DeviceAmbiguous))
+        ));
+
+        assert!(matches!(
+            same_adapter(&[], Some("FT01")),
+            Err(X232Error::HAL(ErrorKind::Disconnected))
+        ));
+    }
+}
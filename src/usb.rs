@@ -0,0 +1,385 @@
+//! Pure-Rust FTDI backend on top of [`rusb`].
+//!
+//! This backend talks to the chip with the FTDI vendor control requests and
+//! bulk transfers directly, so it does not need the C libftdi1 library. It is
+//! used by [`FTx232H::init`] and [`FTx232H::init_device`] if the
+//! `rusb-backend` cargo feature is enabled.
+//!
+//! Every bulk IN packet sent by the chip starts with two modem status bytes,
+//! they are stripped before the data is returned to the caller.
+//!
+//! [`FTx232H::init`]: crate::x232h::FTx232H::init
+//! [`FTx232H::init_device`]: crate::x232h::FTx232H::init_device
+
+use crate::chip::ChipType;
+use crate::enumerate::{bcd_device, DeviceSelector, STRING_TIMEOUT};
+use crate::error::{ErrorKind, Result, X232Error};
+use crate::transport::{BitMode, Interface, MpsseTransport};
+
+use rusb::{Context, Direction, Recipient, RequestType, TransferType, UsbContext};
+use std::collections::VecDeque;
+use std::io;
+use std::time::{Duration, Instant};
+
+// FTDI vendor requests
+const SIO_RESET: u8 = 0x00;
+const SIO_SET_LATENCY_TIMER: u8 = 0x09;
+const SIO_SET_BITMODE: u8 = 0x0B;
+
+// SIO_RESET request values
+const SIO_RESET_SIO: u16 = 0;
+const SIO_RESET_PURGE_RX: u16 = 1;
+const SIO_RESET_PURGE_TX: u16 = 2;
+
+// Modem status bytes at the start of every bulk IN packet
+const STATUS_LEN: usize = 2;
+
+// Packet size of high-speed bulk endpoints, used if the descriptor is not
+// available
+const PACKET_SIZE: usize = 512;
+
+// Number of packets requested by a single bulk IN transfer
+const READ_PACKETS: usize = 8;
+
+const WRITE_CHUNK_SIZE: usize = 1024;
+
+/// Copy payload of bulk IN packets to `out`, skipping modem status bytes.
+///
+/// The buffer `data` is the result of a single bulk transfer: a sequence of
+/// `packet_size` packets, the last one may be short.
+fn strip_status(data: &[u8], packet_size: usize, out: &mut VecDeque<u8>) {
+    for packet in data.chunks(packet_size) {
+        if packet.len() > STATUS_LEN {
+            out.extend(&packet[STATUS_LEN..]);
+        }
+    }
+}
+
+/// FTDI chip interface opened with rusb.
+pub struct UsbDevice {
+    handle: rusb::DeviceHandle<Context>,
+    interface: Interface,
+    chip: ChipType,
+    serial: Option<String>,
+    packet_size: usize,
+    read_size: usize,
+    timeout: Duration,
+    write_timeout: Duration,
+    rx: VecDeque<u8>,
+}
+
+impl UsbDevice {
+    /// Open the first device with the given USB VID/PID.
+    pub fn open(vendor: u16, product: u16, interface: Interface) -> Result<UsbDevice> {
+        let device = Context::new()?
+            .devices()?
+            .iter()
+            .find(|dev| {
+                dev.device_descriptor()
+                    .map(|desc| desc.vendor_id() == vendor && desc.product_id() == product)
+                    .unwrap_or(false)
+            })
+            .ok_or(X232Error::Usb(rusb::Error::NoDevice))?;
+
+        UsbDevice::with_device(device, interface)
+    }
+
+    /// Claim an interface of an enumerated USB device.
+    pub fn with_device(device: rusb::Device<Context>, interface: Interface) -> Result<UsbDevice> {
+        let desc = device.device_descriptor()?;
+        let chip = ChipType::from_bcd_device(bcd_device(desc.device_version()));
+
+        if !chip.has_mpsse(interface) {
+            return Err(X232Error::HAL(ErrorKind::MpsseNotSupported));
+        }
+
+        let packet_size = device
+            .active_config_descriptor()
+            .ok()
+            .and_then(|config| {
+                config
+                    .interfaces()
+                    .find(|intf| intf.number() == interface.number())
+                    .and_then(|intf| intf.descriptors().next())
+                    .and_then(|desc| {
+                        desc.endpoint_descriptors()
+                            .find(|ep| {
+                                ep.direction() == Direction::In
+                                    && ep.transfer_type() == TransferType::Bulk
+                            })
+                            .map(|ep| ep.max_packet_size() as usize)
+                    })
+            })
+            .unwrap_or(PACKET_SIZE);
+
+        let handle = device.open()?;
+
+        // ftdi_sio driver is bound to the interface on Linux
+        let _ = handle.set_auto_detach_kernel_driver(true);
+        handle.claim_interface(interface.number())?;
+
+        let serial = handle
+            .read_languages(STRING_TIMEOUT)
+            .ok()
+            .and_then(|languages| languages.first().copied())
+            .and_then(|lang| {
+                handle
+                    .read_serial_number_string(lang, &desc, STRING_TIMEOUT)
+                    .ok()
+            });
+
+        Ok(UsbDevice {
+            handle,
+            interface,
+            chip,
+            serial,
+            packet_size,
+            read_size: packet_size * READ_PACKETS,
+            timeout: Duration::from_secs(1),
+            write_timeout: Duration::from_secs(1),
+            rx: VecDeque::new(),
+        })
+    }
+
+    /// USB device the interface belongs to.
+    pub(crate) fn usb_device(&self) -> rusb::Device<Context> {
+        self.handle.device()
+    }
+
+    /// Serial number of the adapter, if it has one.
+    pub fn serial_number(&self) -> Option<&str> {
+        self.serial.as_deref()
+    }
+
+    /// Set timeout for USB transfers and for waiting MPSSE responses.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+        self.write_timeout = timeout;
+    }
+
+    fn index(&self) -> u16 {
+        self.interface.number() as u16 + 1
+    }
+
+    fn endpoint_in(&self) -> u8 {
+        0x81 + 2 * self.interface.number()
+    }
+
+    fn endpoint_out(&self) -> u8 {
+        0x02 + 2 * self.interface.number()
+    }
+
+    fn control(&self, request: u8, value: u16) -> Result<()> {
+        let request_type =
+            rusb::request_type(Direction::Out, RequestType::Vendor, Recipient::Device);
+
+        self.handle.write_control(
+            request_type,
+            request,
+            value,
+            self.index(),
+            &[],
+            self.write_timeout,
+        )?;
+
+        Ok(())
+    }
+}
+
+impl Drop for UsbDevice {
+    fn drop(&mut self) {
+        let _ = self.handle.release_interface(self.interface.number());
+    }
+}
+
+impl MpsseTransport for UsbDevice {
+    fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        for chunk in data.chunks(WRITE_CHUNK_SIZE) {
+            let mut written = 0;
+
+            while written < chunk.len() {
+                written += self.handle.write_bulk(
+                    self.endpoint_out(),
+                    &chunk[written..],
+                    self.write_timeout,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_exact(&mut self, data: &mut [u8]) -> Result<()> {
+        let deadline = Instant::now() + self.timeout;
+        let mut buf = vec![0; self.read_size];
+
+        while self.rx.len() < data.len() {
+            let now = Instant::now();
+
+            if now >= deadline {
+                return Err(X232Error::Io(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "not enough MPSSE response data",
+                )));
+            }
+
+            let len = match self
+                .handle
+                .read_bulk(self.endpoint_in(), &mut buf, deadline - now)
+            {
+                Ok(len) => len,
+                Err(rusb::Error::Timeout) => continue,
+                Err(err) => return Err(err.into()),
+            };
+
+            strip_status(&buf[..len], self.packet_size, &mut self.rx);
+        }
+
+        for b in data.iter_mut() {
+            *b = self.rx.pop_front().unwrap();
+        }
+
+        Ok(())
+    }
+
+    fn purge(&mut self) -> Result<()> {
+        self.rx.clear();
+        self.control(SIO_RESET, SIO_RESET_PURGE_RX)?;
+        self.control(SIO_RESET, SIO_RESET_PURGE_TX)?;
+
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.rx.clear();
+        self.control(SIO_RESET, SIO_RESET_SIO)
+    }
+
+    fn set_latency_timer(&mut self, value: u8) -> Result<()> {
+        self.control(SIO_SET_LATENCY_TIMER, value as u16)
+    }
+
+    fn set_bitmode(&mut self, mask: u8, mode: BitMode) -> Result<()> {
+        let mode: u16 = match mode {
+            BitMode::Reset => 0x00,
+            BitMode::Bitbang => 0x01,
+            BitMode::Mpsse => 0x02,
+        };
+
+        self.control(SIO_SET_BITMODE, (mode << 8) | mask as u16)
+    }
+
+    fn set_transfer_size(&mut self, in_size: u32) -> Result<()> {
+        // whole packets only, so that status bytes can be stripped
+        let packets = (in_size as usize).saturating_sub(1) / self.packet_size + 1;
+        self.read_size = packets * self.packet_size;
+
+        Ok(())
+    }
+
+    fn set_timeouts(&mut self, read: Duration, write: Duration) -> Result<()> {
+        self.timeout = read;
+        self.write_timeout = write;
+
+        Ok(())
+    }
+
+    fn chip_type(&mut self) -> Option<ChipType> {
+        Some(self.chip)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.handle.release_interface(self.interface.number())?;
+        Ok(())
+    }
+
+    fn reopen(&mut self) -> Result<()> {
+        // the adapter may come back at another bus address
+        let serial = match self.serial {
+            Some(ref serial) => serial.clone(),
+            None => return Err(X232Error::HAL(ErrorKind::ReconnectNotSupported)),
+        };
+
+        let (device, _) = crate::enumerate::find(&DeviceSelector::Serial(serial))?;
+        let (read_size, timeout, write_timeout) =
+            (self.read_size, self.timeout, self.write_timeout);
+
+        *self = UsbDevice::with_device(device, self.interface)?;
+        self.read_size = read_size;
+        self.timeout = timeout;
+        self.write_timeout = write_timeout;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(status: [u8; 2], payload: &[u8]) -> Vec<u8> {
+        let mut p = status.to_vec();
+        p.extend_from_slice(payload);
+        p
+    }
+
+    #[test]
+    fn test_status_only() {
+        let mut out = VecDeque::new();
+
+        // idle chip returns status bytes only
+        strip_status(&[0x32, 0x60], 512, &mut out);
+        assert!(out.is_empty());
+
+        strip_status(&[], 512, &mut out);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_short_packet() {
+        let mut out = VecDeque::new();
+
+        strip_status(&packet([0x32, 0x60], &[0xfa, 0xab]), 512, &mut out);
+        assert_eq!(out, [0xfa, 0xab]);
+    }
+
+    #[test]
+    fn test_multiple_packets() {
+        let payload: Vec<u8> = (0..1200).map(|i| i as u8).collect();
+        let mut data = Vec::new();
+
+        // two full packets and a short one
+        data.extend(packet([0x32, 0x60], &payload[..510]));
+        data.extend(packet([0x32, 0x60], &payload[510..1020]));
+        data.extend(packet([0x32, 0x60], &payload[1020..]));
+        assert_eq!(data.len(), 1206);
+
+        let mut out = VecDeque::new();
+        strip_status(&data, 512, &mut out);
+        assert_eq!(out, payload);
+    }
+
+    #[test]
+    fn test_full_speed_packets() {
+        let mut data = Vec::new();
+        data.extend(packet([0x01, 0x60], &[0xaa; 62]));
+        data.extend(packet([0x01, 0x60], &[0xbb; 3]));
+
+        let mut out = VecDeque::new();
+        strip_status(&data, 64, &mut out);
+
+        assert_eq!(out.len(), 65);
+        assert!(out.iter().take(62).all(|&b| b == 0xaa));
+        assert!(out.iter().skip(62).all(|&b| b == 0xbb));
+    }
+
+    #[test]
+    fn test_accumulate() {
+        let mut out = VecDeque::new();
+
+        strip_status(&packet([0x32, 0x60], &[0x01]), 512, &mut out);
+        strip_status(&[0x32, 0x60], 512, &mut out);
+        strip_status(&packet([0x32, 0x60], &[0x02, 0x03]), 512, &mut out);
+        assert_eq!(out, [0x01, 0x02, 0x03]);
+    }
+}
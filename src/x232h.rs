@@ -1,103 +1,513 @@
-use ftdi::BitMode;
-pub use ftdi::Interface;
+pub use crate::transport::Interface;
 
+use crate::builder::FTx232HBuilder;
+use crate::chip::{Capabilities, ChipType, Clock};
+#[cfg(feature = "enumerate")]
+use crate::enumerate::DeviceInfo;
+use crate::enumerate::DeviceSelector;
 use crate::error::{ErrorKind, Result, X232Error};
 
 use crate::ftdimpsse::MpsseCmd;
 use crate::ftdimpsse::MpsseCmdBuilder;
+use crate::ftdimpsse::MpsseSettings;
 use crate::gpio::GpioPin;
 use crate::gpio::PinBank;
 use crate::i2c::I2cBus;
 use crate::i2c::I2cSpeed;
+use crate::record::Recorder;
 use crate::spi::SpiBus;
 use crate::spi::SpiSpeed;
+use crate::transport::BitMode;
+#[cfg(not(feature = "rusb-backend"))]
+use crate::transport::FtdiDevice;
+use crate::transport::{MpsseContext, MpsseTransport};
+#[cfg(feature = "rusb-backend")]
+use crate::usb::UsbDevice;
 
 use std::cell::RefCell;
-use std::io::Write;
-use std::sync::Mutex;
-
-pub struct FTx232H {
-    mtx: Mutex<RefCell<ftdi::Device>>,
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Device type used by [`FTx232H::init`]: the rusb backend if the
+/// `rusb-backend` feature is enabled, libftdi otherwise.
+#[cfg(feature = "rusb-backend")]
+pub type Device = UsbDevice;
+
+/// Device type used by [`FTx232H::init`]: the rusb backend if the
+/// `rusb-backend` feature is enabled, libftdi otherwise.
+#[cfg(not(feature = "rusb-backend"))]
+pub type Device = FtdiDevice;
+
+pub struct FTx232H<T: MpsseTransport = Device> {
+    mtx: Mutex<RefCell<MpsseContext<T>>>,
+    chip: Option<ChipType>,
+    settings: MpsseSettings,
     loopback: bool,
-
-    i2c: RefCell<Option<I2cSpeed>>,
-    spi: RefCell<Option<SpiSpeed>>,
-
-    pl0: RefCell<bool>,
-    pl1: RefCell<bool>,
-    pl2: RefCell<bool>,
-    pl3: RefCell<bool>,
-
-    ph0: RefCell<bool>,
-    ph1: RefCell<bool>,
-    ph2: RefCell<bool>,
-    ph3: RefCell<bool>,
-    ph4: RefCell<bool>,
-    ph5: RefCell<bool>,
-    ph6: RefCell<bool>,
-    ph7: RefCell<bool>,
+    // pins set by `close`, tri-stated if not given
+    safe: Option<((u8, u8), (u8, u8))>,
+    closed: bool,
+
+    // bus modes are changed with `mtx` held
+    i2c: Mutex<Option<I2cSpeed>>,
+    spi: Mutex<Option<SpiSpeed>>,
+
+    pl0: AtomicBool,
+    pl1: AtomicBool,
+    pl2: AtomicBool,
+    pl3: AtomicBool,
+
+    ph0: AtomicBool,
+    ph1: AtomicBool,
+    ph2: AtomicBool,
+    ph3: AtomicBool,
+    ph4: AtomicBool,
+    ph5: AtomicBool,
+    ph6: AtomicBool,
+    ph7: AtomicBool,
 }
 
-impl FTx232H {
+impl FTx232H<Device> {
+    /// List all the attached FTDI devices.
+    #[cfg(feature = "enumerate")]
+    pub fn list() -> Result<Vec<DeviceInfo>> {
+        crate::enumerate::list()
+    }
+
     pub fn init(vendor: u16, product: u16) -> Result<FTx232H> {
-        FTx232H::init_ctx(vendor, product, ftdi::Interface::A)
+        FTx232H::init_ctx(vendor, product, Interface::A)
     }
 
-    pub fn init_chan(vendor: u16, product: u16, intf: ftdi::Interface) -> Result<FTx232H> {
+    pub fn init_chan(vendor: u16, product: u16, intf: Interface) -> Result<FTx232H> {
         FTx232H::init_ctx(vendor, product, intf)
     }
 
-    fn init_ctx(vendor: u16, product: u16, intf: ftdi::Interface) -> Result<FTx232H> {
-        let mut device = ftdi::find_by_vid_pid(vendor, product)
-            .interface(intf)
+    fn init_ctx(vendor: u16, product: u16, intf: Interface) -> Result<FTx232H> {
+        let (device, chip) = FTx232H::open_device(vendor, product, intf)?;
+        FTx232H::with_transport_chip(device, chip)
+    }
+
+    #[cfg(feature = "rusb-backend")]
+    pub(crate) fn open_device(
+        vendor: u16,
+        product: u16,
+        intf: Interface,
+    ) -> Result<(Device, Option<ChipType>)> {
+        let mut device = UsbDevice::open(vendor, product, intf)?;
+        let chip = device.chip_type();
+
+        Ok((device, chip))
+    }
+
+    #[cfg(not(feature = "rusb-backend"))]
+    pub(crate) fn open_device(
+        vendor: u16,
+        product: u16,
+        intf: Interface,
+    ) -> Result<(Device, Option<ChipType>)> {
+        let device = ftdi::find_by_vid_pid(vendor, product)
+            .interface(intf.into())
+            .open()?;
+
+        // the first listed device is the one opened
+        let serial = crate::enumerate::ftdi_strings(vendor, product)
+            .ok()
+            .and_then(|devices| devices.into_iter().next())
+            .and_then(|(serial, _)| serial);
+
+        FTx232H::ftdi_device(device, vendor, product, intf, serial)
+    }
+
+    /// Open another interface of the adapter `device` belongs to.
+    #[cfg(feature = "rusb-backend")]
+    pub(crate) fn open_sibling(
+        device: &mut Device,
+        intf: Interface,
+    ) -> Result<(Device, Option<ChipType>)> {
+        let mut device = UsbDevice::with_device(device.usb_device(), intf)?;
+        let chip = device.chip_type();
+
+        Ok((device, chip))
+    }
+
+    /// Open another interface of the adapter `device` belongs to.
+    #[cfg(not(feature = "rusb-backend"))]
+    pub(crate) fn open_sibling(
+        device: &mut Device,
+        intf: Interface,
+    ) -> Result<(Device, Option<ChipType>)> {
+        let mut device = device.open_interface(intf)?;
+        let chip = device.chip_type();
+
+        if !chip.unwrap_or(ChipType::Unknown).has_mpsse(intf) {
+            return Err(X232Error::HAL(ErrorKind::MpsseNotSupported));
+        }
+
+        Ok((device, chip))
+    }
+
+    /// Configure the adapter step by step, see [`FTx232HBuilder`].
+    pub fn builder() -> FTx232HBuilder {
+        FTx232HBuilder::new()
+    }
+
+    /// Open an interface of the adapter matching `selector`.
+    pub fn init_device(selector: &DeviceSelector, intf: Interface) -> Result<FTx232H> {
+        let (device, chip) = FTx232H::open_selected(selector, intf)?;
+        FTx232H::with_transport_chip(device, chip)
+    }
+
+    /// Open an interface of the adapter matching `selector` and initialize
+    /// MPSSE with `settings`, see [`with_transport_settings`].
+    ///
+    /// [`with_transport_settings`]: FTx232H::with_transport_settings
+    pub fn init_with_settings(
+        selector: &DeviceSelector,
+        intf: Interface,
+        settings: MpsseSettings,
+    ) -> Result<FTx232H> {
+        let (device, chip) = FTx232H::open_selected(selector, intf)?;
+        let init = Init {
+            settings,
+            usb: true,
+            ..Init::default()
+        };
+
+        FTx232H::with_init(device, chip, init)
+    }
+
+    #[cfg(feature = "rusb-backend")]
+    pub(crate) fn open_selected(
+        selector: &DeviceSelector,
+        intf: Interface,
+    ) -> Result<(Device, Option<ChipType>)> {
+        let (device, _) = crate::enumerate::find(selector)?;
+        let mut device = UsbDevice::with_device(device, intf)?;
+        let chip = device.chip_type();
+
+        Ok((device, chip))
+    }
+
+    #[cfg(all(feature = "enumerate", not(feature = "rusb-backend")))]
+    pub(crate) fn open_selected(
+        selector: &DeviceSelector,
+        intf: Interface,
+    ) -> Result<(Device, Option<ChipType>)> {
+        let (_, info) = crate::enumerate::find(selector)?;
+
+        // the listed device is opened by its USB bus and address
+        let device = ftdi::find_by_bus_address(info.bus_number, info.address)
+            .interface(intf.into())
+            .open()?;
+
+        FTx232H::ftdi_device(
+            device,
+            info.vendor_id,
+            info.product_id,
+            intf,
+            info.serial_number,
+        )
+    }
+
+    #[cfg(not(feature = "enumerate"))]
+    pub(crate) fn open_selected(
+        selector: &DeviceSelector,
+        intf: Interface,
+    ) -> Result<(Device, Option<ChipType>)> {
+        let (vendor, product, nth, serial) = crate::enumerate::find_ftdi(selector)?;
+        let device = ftdi::find_by_vid_pid(vendor, product)
+            .nth(nth)
+            .interface(intf.into())
             .open()?;
 
+        FTx232H::ftdi_device(device, vendor, product, intf, serial)
+    }
+
+    #[cfg(not(feature = "rusb-backend"))]
+    fn ftdi_device(
+        mut device: ftdi::Device,
+        vendor: u16,
+        product: u16,
+        intf: Interface,
+        serial: Option<String>,
+    ) -> Result<(Device, Option<ChipType>)> {
+        let chip = crate::transport::ftdi_chip_type(&mut device);
+
+        if !chip.has_mpsse(intf) {
+            return Err(X232Error::HAL(ErrorKind::MpsseNotSupported));
+        }
+
         device.set_write_chunksize(1024);
         device.set_read_chunksize(1024);
-        device.usb_reset()?;
-        device.set_latency_timer(5)?;
-        device.set_bitmode(0, BitMode::Mpsse)?;
-        device.usb_purge_buffers()?;
 
-        // Device settings:
+        let device = FtdiDevice::with_id(device, vendor, product, intf, serial);
+
+        Ok((device, Some(chip)))
+    }
+}
+
+impl FTx232H<Recorder<Device>> {
+    /// Same as [`init_chan`], all the traffic is captured to a file at `path`.
+    ///
+    /// [`init_chan`]: FTx232H::init_chan
+    pub fn init_recorded<P: AsRef<Path>>(
+        vendor: u16,
+        product: u16,
+        intf: Interface,
+        path: P,
+    ) -> Result<FTx232H<Recorder<Device>>> {
+        let (device, chip) = FTx232H::open_device(vendor, product, intf)?;
+        FTx232H::with_transport_chip(Recorder::create(device, path)?, chip)
+    }
+}
+
+impl<T: MpsseTransport> FTx232H<T> {
+    /// Initialize MPSSE on an already opened transport.
+    pub fn with_transport(mut device: T) -> Result<FTx232H<T>> {
+        let chip = device.chip_type();
+        FTx232H::with_transport_chip(device, chip)
+    }
+
+    /// Initialize MPSSE on an already opened transport applying all the
+    /// `settings`: USB transfer size and timeouts, reset, latency timer,
+    /// bitmode mask and clock frequency.
+    ///
+    /// The settings are checked before anything is sent to the chip: the
+    /// latency timer must be 1..255 ms, the transfer size a multiple of 64
+    /// in 64..64k bytes, the timeouts non-zero and the clock reachable by
+    /// the chip.
+    pub fn with_transport_settings(mut device: T, settings: MpsseSettings) -> Result<FTx232H<T>> {
+        let chip = device.chip_type();
+        let init = Init {
+            settings,
+            usb: true,
+            ..Init::default()
+        };
+
+        FTx232H::with_init(device, chip, init)
+    }
+
+    pub(crate) fn with_transport_chip(device: T, chip: Option<ChipType>) -> Result<FTx232H<T>> {
+        FTx232H::with_init(device, chip, Init::default())
+    }
+
+    pub(crate) fn with_init(
+        mut device: T,
+        chip: Option<ChipType>,
+        init: Init,
+    ) -> Result<FTx232H<T>> {
+        let caps = chip.unwrap_or(ChipType::Unknown).capabilities();
+        let settings = init.settings;
+
+        // everything is checked before the first request to the device
+        if init.usb {
+            let size = settings.in_transfer_size;
+            if !(64..=0x10000).contains(&size) || size & 0x3f != 0 {
+                return Err(X232Error::HAL(ErrorKind::InvalidParams));
+            }
+
+            if settings.read_timeout.is_zero() || settings.write_timeout.is_zero() {
+                return Err(X232Error::HAL(ErrorKind::InvalidParams));
+            }
+        }
+
+        latency_ms(settings.latency_timer)?;
+
+        let hz = match init.bus {
+            Some(Bus::Spi(speed)) => Some(speed.frequency()),
+            Some(Bus::I2c(speed)) => Some(speed.frequency()),
+            None => settings.clock_frequency,
+        };
+
+        let clock = match hz {
+            Some(hz) => Some(
+                caps.clock(hz)
+                    .ok_or(X232Error::HAL(ErrorKind::InvalidClock))?,
+            ),
+            None => None,
+        };
+
+        let safe_upper = init.safe.map(|(_, upper)| upper).unwrap_or_default();
+        if caps.gpio_banks < 2 && (init.upper != Init::default().upper || safe_upper != (0, 0)) {
+            return Err(X232Error::HAL(ErrorKind::GpioNoUpperByte));
+        }
+
+        // SPI/I2C: DI - input, DO - output(0), SK - output(0)
+        let lower = match init.bus {
+            Some(_) => bus_pins(init.lower),
+            None => init.lower,
+        };
+
+        if init.usb {
+            device.set_transfer_size(settings.in_transfer_size)?;
+            device.set_timeouts(settings.read_timeout, settings.write_timeout)?;
+        }
+
+        let mut ctx = MpsseContext::new(device);
+        let mut cmd = FTx232H::init_mpsse(&mut ctx, caps, &settings)?;
+
+        cmd = ctx.set_lower(cmd, lower.0, lower.1);
+
+        if caps.gpio_banks > 1 {
+            cmd = ctx.set_upper(cmd, init.upper.0, init.upper.1);
+        }
+
+        if let Some(clock) = clock {
+            cmd = cmd.clock(clock);
+        }
+
+        if init.loopback {
+            cmd = cmd.enable_loopback();
+        }
+
+        ctx.write_all(cmd.as_slice())?;
+        ctx.clock = clock;
+
+        let (i2c, spi) = match init.bus {
+            Some(Bus::Spi(speed)) => (None, Some(speed)),
+            Some(Bus::I2c(speed)) => (Some(speed), None),
+            None => (None, None),
+        };
+
+        let d = FTx232H {
+            mtx: Mutex::new(RefCell::new(ctx)),
+            chip,
+            settings,
+            loopback: init.loopback,
+            safe: init.safe,
+            closed: false,
+
+            i2c: Mutex::new(i2c),
+            spi: Mutex::new(spi),
+
+            pl0: AtomicBool::new(true),
+            pl1: AtomicBool::new(true),
+            pl2: AtomicBool::new(true),
+            pl3: AtomicBool::new(true),
+
+            ph0: AtomicBool::new(true),
+            ph1: AtomicBool::new(true),
+            ph2: AtomicBool::new(true),
+            ph3: AtomicBool::new(true),
+            ph4: AtomicBool::new(true),
+            ph5: AtomicBool::new(true),
+            ph6: AtomicBool::new(true),
+            ph7: AtomicBool::new(true),
+        };
+
+        Ok(d)
+    }
+
+    // Switch the chip to MPSSE mode, the returned commands put it to the
+    // initial state, GPIO pins are to be appended by the caller
+    fn init_mpsse(
+        ctx: &mut MpsseContext<T>,
+        caps: Capabilities,
+        settings: &MpsseSettings,
+    ) -> Result<MpsseCmdBuilder> {
+        if settings.reset {
+            ctx.reset()?;
+        }
+
+        ctx.set_latency_timer(latency_ms(settings.latency_timer)?)?;
+        ctx.set_bitmode(settings.mask, BitMode::Mpsse)?;
+        ctx.sync()?;
+
+        // Device settings, features missing on the chip are skipped:
         // - disable DIV_5 => 60MHz
         // - disable adaptive clocking
         // - disable 3-phase clocking
         // - disable loopback
-        // - low bits: all outputs(0)
-        // FIXME: current approach is limited: fixed in/out pin configuration:
-        let cmd_init = MpsseCmdBuilder::with_vec(vec![MpsseCmd::DisableClockDivide.into()])
-            .disable_adaptive_data_clocking()
-            .disable_3phase_data_clocking()
-            .disable_loopback()
-            .set_gpio_lower(0x0, 0b1111_1111)
-            .set_gpio_upper(0x0, 0b1111_1111);
+        let mut cmd_init = MpsseCmdBuilder::new();
+
+        if caps.has_clock_divide() {
+            cmd_init.0.push(MpsseCmd::DisableClockDivide.into());
+        }
 
-        device.write_all(cmd_init.as_slice())?;
+        if caps.adaptive_clocking {
+            cmd_init = cmd_init.disable_adaptive_data_clocking();
+        }
 
-        let d = FTx232H {
-            mtx: Mutex::new(RefCell::new(device)),
-            loopback: false,
+        if caps.three_phase_clocking {
+            cmd_init = cmd_init.disable_3phase_data_clocking();
+        }
+
+        ctx.clock = None;
 
-            i2c: RefCell::new(None),
-            spi: RefCell::new(None),
-
-            pl0: RefCell::new(true),
-            pl1: RefCell::new(true),
-            pl2: RefCell::new(true),
-            pl3: RefCell::new(true),
-
-            ph0: RefCell::new(true),
-            ph1: RefCell::new(true),
-            ph2: RefCell::new(true),
-            ph3: RefCell::new(true),
-            ph4: RefCell::new(true),
-            ph5: RefCell::new(true),
-            ph6: RefCell::new(true),
-            ph7: RefCell::new(true),
+        Ok(cmd_init.disable_loopback())
+    }
+
+    /// Put the adapter to a neutral state and release its interface, the
+    /// same as dropping the device but reporting errors.
+    ///
+    /// All the pins become inputs and the chip leaves MPSSE mode. If a safe
+    /// pattern is configured with [`FTx232HBuilder::safe_lower`] or
+    /// [`FTx232HBuilder::safe_upper`], the pins are set to it instead and the
+    /// chip stays in MPSSE mode to keep driving them.
+    pub fn close(mut self) -> Result<()> {
+        self.closed = true;
+        self.shutdown()
+    }
+
+    // Every step is tried even if the previous ones fail, the first error
+    // is reported
+    fn shutdown(&self) -> Result<()> {
+        let lock = match self.mtx.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
         };
+        let mut ctx = lock.borrow_mut();
 
-        Ok(d)
+        let (lower, upper) = self.safe.unwrap_or_default();
+        let mut cmd = ctx.set_lower(MpsseCmdBuilder::new(), lower.0, lower.1);
+
+        if self.has_gpio_upper() {
+            cmd = ctx.set_upper(cmd, upper.0, upper.1);
+        }
+
+        let mut res = ctx.purge().and_then(|_| ctx.write_all(cmd.as_slice()));
+
+        if self.safe.is_none() {
+            res = res.and(ctx.set_bitmode(0x0, BitMode::Reset));
+        }
+
+        res.and(ctx.close())
+    }
+
+    /// Reopen the chip after it has been unplugged and restore the settings:
+    /// MPSSE mode, clock, loopback and GPIO pins. Existing bus and pin
+    /// handles keep working afterwards.
+    ///
+    /// The same adapter is found by its serial number, so a libftdi device
+    /// must be wrapped with [`FtdiDevice::with_id`] to be reopened.
+    pub fn reconnect(&self) -> Result<()> {
+        let lock = self.mtx.lock().unwrap();
+        let mut ctx = lock.borrow_mut();
+        let (lower, upper, clock) = (ctx.lower, ctx.upper, ctx.clock);
+
+        ctx.reopen()?;
+
+        let cmd = FTx232H::init_mpsse(&mut ctx, self.capabilities(), &self.settings)?;
+        let mut cmd = ctx.set_lower(cmd, lower.0, lower.1);
+
+        if self.has_gpio_upper() {
+            cmd = ctx.set_upper(cmd, upper.0, upper.1);
+        }
+
+        if let Some(clock) = clock {
+            cmd = cmd.clock(clock);
+        }
+
+        if self.loopback {
+            cmd = cmd.enable_loopback();
+        }
+
+        ctx.write_all(cmd.as_slice())?;
+        ctx.clock = clock;
+
+        Ok(())
     }
 
     pub fn loopback(&mut self, lp: bool) -> Result<()> {
@@ -121,87 +531,144 @@ impl FTx232H {
         self.loopback
     }
 
+    /// Type of the chip, if known.
+    pub fn chip_type(&self) -> Option<ChipType> {
+        self.chip
+    }
+
+    /// MPSSE features of the chip, an unknown chip is assumed to be FT232H
+    /// compatible.
+    pub fn capabilities(&self) -> Capabilities {
+        self.chip.unwrap_or(ChipType::Unknown).capabilities()
+    }
+
+    /// Check if the upper GPIO byte (`ph0`..`ph7` pins) is available.
+    pub fn has_gpio_upper(&self) -> bool {
+        self.capabilities().gpio_banks > 1
+    }
+
+    /// Enable or disable 3-phase data clocking, not available on FT2232C/D.
+    pub fn set_3phase_clocking(&self, enable: bool) -> Result<()> {
+        if !self.capabilities().three_phase_clocking {
+            return Err(X232Error::HAL(ErrorKind::FeatureNotSupported));
+        }
+
+        let cmd = if enable {
+            MpsseCmdBuilder::new().enable_3phase_data_clocking()
+        } else {
+            MpsseCmdBuilder::new().disable_3phase_data_clocking()
+        };
+
+        self.write_cmd(cmd)
+    }
+
+    /// Enable or disable adaptive clocking, available on FT232H and FT2232H.
+    pub fn set_adaptive_clocking(&self, enable: bool) -> Result<()> {
+        if !self.capabilities().adaptive_clocking {
+            return Err(X232Error::HAL(ErrorKind::FeatureNotSupported));
+        }
+
+        let cmd = if enable {
+            MpsseCmdBuilder::new().enable_adaptive_data_clocking()
+        } else {
+            MpsseCmdBuilder::new().disable_adaptive_data_clocking()
+        };
+
+        self.write_cmd(cmd)
+    }
+
+    /// Make the pins selected by `lower` and `upper` masks open-drain: they
+    /// are only driven low. Available on FT232H only.
+    pub fn set_drive_only_zero(&self, lower: u8, upper: u8) -> Result<()> {
+        if !self.capabilities().drive_only_zero {
+            return Err(X232Error::HAL(ErrorKind::FeatureNotSupported));
+        }
+
+        self.write_cmd(MpsseCmdBuilder::new().enable_drive_only_zero(lower, upper))
+    }
+
+    /// Transport shared by the buses and pins.
+    pub(crate) fn context(&self) -> &Mutex<RefCell<MpsseContext<T>>> {
+        &self.mtx
+    }
+
+    fn write_cmd(&self, cmd: MpsseCmdBuilder) -> Result<()> {
+        let lock = self.mtx.lock().unwrap();
+        let mut ftdi = lock.borrow_mut();
+
+        ftdi.write_all(cmd.as_slice())
+    }
+
+    /// SK frequency of the SPI/I2C bus in Hz, it may be lower than the
+    /// requested one, see [`Capabilities::clock`].
+    pub fn clock_frequency(&self) -> Option<u32> {
+        let lock = self.mtx.lock().unwrap();
+        let ftdi = lock.borrow();
+
+        ftdi.clock.map(|clock| clock.frequency)
+    }
+
+    fn clock(&self, hz: u32) -> Result<Clock> {
+        self.capabilities()
+            .clock(hz)
+            .ok_or(X232Error::HAL(ErrorKind::InvalidClock))
+    }
+
     // spi/i2c buses
 
-    pub fn spi(&self, speed: SpiSpeed) -> Result<SpiBus> {
-        if (*self.i2c.borrow()).is_some() {
+    pub fn spi(&self, speed: SpiSpeed) -> Result<SpiBus<T>> {
+        let lock = self.mtx.lock().unwrap();
+        let mut spi = self.spi.lock().unwrap();
+
+        if (*self.i2c.lock().unwrap()).is_some() {
             return Err(X232Error::HAL(ErrorKind::BusBusy));
         }
 
-        if (*self.spi.borrow()).is_none() {
-            let lock = self.mtx.lock().unwrap();
+        if (*spi).is_none() {
             let mut ftdi = lock.borrow_mut();
 
-            self.spi.replace(Some(speed));
+            let clock = self.clock(speed.frequency())?;
+            *spi = Some(speed);
 
             // SPI: DI - input, DO - output(0), SK - output(0)
-            ftdi.write_all(
-                MpsseCmdBuilder::new()
-                    .set_gpio_lower(0x0, 0b1111_1011)
-                    .as_slice(),
-            )?;
-
-            // FIXME: set fixed speed 1MHz for all devices assuming 60MHz clock
-            // SCK_freq = 60MHz / ((1 + (div1 | (div2 << 8))) * 2)
-            let (div1, div2) = match speed {
-                SpiSpeed::CLK_500kHz => (0x3b, 0x0),
-                SpiSpeed::CLK_1MHz | SpiSpeed::CLK_AUTO => (0x1d, 0x0),
-                SpiSpeed::CLK_2MHz => (0xe, 0x0),
-                SpiSpeed::CLK_2_5MHz => (0xb, 0x0),
-                SpiSpeed::CLK_3MHz => (0x9, 0x0),
-                SpiSpeed::CLK_5MHz => (0x5, 0x0),
-                SpiSpeed::CLK_10MHz => (0x2, 0x0),
-                SpiSpeed::CLK_20MHz => (0x1, 0x0),
-            };
-
-            ftdi.write_all(
-                MpsseCmdBuilder::with_vec(vec![MpsseCmd::SetClockFrequency.into(), div1, div2])
-                    .as_slice(),
-            )?;
-        } else if speed != SpiSpeed::CLK_AUTO {
-            // clock sanity check
-            if Some(speed) != *self.spi.borrow() {
-                return Err(X232Error::HAL(ErrorKind::InvalidClock));
-            }
+            let lower = bus_pins(ftdi.lower);
+            let cmd = ftdi.set_lower(MpsseCmdBuilder::new(), lower.0, lower.1);
+            ftdi.write_all(cmd.as_slice())?;
+
+            ftdi.write_all(MpsseCmdBuilder::new().clock(clock).as_slice())?;
+            ftdi.clock = Some(clock);
+        } else if !spi.is_some_and(|current| current.serves(speed)) {
+            // clock sanity check: the same frequency or auto
+            return Err(X232Error::HAL(ErrorKind::InvalidClock));
         }
 
         Ok(SpiBus::new(&self.mtx))
     }
 
-    pub fn i2c(&self, speed: I2cSpeed) -> Result<I2cBus> {
-        if (*self.spi.borrow()).is_some() {
+    pub fn i2c(&self, speed: I2cSpeed) -> Result<I2cBus<T>> {
+        let lock = self.mtx.lock().unwrap();
+        let mut i2c = self.i2c.lock().unwrap();
+
+        if (*self.spi.lock().unwrap()).is_some() {
             return Err(X232Error::HAL(ErrorKind::BusBusy));
         }
 
-        if (*self.i2c.borrow()).is_none() {
-            let lock = self.mtx.lock().unwrap();
+        if (*i2c).is_none() {
             let mut ftdi = lock.borrow_mut();
 
-            self.i2c.replace(Some(speed));
+            let clock = self.clock(speed.frequency())?;
+            *i2c = Some(speed);
 
             // I2C: DI - input, DO - output(0), SK - output(0)
-            ftdi.write_all(
-                MpsseCmdBuilder::new()
-                    .set_gpio_lower(0x0, 0b1111_1011)
-                    .as_slice(),
-            )?;
-
-            // FIXME: set fixed speed 1MHz for all devices assuming 60MHz clock
-            // SCK_freq = 60MHz / ((1 + (div1 | (div2 << 8))) * 2)
-            let (div1, div2) = match speed {
-                I2cSpeed::CLK_100kHz | I2cSpeed::CLK_AUTO => (0x2b, 0x1),
-                I2cSpeed::CLK_400kHz => (0x4a, 0x0),
-            };
-
-            ftdi.write_all(
-                MpsseCmdBuilder::with_vec(vec![MpsseCmd::SetClockFrequency.into(), div1, div2])
-                    .as_slice(),
-            )?;
-        } else if speed != I2cSpeed::CLK_AUTO {
-            // clock sanity check
-            if Some(speed) != *self.i2c.borrow() {
-                return Err(X232Error::HAL(ErrorKind::InvalidClock));
-            }
+            let lower = bus_pins(ftdi.lower);
+            let cmd = ftdi.set_lower(MpsseCmdBuilder::new(), lower.0, lower.1);
+            ftdi.write_all(cmd.as_slice())?;
+
+            ftdi.write_all(MpsseCmdBuilder::new().clock(clock).as_slice())?;
+            ftdi.clock = Some(clock);
+        } else if !i2c.is_some_and(|current| current.serves(speed)) {
+            // clock sanity check: the same frequency or auto
+            return Err(X232Error::HAL(ErrorKind::InvalidClock));
         }
 
         Ok(I2cBus::new(&self.mtx))
@@ -224,14 +691,88 @@ impl FTx232H {
     crate::declare_gpio_pin!(ph7, 7, PinBank::High);
 }
 
-impl Drop for FTx232H {
-    fn drop(&mut self) {
-        let lock = match self.mtx.lock() {
-            Ok(guard) => guard,
-            Err(poisoned) => poisoned.into_inner(),
-        };
-        let mut ftdi = lock.borrow_mut();
+/// Bus selected at initialization.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Bus {
+    Spi(SpiSpeed),
+    I2c(I2cSpeed),
+}
+
+/// Initial state applied by the MPSSE init sequence.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Init {
+    pub(crate) settings: MpsseSettings,
+    /// Apply USB transfer size and timeouts of `settings`, otherwise the
+    /// transport keeps its own.
+    pub(crate) usb: bool,
+    /// Value and direction of the lower GPIO byte.
+    pub(crate) lower: (u8, u8),
+    /// Value and direction of the upper GPIO byte.
+    pub(crate) upper: (u8, u8),
+    pub(crate) loopback: bool,
+    pub(crate) bus: Option<Bus>,
+    /// Lower and upper GPIO bytes set on close.
+    pub(crate) safe: Option<((u8, u8), (u8, u8))>,
+}
+
+impl Default for Init {
+    fn default() -> Self {
+        Init {
+            settings: MpsseSettings {
+                reset: true,
+                latency_timer: Duration::from_millis(5),
+                mask: 0x0,
+                clock_frequency: None,
+                ..MpsseSettings::default()
+            },
+            usb: false,
+            // all inputs: nothing is driven until the pins are used
+            lower: (0x0, 0x0),
+            upper: (0x0, 0x0),
+            loopback: false,
+            bus: None,
+            safe: None,
+        }
+    }
+}
+
+// Lower byte with bus pins set up: SK and DO - output(0), DI - input,
+// AD3 (SPI chip select) and GPIO pins keep their state
+fn bus_pins(lower: (u8, u8)) -> (u8, u8) {
+    (lower.0 & 0b1111_1000, (lower.1 & 0b1111_1000) | 0b0000_0011)
+}
 
-        ftdi.usb_purge_buffers().unwrap();
+// Latency timer value in ms, the chip accepts 1..255 ms
+fn latency_ms(latency: Duration) -> Result<u8> {
+    match latency.as_millis() {
+        ms @ 1..=255 => Ok(ms as u8),
+        _ => Err(X232Error::HAL(ErrorKind::InvalidParams)),
+    }
+}
+
+/// Device of a bus or pin handle: borrowed from [`FTx232H`] or shared with
+/// [`FTx232HShared`](crate::shared::FTx232HShared).
+pub(crate) enum DeviceRef<'a, T: MpsseTransport> {
+    Borrowed(&'a Mutex<RefCell<MpsseContext<T>>>),
+    Shared(Arc<FTx232H<T>>),
+}
+
+impl<'a, T: MpsseTransport> Deref for DeviceRef<'a, T> {
+    type Target = Mutex<RefCell<MpsseContext<T>>>;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            DeviceRef::Borrowed(ctx) => ctx,
+            DeviceRef::Shared(dev) => dev.context(),
+        }
+    }
+}
+
+impl<T: MpsseTransport> Drop for FTx232H<T> {
+    fn drop(&mut self) {
+        // nobody is there to handle the errors, e.g. the adapter is unplugged
+        if !self.closed {
+            let _ = self.shutdown();
+        }
     }
 }